@@ -0,0 +1,285 @@
+//! Bounding boxes in YOLO text files: `<image stem>.txt` next to every annotated image, one
+//! `class cx cy w h` line (normalized to the image size) per box, with the class names listed
+//! in `classes.txt` at the store path.
+
+use opencv::{
+    core::{CV_8UC3, Mat, Point, Rect, Scalar},
+    highgui::{
+        EVENT_LBUTTONDOWN, EVENT_LBUTTONUP, EVENT_MOUSEMOVE, EVENT_RBUTTONDOWN, WINDOW_AUTOSIZE,
+        imshow, named_window, set_mouse_callback, wait_key_ex,
+    },
+    imgcodecs::{IMREAD_UNCHANGED, imread},
+    imgproc::{LINE_AA, rectangle},
+    prelude::*,
+};
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::classes::ClassMap;
+use crate::ui::{KEY_LEFT, KEY_RIGHT, RoiSelection, draw_text, rect_between, to_display};
+use crate::{AppError, dataset};
+
+const WINDOW: &str = "annotate";
+const CLASSES_FILE: &str = "classes.txt";
+
+#[derive(Debug, Clone, Copy)]
+pub struct BBox {
+    pub class: usize,
+    pub center_x: f64,
+    pub center_y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl BBox {
+    fn from_rect(class: usize, rect: Rect, width: i32, height: i32) -> Self {
+        let (width, height) = (width as f64, height as f64);
+        BBox {
+            class,
+            center_x: (rect.x as f64 + rect.width as f64 / 2.0) / width,
+            center_y: (rect.y as f64 + rect.height as f64 / 2.0) / height,
+            width: rect.width as f64 / width,
+            height: rect.height as f64 / height,
+        }
+    }
+
+    /// Top-left corner, width and height in pixels of a `width` x `height` image.
+    pub fn pixels(&self, width: i32, height: i32) -> [f64; 4] {
+        let (width, height) = (width as f64, height as f64);
+        [
+            (self.center_x - self.width / 2.0) * width,
+            (self.center_y - self.height / 2.0) * height,
+            self.width * width,
+            self.height * height,
+        ]
+    }
+
+    fn rect(&self, width: i32, height: i32) -> Rect {
+        let [x, y, w, h] = self.pixels(width, height);
+        Rect::new(x as i32, y as i32, w as i32, h as i32)
+    }
+}
+
+/// The label file that belongs to `image`.
+pub fn label_path(image: &Path) -> PathBuf {
+    image.with_extension("txt")
+}
+
+/// Boxes of `image`; images without a label file have none.
+pub fn load(image: &Path) -> Result<Vec<BBox>, AppError> {
+    let path = label_path(image);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let mut boxes = Vec::new();
+    for line in fs::read_to_string(&path)?.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [class, center_x, center_y, width, height] = fields[..] else {
+            continue;
+        };
+        let number = |field: &str| {
+            field
+                .parse::<f64>()
+                .map_err(|e| AppError::DatasetError(format!("{:?}: {}", path, e)))
+        };
+        boxes.push(BBox {
+            class: class
+                .parse()
+                .map_err(|e| AppError::DatasetError(format!("{:?}: {}", path, e)))?,
+            center_x: number(center_x)?,
+            center_y: number(center_y)?,
+            width: number(width)?,
+            height: number(height)?,
+        });
+    }
+    Ok(boxes)
+}
+
+fn save(image: &Path, boxes: &[BBox]) -> Result<(), AppError> {
+    let path = label_path(image);
+    if boxes.is_empty() {
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let text: String = boxes
+        .iter()
+        .map(|b| {
+            format!(
+                "{} {:.6} {:.6} {:.6} {:.6}\n",
+                b.class, b.center_x, b.center_y, b.width, b.height
+            )
+        })
+        .collect();
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Box class names in id order, or `None` if nothing has been annotated yet.
+pub fn classes(store_path: &Path) -> Result<Option<Vec<String>>, AppError> {
+    let path = store_path.join(CLASSES_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(
+        fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect(),
+    ))
+}
+
+/// Left-drag draws the pending box, right-click discards it.
+fn track_box(selection: &Arc<Mutex<RoiSelection>>) -> opencv::Result<()> {
+    let selection = Arc::clone(selection);
+    set_mouse_callback(
+        WINDOW,
+        Some(Box::new(move |event, x, y, _flags| {
+            let Ok(mut selection) = selection.lock() else {
+                return;
+            };
+            let point = Point::new(x, y);
+            match event {
+                EVENT_LBUTTONDOWN => {
+                    selection.anchor = Some(point);
+                    selection.current = point;
+                }
+                EVENT_MOUSEMOVE => selection.current = point,
+                EVENT_LBUTTONUP => {
+                    if let Some(anchor) = selection.anchor.take() {
+                        let rect = rect_between(anchor, point);
+                        if rect.width > 1 && rect.height > 1 {
+                            selection.roi = Some(rect);
+                        }
+                    }
+                }
+                EVENT_RBUTTONDOWN => selection.roi = None,
+                _ => {}
+            }
+        })),
+    )
+}
+
+/// Steps through the saved images: drag a box and press a class key to label it, Backspace
+/// removes the last box, arrows navigate, Esc quits. Labels are written on every change.
+pub fn run(store_path: &Path) -> Result<(), Box<dyn Error>> {
+    let samples = dataset::samples(store_path)?;
+    if samples.is_empty() {
+        println!("no images under {:?}", store_path);
+        return Ok(());
+    }
+    let mut classes = classes(store_path)?.unwrap_or_default();
+    let class_map = ClassMap::from_store(store_path)?;
+    named_window(WINDOW, WINDOW_AUTOSIZE)?;
+    let selection = Arc::new(Mutex::new(RoiSelection::default()));
+    track_box(&selection)?;
+
+    let mut current = 0;
+    let mut loaded = None;
+    let mut image = Mat::default();
+    let mut boxes = Vec::new();
+    loop {
+        let sample = &samples[current];
+        if loaded != Some(current) {
+            let path = sample
+                .path
+                .to_str()
+                .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+            let decoded = imread(path, IMREAD_UNCHANGED)?;
+            image = if decoded.empty() {
+                Mat::new_rows_cols_with_default(240, 320, CV_8UC3, Scalar::all(0.0))?
+            } else {
+                to_display(&decoded)?
+            };
+            boxes = load(&sample.path)?;
+            loaded = Some(current);
+            if let Ok(mut selection) = selection.lock() {
+                selection.roi = None;
+            }
+        }
+
+        let mut preview = image.try_clone()?;
+        for b in &boxes {
+            let rect = b.rect(image.cols(), image.rows());
+            rectangle(
+                &mut preview,
+                rect,
+                Scalar::new(0.0, 255.0, 0.0, 0.0),
+                2,
+                LINE_AA,
+                0,
+            )?;
+            let name = classes.get(b.class).map_or("?", String::as_str);
+            draw_text(&mut preview, name, Point::new(rect.x, rect.y - 4), 0.5)?;
+        }
+        let pending = selection.lock().ok().and_then(|selection| {
+            let drag = selection
+                .anchor
+                .map(|anchor| rect_between(anchor, selection.current));
+            drag.or(selection.roi)
+        });
+        if let Some(rect) = pending {
+            rectangle(
+                &mut preview,
+                rect,
+                Scalar::new(0.0, 255.0, 255.0, 0.0),
+                1,
+                LINE_AA,
+                0,
+            )?;
+        }
+        let status = format!(
+            "{} ({}/{}) {} boxes",
+            sample.label,
+            current + 1,
+            samples.len(),
+            boxes.len()
+        );
+        draw_text(&mut preview, &status, Point::new(10, 30), 0.8)?;
+        if imshow(WINDOW, &preview).is_err() {
+            break;
+        }
+
+        let key = wait_key_ex(30).unwrap_or(-1);
+        if key == -1 {
+            continue;
+        }
+        if KEY_LEFT.contains(&key) {
+            current = current.saturating_sub(1);
+            continue;
+        }
+        if KEY_RIGHT.contains(&key) {
+            current = (current + 1).min(samples.len() - 1);
+            continue;
+        }
+        match char::from_u32((key & 0xFF) as u32) {
+            Some('\x1b') => break,
+            Some('\x08') if boxes.pop().is_some() => save(&sample.path, &boxes)?,
+            Some(key @ ('a'..='z' | '0'..='9' | 'A'..='Z')) => {
+                let Some(rect) = selection.lock().ok().and_then(|mut s| s.roi.take()) else {
+                    println!("drag a box before pressing a class key");
+                    continue;
+                };
+                let name = class_map.label(key);
+                let class = match classes.iter().position(|c| *c == name) {
+                    Some(class) => class,
+                    None => {
+                        classes.push(name);
+                        fs::write(store_path.join(CLASSES_FILE), classes.join("\n") + "\n")?;
+                        classes.len() - 1
+                    }
+                };
+                boxes.push(BBox::from_rect(class, rect, image.cols(), image.rows()));
+                save(&sample.path, &boxes)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::{self, Rng, Sample};
+
+/// Prints the images per class and, with `prune`, randomly removes images from classes above
+/// `target` (the smallest class by default). `quarantine` moves them aside instead.
+pub fn run(
+    store_path: &Path,
+    target: Option<usize>,
+    seed: u64,
+    prune: bool,
+    quarantine: Option<&PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut classes: BTreeMap<String, Vec<Sample>> = BTreeMap::new();
+    for sample in dataset::samples(store_path)? {
+        classes
+            .entry(sample.label.clone())
+            .or_default()
+            .push(sample);
+    }
+    let smallest = classes.values().map(Vec::len).min().unwrap_or(0);
+    let largest = classes.values().map(Vec::len).max().unwrap_or(0);
+    let target = target.unwrap_or(smallest);
+    for (label, samples) in &classes {
+        println!(
+            "  {:<20} {:>8} images {:>8} over target",
+            label,
+            samples.len(),
+            samples.len().saturating_sub(target)
+        );
+    }
+    if smallest > 0 {
+        println!(
+            "imbalance ratio {:.2} (largest / smallest)",
+            largest as f64 / smallest as f64
+        );
+    }
+    if !prune && quarantine.is_none() {
+        return Ok(());
+    }
+
+    let mut rng = Rng::new(seed);
+    let mut removed = 0;
+    for (label, mut samples) in classes {
+        if samples.len() <= target {
+            continue;
+        }
+        rng.shuffle(&mut samples);
+        for sample in &samples[target..] {
+            dataset::remove(store_path, &sample.path, quarantine.map(PathBuf::as_path))?;
+            removed += 1;
+        }
+        println!("{}: keep {} of {}", label, target, samples.len());
+    }
+    println!(
+        "{} images {}",
+        removed,
+        if quarantine.is_some() {
+            "quarantined"
+        } else {
+            "deleted"
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_balance_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A store with 5 cat, 3 dog and 2 bird images.
+    fn store(name: &str) -> PathBuf {
+        let store = scratch(name);
+        for (label, count) in [("cat", 5), ("dog", 3), ("bird", 2)] {
+            fs::create_dir_all(store.join(label)).unwrap();
+            for i in 0..count {
+                fs::write(store.join(label).join(format!("{}.png", i)), b"png").unwrap();
+            }
+        }
+        store
+    }
+
+    fn count(dir: &Path, label: &str) -> usize {
+        fs::read_dir(dir.join(label)).map_or(0, |entries| entries.count())
+    }
+
+    #[test]
+    fn without_prune_only_counts_are_reported() {
+        let store = store("report");
+        run(&store, None, 0, false, None).unwrap();
+        assert_eq!(
+            ["cat", "dog", "bird"].map(|label| count(&store, label)),
+            [5, 3, 2]
+        );
+    }
+
+    #[test]
+    fn prune_cuts_every_class_down_to_the_smallest() {
+        let store = store("smallest");
+        run(&store, None, 0, true, None).unwrap();
+        assert_eq!(
+            ["cat", "dog", "bird"].map(|label| count(&store, label)),
+            [2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn an_explicit_target_leaves_smaller_classes_alone() {
+        let store = store("target");
+        run(&store, Some(4), 0, true, None).unwrap();
+        assert_eq!(
+            ["cat", "dog", "bird"].map(|label| count(&store, label)),
+            [4, 3, 2]
+        );
+    }
+
+    #[test]
+    fn quarantine_moves_the_surplus_aside() {
+        let store = store("quarantine");
+        let quarantine = scratch("quarantine_dir");
+        run(&store, Some(3), 0, false, Some(&quarantine)).unwrap();
+        assert_eq!((count(&store, "cat"), count(&quarantine, "cat")), (3, 2));
+        assert_eq!(count(&quarantine, "dog"), 0);
+    }
+
+    #[test]
+    fn the_seed_picks_which_images_go() {
+        let kept = |seed| {
+            let store = store(&format!("seed_{}", seed));
+            run(&store, Some(2), seed, true, None).unwrap();
+            let mut names: Vec<_> = fs::read_dir(store.join("cat"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(kept(1), kept(1));
+        assert_ne!(kept(1), kept(2));
+    }
+}
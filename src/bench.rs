@@ -0,0 +1,122 @@
+//! `bench SOURCE`: how fast this machine reads the source, and how long each output format
+//! takes to encode and write one of its frames, to pick settings that keep up with the camera.
+
+use opencv::{
+    core::{Mat, Vector},
+    imgcodecs::imencode,
+    prelude::*,
+};
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant};
+
+use crate::sink::write_atomic;
+use crate::source::{VideoSource, open_source, read_frames};
+use crate::{AppError, ImageFormat};
+
+/// Encodes and writes timed per format.
+const SAMPLES: u32 = 10;
+
+/// Format, quality and PNG compression of every benchmarked setting.
+const SETTINGS: &[(ImageFormat, Option<i32>, Option<i32>)] = &[
+    (ImageFormat::Png, None, Some(0)),
+    (ImageFormat::Png, None, Some(1)),
+    (ImageFormat::Png, None, Some(3)),
+    (ImageFormat::Png, None, Some(9)),
+    (ImageFormat::Jpg, Some(75), None),
+    (ImageFormat::Jpg, Some(90), None),
+    (ImageFormat::Jpg, Some(95), None),
+    (ImageFormat::Jpg, Some(100), None),
+    (ImageFormat::Webp, Some(75), None),
+    (ImageFormat::Webp, Some(90), None),
+    (ImageFormat::Webp, Some(100), None),
+    (ImageFormat::Tiff, None, None),
+];
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub fn run(source: &VideoSource, frames: usize) -> Result<(), Box<dyn Error>> {
+    let mut videos = open_source(source)?;
+    let mut buffers = vec![Mat::default(); videos.len()];
+    let mut reads = Vec::with_capacity(frames);
+    let mut failed = 0;
+    let started = Instant::now();
+    for _ in 0..frames {
+        let read_started = Instant::now();
+        if read_frames(&mut videos, &mut buffers) {
+            reads.push(read_started.elapsed());
+        } else {
+            failed += 1;
+        }
+    }
+    let elapsed = started.elapsed();
+    for video in videos.iter_mut() {
+        let _ = video.release();
+    }
+    let frame = &buffers[0];
+    if reads.is_empty() || frame.empty() {
+        return Err(AppError::SourceError("no frame could be read".into()).into());
+    }
+    reads.sort();
+    let fps = reads.len() as f64 / elapsed.as_secs_f64();
+    let mean_read = reads.iter().sum::<Duration>() / reads.len() as u32;
+    let p95_read = reads[(reads.len() * 95 / 100).min(reads.len() - 1)];
+    println!(
+        "read {} frames of {}x{} at {:.1} fps: {:.1} ms mean, {:.1} ms p95, {} failed",
+        reads.len(),
+        frame.cols(),
+        frame.rows(),
+        fps,
+        ms(mean_read),
+        ms(p95_read),
+        failed
+    );
+
+    let dir = env::temp_dir().join(format!("img_collector_bench_{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    println!(
+        "{:<12} {:>10} {:>10} {:>10} {:>10} {:>12}  keeps up",
+        "format", "encode ms", "write ms", "size KiB", "max fps", "latency ms"
+    );
+    for (format, quality, png_compression) in SETTINGS {
+        let ext = format.extension();
+        let params = format.params(*quality, *png_compression);
+        let path = dir.join(format!("bench.{}", ext));
+        let (mut encode, mut write, mut size) = (Duration::ZERO, Duration::ZERO, 0);
+        for _ in 0..SAMPLES {
+            let encode_started = Instant::now();
+            let mut encoded = Vector::<u8>::new();
+            imencode(&format!(".{}", ext), frame, &mut encoded, &params)?;
+            let write_started = Instant::now();
+            write_atomic(&path, encoded.as_slice())?;
+            encode += write_started - encode_started;
+            write += write_started.elapsed();
+            size = encoded.len();
+        }
+        let (encode, write) = (encode / SAMPLES, write / SAMPLES);
+        let max_fps = 1.0 / (encode + write).as_secs_f64().max(1e-6);
+        let setting = match (quality, png_compression) {
+            (Some(quality), _) => format!("{} q{}", ext, quality),
+            (_, Some(level)) => format!("{} c{}", ext, level),
+            _ => ext.to_string(),
+        };
+        println!(
+            "{:<12} {:>10.1} {:>10.1} {:>10} {:>10.1} {:>12.1}  {}",
+            setting,
+            ms(encode),
+            ms(write),
+            size / 1024,
+            max_fps,
+            // From the start of a read to the image being in place.
+            ms(mean_read + encode + write),
+            if max_fps >= fps { "yes" } else { "no" }
+        );
+    }
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use opencv::{
+    calib3d::{
+        CALIB_CB_ADAPTIVE_THRESH, CALIB_CB_FAST_CHECK, CALIB_CB_NORMALIZE_IMAGE,
+        calibrate_camera_def, draw_chessboard_corners, find_chessboard_corners,
+    },
+    core::{
+        FileStorage, FileStorage_Mode, Mat, Point, Point2f, Point3f, Size, TermCriteria,
+        TermCriteria_Type, Vector,
+    },
+    highgui::{imshow, wait_key_ex},
+    imgproc::corner_sub_pix,
+    prelude::*,
+};
+
+use std::error::Error;
+use std::path::Path;
+
+use crate::AppError;
+use crate::source::{VideoSource, open_source, read_frames};
+use crate::transform::to_gray;
+use crate::ui::draw_text;
+
+pub struct Board {
+    pub cols: i32,
+    pub rows: i32,
+    pub square_size: f32,
+}
+
+impl Board {
+    fn pattern(&self) -> Size {
+        Size::new(self.cols, self.rows)
+    }
+
+    fn object_points(&self) -> Vector<Point3f> {
+        let mut points = Vector::with_capacity((self.cols * self.rows) as usize);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                points.push(Point3f::new(
+                    col as f32 * self.square_size,
+                    row as f32 * self.square_size,
+                    0.0,
+                ));
+            }
+        }
+        points
+    }
+}
+
+fn find_corners(board: &Board, gray: &Mat) -> opencv::Result<Option<Vector<Point2f>>> {
+    let mut corners = Vector::<Point2f>::new();
+    let flags = CALIB_CB_ADAPTIVE_THRESH | CALIB_CB_NORMALIZE_IMAGE | CALIB_CB_FAST_CHECK;
+    if !find_chessboard_corners(gray, board.pattern(), &mut corners, flags)? {
+        return Ok(None);
+    }
+    let criteria = TermCriteria {
+        typ: TermCriteria_Type::COUNT as i32 + TermCriteria_Type::EPS as i32,
+        max_count: 30,
+        epsilon: 0.001,
+    };
+    corner_sub_pix(
+        gray,
+        &mut corners,
+        Size::new(11, 11),
+        Size::new(-1, -1),
+        criteria,
+    )?;
+    Ok(Some(corners))
+}
+
+fn write_intrinsics(
+    path: &Path,
+    image_size: Size,
+    camera_matrix: &Mat,
+    dist_coeffs: &Mat,
+    rms: f64,
+) -> Result<(), Box<dyn Error>> {
+    let path = path
+        .to_str()
+        .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+    let mut storage = FileStorage::new(path, FileStorage_Mode::WRITE as i32, "")?;
+    if !storage.is_opened()? {
+        return Err(AppError::CalibrationError(format!("Cannot write {}", path)).into());
+    }
+    storage.write_i32("image_width", image_size.width)?;
+    storage.write_i32("image_height", image_size.height)?;
+    storage.write_mat("camera_matrix", camera_matrix)?;
+    storage.write_mat("dist_coeffs", dist_coeffs)?;
+    storage.write_f64("rms", rms)?;
+    storage.release()?;
+    Ok(())
+}
+
+/// Space collects the current chessboard view, `c` calibrates, `q` or Esc quits.
+pub fn run(
+    source: &VideoSource,
+    board: &Board,
+    output: &Path,
+    min_views: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut videos = open_source(source)?;
+    let mut frames = vec![Mat::default()];
+    let mut image_points = Vector::<Vector<Point2f>>::new();
+    let mut image_size = Size::default();
+    loop {
+        if !read_frames(&mut videos[..1], &mut frames) {
+            break;
+        }
+        let gray = to_gray(&frames[0])?;
+        image_size = gray.size()?;
+        let corners = find_corners(board, &gray)?;
+        let mut preview = frames[0].try_clone()?;
+        if let Some(corners) = &corners {
+            draw_chessboard_corners(&mut preview, board.pattern(), corners, true)?;
+        }
+        let status = format!("views: {}/{}", image_points.len(), min_views);
+        draw_text(&mut preview, &status, Point::new(10, 30), 0.8)?;
+        if imshow("calibrate", &preview).is_err() {
+            break;
+        }
+        let key = wait_key_ex(30).unwrap_or(-1);
+        if key == -1 {
+            continue;
+        }
+        match char::from_u32((key & 0xFF) as u32) {
+            Some(' ') => match corners {
+                Some(corners) => {
+                    image_points.push(corners);
+                    println!("collected view {}", image_points.len());
+                }
+                None => println!("no chessboard in view"),
+            },
+            Some('c') if image_points.len() < min_views => {
+                println!("need at least {} views", min_views);
+            }
+            Some('c') => break,
+            Some('q') | Some('\x1b') => {
+                image_points.clear();
+                break;
+            }
+            _ => {}
+        }
+    }
+    for video in videos.iter_mut() {
+        let _ = video.release();
+    }
+    if image_points.len() < min_views {
+        println!("calibration cancelled");
+        return Ok(());
+    }
+
+    let object_points: Vector<Vector<Point3f>> = (0..image_points.len())
+        .map(|_| board.object_points())
+        .collect();
+    let mut camera_matrix = Mat::default();
+    let mut dist_coeffs = Mat::default();
+    let mut rvecs = Vector::<Mat>::new();
+    let mut tvecs = Vector::<Mat>::new();
+    let rms = calibrate_camera_def(
+        &object_points,
+        &image_points,
+        image_size,
+        &mut camera_matrix,
+        &mut dist_coeffs,
+        &mut rvecs,
+        &mut tvecs,
+    )?;
+    println!("calibration RMS reprojection error: {:.4}", rms);
+    write_intrinsics(output, image_size, &camera_matrix, &dist_coeffs, rms)?;
+    println!("save calibration to {:?}", output);
+    Ok(())
+}
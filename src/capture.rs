@@ -0,0 +1,304 @@
+//! Camera reads on their own thread, so the read rate no longer depends on how long the
+//! preview and key handling take. The thread keeps the newest frames in a shared slot; the
+//! capture loop shows and saves whatever is newest and borrows the cameras for settings.
+//! With `--pre-roll`, the thread also keeps the frames of the last few seconds, so a capture
+//! can reach back to before the key was pressed.
+
+use opencv::core::Mat;
+use opencv::prelude::*;
+use opencv::videoio::CAP_PROP_FPS;
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::events::{Event, Events};
+use crate::source::{DepthSensor, FrameSource, read_source, reconnect_stream};
+use crate::{AppError, FrameTiming};
+
+/// Pause after a failed read before trying again.
+const RETRY: Duration = Duration::from_millis(10);
+
+/// Where to reconnect when a stream drops: URL, timeout and attempts.
+pub type Reconnect = (String, i32, u32);
+
+#[derive(Default)]
+struct Latest {
+    frames: Vec<Mat>,
+    /// Counts published frames, so readers can tell a new frame from the one they have.
+    seq: u64,
+    timing: FrameTiming,
+    /// Frames read within the pre-roll window, oldest first.
+    recent: VecDeque<(Instant, Vec<Mat>)>,
+    /// Why the thread stopped, for the capture loop to report.
+    error: Option<String>,
+    /// Whether a file source has no more frames.
+    ended: bool,
+}
+
+struct Shared {
+    videos: Mutex<Vec<Box<dyn FrameSource>>>,
+    latest: Mutex<Latest>,
+    published: Condvar,
+    /// Set while the capture loop waits for the cameras, so the thread lets go of them.
+    wanted: Mutex<bool>,
+    /// Signalled when the capture loop took the cameras and the thread may queue up again.
+    released: Condvar,
+    stop: AtomicBool,
+}
+
+pub struct Capture {
+    shared: Arc<Shared>,
+    seen: u64,
+    /// The `seen` of the frames saved last.
+    saved: Cell<u64>,
+    events: Arc<Events>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Starts reading `videos` into copies of `frames`, keeping the last `pre_roll` of them.
+    /// Files are read at their own frame rate rather than as fast as they decode, until
+    /// they end.
+    pub fn spawn(
+        videos: Vec<Box<dyn FrameSource>>,
+        frames: &[Mat],
+        depth: Option<DepthSensor>,
+        reconnect: Option<Reconnect>,
+        file: bool,
+        pre_roll: Option<Duration>,
+        events: Arc<Events>,
+    ) -> Result<Self, AppError> {
+        let pace = match videos.first() {
+            Some(video) if file => {
+                let fps = video.get(CAP_PROP_FPS)?;
+                Some(Duration::from_secs_f64(
+                    1.0 / if fps > 0.0 { fps } else { 30.0 },
+                ))
+            }
+            _ => None,
+        };
+        let mut buffers = frames
+            .iter()
+            .map(|frame| frame.try_clone())
+            .collect::<Result<Vec<_>, _>>()?;
+        let shared = Arc::new(Shared {
+            videos: Mutex::new(videos),
+            latest: Mutex::new(Latest::default()),
+            published: Condvar::new(),
+            wanted: Mutex::new(false),
+            released: Condvar::new(),
+            stop: AtomicBool::new(false),
+        });
+        let (thread_shared, thread_events) = (Arc::clone(&shared), Arc::clone(&events));
+        let handle = thread::spawn(move || {
+            let (shared, events) = (thread_shared, thread_events);
+            while !shared.stop.load(Ordering::SeqCst) {
+                if let Ok(wanted) = shared.wanted.lock() {
+                    drop(shared.released.wait_while(wanted, |wanted| *wanted));
+                }
+                let read_started = Instant::now();
+                let read = {
+                    let Ok(mut videos) = shared.videos.lock() else {
+                        break;
+                    };
+                    read_source(&mut videos, &mut buffers, depth)
+                };
+                if !read && file {
+                    println!("end of file, showing the last frame");
+                    if let Ok(mut latest) = shared.latest.lock() {
+                        latest.ended = true;
+                    }
+                    break;
+                }
+                if !read {
+                    events.record(Event::ReadFailed, 1, "");
+                }
+                if !read && let Some((url, timeout_ms, attempts)) = &reconnect {
+                    if let Ok(mut videos) = shared.videos.lock() {
+                        let _ = videos[0].release();
+                    }
+                    // The attempts can take several timeouts, so the cameras stay free for
+                    // the capture loop meanwhile and are only locked to swap the stream in.
+                    match reconnect_stream(url, *timeout_ms, *attempts) {
+                        Ok(video) => {
+                            let Ok(mut videos) = shared.videos.lock() else {
+                                break;
+                            };
+                            videos[0] = Box::new(video);
+                        }
+                        Err(e) => {
+                            if let Ok(mut latest) = shared.latest.lock() {
+                                latest.error = Some(e.to_string());
+                            }
+                            shared.published.notify_all();
+                            break;
+                        }
+                    }
+                }
+                if !read {
+                    thread::sleep(RETRY);
+                    continue;
+                }
+                let Ok(frames) = buffers
+                    .iter()
+                    .map(|frame| frame.try_clone())
+                    .collect::<Result<Vec<_>, _>>()
+                else {
+                    continue;
+                };
+                let kept: opencv::Result<Vec<Mat>> = match pre_roll {
+                    Some(_) => frames.iter().map(|frame| frame.try_clone()).collect(),
+                    None => Ok(Vec::new()),
+                };
+                if let Ok(mut latest) = shared.latest.lock() {
+                    latest.timing.record(read_started);
+                    latest.frames = frames;
+                    latest.seq += 1;
+                    if let (Some(pre_roll), Ok(kept)) = (pre_roll, kept) {
+                        let now = Instant::now();
+                        latest.recent.push_back((now, kept));
+                        while latest
+                            .recent
+                            .front()
+                            .is_some_and(|(read, _)| now - *read > pre_roll)
+                        {
+                            latest.recent.pop_front();
+                        }
+                    }
+                }
+                shared.published.notify_all();
+                if let Some(pace) = pace {
+                    thread::sleep(pace.saturating_sub(read_started.elapsed()));
+                }
+            }
+        });
+        Ok(Capture {
+            shared,
+            seen: 0,
+            saved: Cell::new(0),
+            events,
+            handle: Some(handle),
+        })
+    }
+
+    /// The cameras, with reading paused until the guard is dropped.
+    pub fn videos(&self) -> MutexGuard<'_, Vec<Box<dyn FrameSource>>> {
+        self.set_wanted(true);
+        let videos = self
+            .shared
+            .videos
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.set_wanted(false);
+        videos
+    }
+
+    fn set_wanted(&self, wanted: bool) {
+        *self
+            .shared
+            .wanted
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = wanted;
+        if !wanted {
+            self.shared.released.notify_all();
+        }
+    }
+
+    /// Waits up to `timeout` for frames newer than the last ones taken and copies them into
+    /// `frames`. Whether there were new frames.
+    pub fn next(&mut self, frames: &mut [Mat], timeout: Duration) -> Result<bool, AppError> {
+        let latest = self
+            .shared
+            .latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (latest, _) = self
+            .shared
+            .published
+            .wait_timeout_while(latest, timeout, |latest| {
+                latest.seq == self.seen && latest.error.is_none()
+            })
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(error) = &latest.error {
+            return Err(AppError::StreamError(error.clone()));
+        }
+        if latest.seq == self.seen {
+            return Ok(false);
+        }
+        let dropped = (latest.seq - self.seen - 1) as usize;
+        if dropped > 0 {
+            self.events.record(Event::Dropped, dropped, "");
+        }
+        self.seen = latest.seq;
+        for (frame, new) in frames.iter_mut().zip(&latest.frames) {
+            *frame = new.try_clone()?;
+        }
+        Ok(true)
+    }
+
+    /// Notes that the frames last taken are being saved. Saving them again before a newer
+    /// frame arrived counts as a duplicate, unless a file source ended on them.
+    pub fn saving(&self) {
+        if self.seen == 0 || self.saved.replace(self.seen) != self.seen {
+            return;
+        }
+        let ended = self.shared.latest.lock().is_ok_and(|latest| latest.ended);
+        if !ended {
+            self.events.record(
+                Event::Duplicate,
+                1,
+                "no new frame, saving the last one again",
+            );
+        }
+    }
+
+    /// Passes over the frames read so far, e.g. while paused, without counting them dropped.
+    pub fn skip(&mut self) {
+        if let Ok(latest) = self.shared.latest.lock() {
+            self.seen = latest.seq;
+        }
+    }
+
+    /// Copies of the frames read in the last `window`, oldest first.
+    pub fn recent(&self, window: Duration) -> Result<Vec<Vec<Mat>>, AppError> {
+        let latest = self
+            .shared
+            .latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut recent = Vec::new();
+        for (read, frames) in &latest.recent {
+            if read.elapsed() <= window {
+                recent.push(
+                    frames
+                        .iter()
+                        .map(|frame| frame.try_clone())
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+        }
+        Ok(recent)
+    }
+
+    /// Smoothed frames per second and read time in milliseconds.
+    pub fn timing(&self) -> (f64, f64) {
+        self.shared
+            .latest
+            .lock()
+            .map(|latest| (latest.timing.fps, latest.timing.read_ms))
+            .unwrap_or_default()
+    }
+
+    /// Stops the thread and hands the cameras back.
+    pub fn stop(mut self) -> Vec<Box<dyn FrameSource>> {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        std::mem::take(&mut *self.videos())
+    }
+}
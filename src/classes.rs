@@ -0,0 +1,101 @@
+//! Key-to-class-name mapping, e.g. `c = "cat"`, so class directories get readable names.
+//! A class can also set how many captures it needs: `c = { name = "cat", target = 500 }`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{AppError, sanitize_label};
+
+/// File name of the copy kept in the store path, documenting the mapping with the data.
+pub const STORE_FILE: &str = "classes.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Name(String),
+    Class { name: String, target: Option<usize> },
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ClassMap {
+    names: BTreeMap<char, String>,
+    targets: BTreeMap<String, usize>,
+}
+
+impl ClassMap {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let table: BTreeMap<String, Entry> = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut names = BTreeMap::new();
+        let mut targets = BTreeMap::new();
+        for (key, entry) in table {
+            let mut chars = key.chars();
+            let (Some(key), None) = (chars.next(), chars.next()) else {
+                return Err(AppError::DatasetError(format!(
+                    "class key {:?} in {:?} is not a single character",
+                    key, path
+                ))
+                .into());
+            };
+            let (name, target) = match entry {
+                Entry::Name(name) => (name, None),
+                Entry::Class { name, target } => (name, target),
+            };
+            let name = sanitize_label(&name);
+            if let Some(target) = target {
+                targets.insert(name.clone(), target);
+            }
+            names.insert(key, name);
+        }
+        Ok(ClassMap { names, targets })
+    }
+
+    /// The mapping saved in `store_path`, or an empty one.
+    pub fn from_store(store_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = store_path.join(STORE_FILE);
+        if path.is_file() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Class directory for `key`; unmapped keys keep their own character.
+    pub fn label(&self, key: char) -> String {
+        self.names
+            .get(&key)
+            .cloned()
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    /// Whether `key` is mapped to a class, so keys outside a-z 0-9 A-Z can capture too.
+    pub fn contains(&self, key: char) -> bool {
+        self.names.contains_key(&key)
+    }
+
+    /// Every mapped key.
+    pub fn keys(&self) -> impl Iterator<Item = char> + '_ {
+        self.names.keys().copied()
+    }
+
+    /// `key  name` lines for every mapped key, for the help overlay.
+    pub fn describe(&self) -> Vec<String> {
+        self.names
+            .iter()
+            .map(|(key, name)| format!("{}            {}", key, name))
+            .collect()
+    }
+
+    /// Capture target configured for the class directory `label`.
+    pub fn target(&self, label: &str) -> Option<usize> {
+        self.targets.get(label).copied()
+    }
+
+    /// Class directories that have a configured target.
+    pub fn targeted(&self) -> impl Iterator<Item = &String> {
+        self.targets.keys()
+    }
+}
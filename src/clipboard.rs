@@ -0,0 +1,94 @@
+//! Puts a frame on the system clipboard through the platform's own tools, so no GUI toolkit
+//! dependency is needed: `wl-copy` or `xclip` on Linux, AppleScript on macOS, PowerShell on
+//! Windows.
+
+use opencv::{
+    core::{Mat, Vector},
+    imgcodecs::imencode,
+    prelude::*,
+};
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::AppError;
+
+fn png(frame: &Mat) -> Result<Vec<u8>, AppError> {
+    let mut buf = Vector::<u8>::new();
+    if !imencode(".png", frame, &mut buf, &Vector::new())? {
+        return Err(AppError::ClipboardError("cannot encode frame".into()));
+    }
+    Ok(buf.to_vec())
+}
+
+/// Runs `program` with the PNG on stdin.
+fn pipe(program: &str, args: &[&str], data: &[u8]) -> Result<(), AppError> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::ClipboardError(format!("{}: {}", program, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data)?;
+    }
+    if !child.wait()?.success() {
+        return Err(AppError::ClipboardError(format!("{} failed", program)));
+    }
+    Ok(())
+}
+
+/// Runs `program` on the PNG saved to a temporary file, whose path replaces `{path}`.
+fn run_on_file(program: &str, args: &[&str], data: &[u8]) -> Result<(), AppError> {
+    let path = env::temp_dir().join("img_collector_clipboard.png");
+    fs::write(&path, data)?;
+    let path_str = path.to_string_lossy();
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.replace("{path}", &path_str))
+        .collect();
+    let status = Command::new(program)
+        .args(&args)
+        .status()
+        .map_err(|e| AppError::ClipboardError(format!("{}: {}", program, e)));
+    let _ = fs::remove_file(&path);
+    if !status?.success() {
+        return Err(AppError::ClipboardError(format!("{} failed", program)));
+    }
+    Ok(())
+}
+
+pub fn copy_image(frame: &Mat) -> Result<(), AppError> {
+    let data = png(frame)?;
+    if cfg!(target_os = "macos") {
+        run_on_file(
+            "osascript",
+            &[
+                "-e",
+                "set the clipboard to (read (POSIX file \"{path}\") as «class PNGf»)",
+            ],
+            &data,
+        )
+    } else if cfg!(windows) {
+        run_on_file(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-STA",
+                "-Command",
+                "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; \
+                 [System.Windows.Forms.Clipboard]::SetImage([System.Drawing.Image]::FromFile('{path}'))",
+            ],
+            &data,
+        )
+    } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+        pipe("wl-copy", &["--type", "image/png"], &data)
+    } else {
+        pipe(
+            "xclip",
+            &["-selection", "clipboard", "-t", "image/png", "-i"],
+            &data,
+        )
+    }
+}
@@ -0,0 +1,288 @@
+//! `--config img_collector.toml`: top-level options by their flag name, e.g.
+//! `store_path = "rig"`, `format = "jpg"`, `beep = true` or `keymap = "keymap.toml"`, plus
+//! `command`, the command line used when none is given, e.g.
+//! `command = ["capture", "--device", "2", "--width", "1920", "manual", "--focus", "30"]`.
+//! Options given on the command line take precedence over the file.
+//!
+//! Every option can also be set as `IMG_COLLECTOR_<OPTION>`, with the subcommand path for
+//! subcommand options: `IMG_COLLECTOR_STORE_PATH=rig`, `IMG_COLLECTOR_CAPTURE_DEVICE=2`,
+//! `IMG_COLLECTOR_CAPTURE_MANUAL_FOCUS=30`; flags take `true` or `false`. The environment
+//! sits between the command line and the config file, and `IMG_COLLECTOR_CONFIG` names the
+//! file when `--config` is not given.
+
+use clap::error::ErrorKind;
+use clap::{Command, CommandFactory};
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{AppError, Args};
+
+pub const ENV_PREFIX: &str = "IMG_COLLECTOR";
+
+/// Attaches `<prefix>_<ARG>` to every argument of `command` and its subcommands.
+fn with_env(command: Command, prefix: &str) -> Command {
+    let command = command.mut_args(|arg| {
+        let name = format!("{}_{}", prefix, arg.get_id().as_str().to_uppercase());
+        arg.env(name)
+    });
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    names.iter().fold(command, |command, name| {
+        let prefix = format!("{}_{}", prefix, name.to_uppercase().replace('-', "_"));
+        command.mut_subcommand(name, |sub| with_env(sub, &prefix))
+    })
+}
+
+/// The command-line interface, reading unset options from the environment.
+pub fn command() -> Command {
+    with_env(Args::command(), ENV_PREFIX)
+}
+
+/// The `--config` path among the raw arguments.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn value_args(flag: &str, value: &toml::Value, out: &mut Vec<OsString>) -> Result<(), AppError> {
+    match value {
+        toml::Value::Boolean(true) => out.push(flag.into()),
+        toml::Value::Boolean(false) => {}
+        toml::Value::String(s) => {
+            out.push(flag.into());
+            out.push(s.into());
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                value_args(flag, value, out)?;
+            }
+        }
+        toml::Value::Table(_) => {
+            return Err(AppError::ConfigError(format!("{} cannot be a table", flag)));
+        }
+        other => {
+            out.push(flag.into());
+            out.push(other.to_string().into());
+        }
+    }
+    Ok(())
+}
+
+/// The process arguments with the options of the `--config` file filled in.
+pub fn args() -> Result<Vec<OsString>, Box<dyn Error>> {
+    with_config(env::args_os().collect())
+}
+
+/// `args` with the options of their `--config` file filled in.
+fn with_config(mut args: Vec<OsString>) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let Some(path) = config_path(&args)
+        .or_else(|| env::var_os(format!("{}_CONFIG", ENV_PREFIX)).map(PathBuf::from))
+    else {
+        return Ok(args);
+    };
+    let table: toml::Table = toml::from_str(&fs::read_to_string(&path)?)?;
+    let given: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let mut options = Vec::new();
+    let mut default_command = Vec::new();
+    for (key, value) in &table {
+        if key == "command" {
+            let toml::Value::Array(values) = value else {
+                return Err(AppError::ConfigError(format!(
+                    "command in {:?} must be a list of arguments",
+                    path
+                ))
+                .into());
+            };
+            for value in values {
+                match value {
+                    toml::Value::String(s) => default_command.push(OsString::from(s)),
+                    other => default_command.push(other.to_string().into()),
+                }
+            }
+            continue;
+        }
+        let flag = format!("--{}", key.replace('_', "-"));
+        let prefix = format!("{}=", flag);
+        let env_name = format!("{}_{}", ENV_PREFIX, key.to_uppercase());
+        if env::var_os(env_name).is_some()
+            || given
+                .iter()
+                .any(|arg| *arg == flag || arg.starts_with(&prefix))
+        {
+            continue;
+        }
+        value_args(&flag, value, &mut options)?;
+    }
+    // Top-level options go before the command, where clap expects them.
+    args.splice(1..1, options);
+    if !default_command.is_empty()
+        && let Err(e) = command().try_get_matches_from(&args)
+        && matches!(
+            e.kind(),
+            ErrorKind::MissingSubcommand | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+        )
+    {
+        args.extend(default_command);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "img_collector_config_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn os(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    /// `args` merged with a config file holding `toml`, whose path replaces `{config}`.
+    fn merged(name: &str, toml: &str, args: &[&str]) -> Result<Vec<String>, Box<dyn Error>> {
+        let path = scratch(name).join("img_collector.toml");
+        fs::write(&path, toml).unwrap();
+        let path = path.to_string_lossy();
+        let args = args
+            .iter()
+            .map(|arg| OsString::from(arg.replace("{config}", &path)))
+            .collect();
+        Ok(with_config(args)?
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().replace(path.as_ref(), "{config}"))
+            .collect())
+    }
+
+    #[test]
+    fn config_path_takes_either_flag_form() {
+        assert_eq!(
+            config_path(&os(&["app", "--config", "a.toml", "reindex"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            config_path(&os(&["app", "--config=b.toml"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(config_path(&os(&["app", "reindex"])), None);
+        assert_eq!(config_path(&os(&["app", "--config"])), None);
+    }
+
+    #[test]
+    fn values_become_flags() {
+        let args = |value: toml::Value| {
+            let mut out = Vec::new();
+            value_args("--opt", &value, &mut out).map(|()| out)
+        };
+        assert_eq!(args(true.into()).unwrap(), os(&["--opt"]));
+        assert!(args(false.into()).unwrap().is_empty());
+        assert_eq!(args("jpg".into()).unwrap(), os(&["--opt", "jpg"]));
+        assert_eq!(args(3.into()).unwrap(), os(&["--opt", "3"]));
+        assert_eq!(args(0.5.into()).unwrap(), os(&["--opt", "0.5"]));
+        assert_eq!(
+            args(vec!["a", "b"].into()).unwrap(),
+            os(&["--opt", "a", "--opt", "b"])
+        );
+        assert!(args(toml::Value::Table(toml::Table::new())).is_err());
+    }
+
+    #[test]
+    fn without_a_config_the_args_are_unchanged() {
+        let args = os(&["app", "reindex"]);
+        assert_eq!(with_config(args.clone()).unwrap(), args);
+    }
+
+    #[test]
+    fn file_options_go_before_the_command() {
+        let args = merged(
+            "options",
+            "store_path = \"rig\"\nhold = true\nburst = 3\n",
+            &["app", "--config", "{config}", "reindex"],
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "app",
+                "--burst",
+                "3",
+                "--hold",
+                "--store-path",
+                "rig",
+                "--config",
+                "{config}",
+                "reindex"
+            ]
+        );
+    }
+
+    #[test]
+    fn the_command_line_wins_over_the_file() {
+        let args = merged(
+            "precedence",
+            "store_path = \"rig\"\nburst = 3\n",
+            &[
+                "app",
+                "--config={config}",
+                "--store-path",
+                "cli",
+                "--burst=5",
+                "reindex",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "app",
+                "--config={config}",
+                "--store-path",
+                "cli",
+                "--burst=5",
+                "reindex"
+            ]
+        );
+    }
+
+    #[test]
+    fn the_default_command_applies_only_without_one() {
+        let toml = "command = [\"stats\", \"--json\"]\n";
+        let args = merged("default", toml, &["app", "--config", "{config}"]).unwrap();
+        assert_eq!(args, ["app", "--config", "{config}", "stats", "--json"]);
+        let args = merged("given", toml, &["app", "--config", "{config}", "reindex"]).unwrap();
+        assert_eq!(args, ["app", "--config", "{config}", "reindex"]);
+    }
+
+    #[test]
+    fn malformed_configs_are_rejected() {
+        let args = ["app", "--config", "{config}"];
+        assert!(merged("command", "command = \"stats\"\n", &args).is_err());
+        assert!(merged("table", "[store_path]\nx = 1\n", &args).is_err());
+        assert!(merged("syntax", "store_path = ", &args).is_err());
+    }
+}
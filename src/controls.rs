@@ -0,0 +1,681 @@
+//! What the capture window's keys do. `Controls` is the capture loop state the keys change,
+//! such as the overlays shown, the typed label and a running countdown; `Keys` answers a key
+//! press with the session's keymap and options.
+
+use opencv::core::Mat;
+use opencv::highgui::{
+    WINDOW_FULLSCREEN, WINDOW_NORMAL, WND_PROP_FULLSCREEN, imshow, set_window_property, wait_key_ex,
+};
+use opencv::videoio::CAP_PROP_AUTOFOCUS;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::capture::Capture;
+use crate::keymap::{self, Action, Keymap};
+use crate::recorder::{Recorder, SETTLE_FRAMES, exposure_bracket, focus_bracket};
+use crate::source::{CameraProfile, CaptureSettings, FrameSource, VideoProp, step_prop};
+use crate::transform::sharpness;
+use crate::ui::to_display;
+use crate::{clipboard, is_uncertain, sanitize_label};
+
+/// Capture loop state the keys change, read back by the loop to draw the preview and to run
+/// countdown and held captures.
+#[derive(Debug, Default)]
+pub struct Controls {
+    /// While paused the last frame stays up: the camera is still read, but nothing new is
+    /// shown or captured.
+    pub paused: bool,
+    pub fullscreen: bool,
+    pub show_help: bool,
+    pub show_timing: bool,
+    pub show_counts: bool,
+    /// Live sharpness and the best value since the overlay was turned on, for manual focusing.
+    pub focus_peak: Option<f64>,
+    pub show_histogram: bool,
+    pub show_zebra: bool,
+    pub show_grid: bool,
+    pub keep_thumbnail: bool,
+    /// The label as typed so far, while one is being typed.
+    pub typing: Option<String>,
+    pub typed_label: Option<String>,
+    /// The class a QR code, marker or class key picked last, which detections capture to.
+    pub active_label: Option<String>,
+    /// The class key whose blurry frame was warned about; pressing it again saves anyway.
+    pub blur_warned: Option<char>,
+    /// The `--hold` label being recorded and when its key was last seen.
+    pub held: Option<(String, Instant)>,
+    /// The `--countdown` label and when the countdown started.
+    pub countdown: Option<(String, Instant)>,
+}
+
+impl Controls {
+    /// Edits the label being typed with `key`: Enter sets it (empty clears it), Esc cancels.
+    /// Whether a label was being typed, so the key went to it.
+    pub fn type_key(&mut self, key: i32) -> bool {
+        let Some(buffer) = &mut self.typing else {
+            return false;
+        };
+        match keymap::key_char(key) {
+            Some('\r' | '\n') => {
+                let label = sanitize_label(buffer);
+                self.typed_label = (!label.is_empty()).then_some(label);
+                match &self.typed_label {
+                    Some(label) => println!("captures go to {}", label),
+                    None => println!("typed label cleared"),
+                }
+                self.typing = None;
+            }
+            Some('\x1b') => self.typing = None,
+            Some('\x08') => {
+                buffer.pop();
+            }
+            Some(c) if !c.is_control() => buffer.push(c),
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The keymap and the session options that decide what a key press does.
+pub struct Keys<'a> {
+    pub keymap: Keymap,
+    /// Settings of a capture source, for brackets and the focus sweep.
+    pub settings: Option<&'a CaptureSettings>,
+    pub profile_path: PathBuf,
+    pub pre_roll: Option<Duration>,
+    pub burst: u32,
+    pub hold: bool,
+    pub countdown: bool,
+    pub active_learning: bool,
+    pub confidence_threshold: f32,
+    pub entropy_threshold: Option<f32>,
+    /// Whether QR codes or ArUco markers name the class rather than the key pressed.
+    pub detected_labels: bool,
+    /// Whether class keys pick the class of face captures instead of capturing.
+    pub faces: bool,
+}
+
+impl Keys<'_> {
+    /// Whether the model is unsure enough of `predictions` for the frame to be worth labelling.
+    pub fn uncertain(&self, predictions: &[(String, f32)]) -> bool {
+        is_uncertain(
+            predictions,
+            self.confidence_threshold,
+            self.entropy_threshold,
+        )
+    }
+
+    /// Handles the raw `wait_key_ex` code `key` for the frames last read into `frames`, with
+    /// the model's `predictions` for them. Whether the key quits the session.
+    pub fn press(
+        &self,
+        key: i32,
+        controls: &mut Controls,
+        recorder: &mut Recorder,
+        capture: &Capture,
+        frames: &mut [Mat],
+        predictions: &[(String, f32)],
+    ) -> Result<bool, Box<dyn Error>> {
+        if controls.type_key(key) {
+            return Ok(false);
+        }
+        match self.keymap.action(key) {
+            Some(action) => self.act(action, controls, recorder, capture, frames, predictions),
+            None => {
+                self.capture(key, controls, recorder, capture, frames, predictions)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs `action`. Whether it quits the session.
+    fn act(
+        &self,
+        action: Action,
+        controls: &mut Controls,
+        recorder: &mut Recorder,
+        capture: &Capture,
+        frames: &mut [Mat],
+        predictions: &[(String, f32)],
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut videos = capture.videos();
+        match action {
+            Action::Quit => return Ok(true),
+            Action::Help => controls.show_help = !controls.show_help,
+            Action::Fullscreen => {
+                controls.fullscreen = !controls.fullscreen;
+                let mode = if controls.fullscreen {
+                    WINDOW_FULLSCREEN
+                } else {
+                    WINDOW_NORMAL
+                };
+                set_window_property("video", WND_PROP_FULLSCREEN, mode as f64)?;
+            }
+            Action::SavePreRoll => {
+                // Goes to the label captures would go to, else the last capture's class.
+                let label = controls
+                    .typed_label
+                    .as_ref()
+                    .or(controls.active_label.as_ref())
+                    .or(recorder.history.last().map(|saved| &saved.label))
+                    .cloned();
+                match (self.pre_roll, label) {
+                    (None, _) => println!("saving the pre-roll needs --pre-roll"),
+                    (_, None) => println!("no label to save the pre-roll to"),
+                    (Some(window), Some(label)) => {
+                        let recent = capture.recent(window)?;
+                        println!("save {} pre-roll frames to {}", recent.len(), label);
+                        recorder.read_cameras(&videos);
+                        for frames in &recent {
+                            recorder.save_frames(&label, frames)?;
+                        }
+                    }
+                }
+            }
+            Action::TypeLabel => {
+                controls.typing = Some(controls.typed_label.clone().unwrap_or_default());
+            }
+            Action::Undo => recorder.undo()?,
+            Action::CopyFrame => {
+                // The camera frame as captured, without overlays or mirroring.
+                match clipboard::copy_image(&to_display(&frames[0])?) {
+                    Ok(()) => recorder.notify("frame copied to clipboard".into()),
+                    Err(e) => recorder.notify(e.to_string()),
+                }
+            }
+            Action::KeepThumbnail => controls.keep_thumbnail = !controls.keep_thumbnail,
+            Action::Grid => controls.show_grid = !controls.show_grid,
+            Action::Zebra => controls.show_zebra = !controls.show_zebra,
+            Action::Histogram => controls.show_histogram = !controls.show_histogram,
+            Action::Sharpness => {
+                controls.focus_peak = match controls.focus_peak {
+                    Some(_) => None,
+                    None => Some(0.0),
+                };
+            }
+            Action::Counts => controls.show_counts = !controls.show_counts,
+            Action::Timing => controls.show_timing = !controls.show_timing,
+            Action::Pause => {
+                controls.paused = !controls.paused;
+                println!("{}", if controls.paused { "paused" } else { "resumed" });
+            }
+            Action::PrintSettings => {
+                for video in videos.iter() {
+                    let f = video.focus()?;
+                    println!("Focus: {}", f);
+                    println!("Exposure: {}", video.exposure()?);
+                    println!("Gain: {}", video.gain()?);
+                    println!("Brightness: {}", video.brightness()?);
+                    println!("WB temperature: {}", video.wb_temperature()?);
+                    println!("Pan: {}", video.pan()?);
+                    println!("Tilt: {}", video.tilt()?);
+                    println!("Zoom: {}", video.zoom()?);
+                }
+            }
+            Action::FocusDown => {
+                for video in videos.iter_mut() {
+                    video.set_focus(video.focus()?.max(1.0) - 1.0)?;
+                }
+            }
+            Action::FocusUp => {
+                for video in videos.iter_mut() {
+                    video.set_focus(video.focus()? + 1.0)?;
+                }
+            }
+            Action::ExposureDown | Action::ExposureUp => step_prop(
+                &mut videos,
+                "Exposure",
+                <dyn FrameSource>::exposure,
+                <dyn FrameSource>::set_exposure,
+                if action == Action::ExposureDown {
+                    -1.0
+                } else {
+                    1.0
+                },
+            )?,
+            Action::GainDown | Action::GainUp => step_prop(
+                &mut videos,
+                "Gain",
+                <dyn FrameSource>::gain,
+                <dyn FrameSource>::set_gain,
+                if action == Action::GainDown {
+                    -1.0
+                } else {
+                    1.0
+                },
+            )?,
+            Action::BrightnessDown | Action::BrightnessUp => step_prop(
+                &mut videos,
+                "Brightness",
+                <dyn FrameSource>::brightness,
+                <dyn FrameSource>::set_brightness,
+                if action == Action::BrightnessDown {
+                    -1.0
+                } else {
+                    1.0
+                },
+            )?,
+            Action::WbDown | Action::WbUp => step_prop(
+                &mut videos,
+                "WB temperature",
+                <dyn FrameSource>::wb_temperature,
+                <dyn FrameSource>::set_wb_temperature,
+                if action == Action::WbDown {
+                    -100.0
+                } else {
+                    100.0
+                },
+            )?,
+            Action::PanLeft | Action::PanRight => step_prop(
+                &mut videos,
+                "Pan",
+                <dyn FrameSource>::pan,
+                <dyn FrameSource>::set_pan,
+                if action == Action::PanLeft { -1.0 } else { 1.0 },
+            )?,
+            Action::TiltUp | Action::TiltDown => step_prop(
+                &mut videos,
+                "Tilt",
+                <dyn FrameSource>::tilt,
+                <dyn FrameSource>::set_tilt,
+                if action == Action::TiltUp { 1.0 } else { -1.0 },
+            )?,
+            Action::ZoomIn | Action::ZoomOut => step_prop(
+                &mut videos,
+                "Zoom",
+                <dyn FrameSource>::zoom,
+                <dyn FrameSource>::set_zoom,
+                if action == Action::ZoomIn { 1.0 } else { -1.0 },
+            )?,
+            Action::FocusSweep => match self.settings {
+                Some(settings) => {
+                    for (video, frame) in videos.iter_mut().zip(frames.iter_mut()) {
+                        focus_sweep(video, frame, settings)?;
+                    }
+                }
+                None => println!("focus sweep needs a capture source"),
+            },
+            Action::SaveProfile => {
+                CameraProfile::from_capture(&videos[0])?.save(&self.profile_path)?;
+                println!("save camera profile to {:?}", self.profile_path);
+            }
+            Action::SavePrediction if self.active_learning && !self.uncertain(predictions) => {
+                println!("model is confident, skipping frame");
+            }
+            Action::SavePrediction => match predictions.first() {
+                Some((name, _)) => {
+                    let label = sanitize_label(name);
+                    capture.saving();
+                    recorder.save_burst(&label, &mut videos, frames, self.burst)?;
+                }
+                None => println!("no model suggestion to confirm"),
+            },
+        }
+        Ok(false)
+    }
+
+    /// Captures to the class of `key`, or to the detected or typed label: right away, as a
+    /// bracket, after the countdown or while the key is held, as the options say.
+    fn capture(
+        &self,
+        key: i32,
+        controls: &mut Controls,
+        recorder: &mut Recorder,
+        capture: &Capture,
+        frames: &mut [Mat],
+        predictions: &[(String, f32)],
+    ) -> Result<(), Box<dyn Error>> {
+        let key = match keymap::key_char(key) {
+            Some(k) if k.is_ascii_alphanumeric() || recorder.class_map.contains(k) => k,
+            _ => return Ok(()),
+        };
+        let label = match (&controls.active_label, &controls.typed_label) {
+            (Some(label), _) if self.detected_labels => label.clone(),
+            (_, Some(label)) => label.clone(),
+            _ => recorder.class_map.label(key),
+        };
+        if self.active_learning && !self.uncertain(predictions) {
+            println!("model is confident, skipping frame");
+            return Ok(());
+        }
+        if self.faces {
+            println!("face captures go to {}", label);
+            controls.active_label = Some(label);
+            return Ok(());
+        }
+        let mut videos = capture.videos();
+        match self.settings {
+            Some(settings)
+                if settings.focus_bracket > 0 || !settings.exposure_bracket.is_empty() =>
+            {
+                if recorder.at_limit(&label)? {
+                    recorder.confirm(false);
+                    return Ok(());
+                }
+                if settings.focus_bracket > 0 {
+                    focus_bracket(recorder, &label, &mut videos, frames, settings)?;
+                } else {
+                    exposure_bracket(recorder, &label, &mut videos, frames, settings)?;
+                }
+            }
+            _ if self.countdown => {
+                controls.countdown = Some((label, Instant::now()));
+            }
+            _ if self.hold => {
+                if controls
+                    .held
+                    .as_ref()
+                    .is_none_or(|(held_label, _)| *held_label != label)
+                {
+                    println!("start recording {}", label);
+                }
+                controls.held = Some((label, Instant::now()));
+            }
+            _ => {
+                // Judged on what would be saved, so a blurry background outside the ROI
+                // doesn't count.
+                if let Some(score) = recorder.blurry(&recorder.prepare_all(frames)?)?
+                    && controls.blur_warned != Some(key)
+                {
+                    println!(
+                        "frame looks blurry ({:.1}), press {} again to save anyway",
+                        score, key
+                    );
+                    controls.blur_warned = Some(key);
+                    return Ok(());
+                }
+                controls.blur_warned = None;
+                let min_sharpness = recorder.min_sharpness.take();
+                capture.saving();
+                recorder.save_burst(&label, &mut videos, frames, self.burst)?;
+                recorder.min_sharpness = min_sharpness;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Steps the focus of `video` through the sweep range and leaves it where `frame` was
+/// sharpest, returning that focus.
+fn focus_sweep(
+    video: &mut dyn FrameSource,
+    frame: &mut Mat,
+    settings: &CaptureSettings,
+) -> Result<f64, Box<dyn Error>> {
+    let _ = video.set(CAP_PROP_AUTOFOCUS, 0.0);
+    let mut best = (video.focus()?, 0.0);
+    let mut focus = settings.sweep_min;
+    while focus <= settings.sweep_max {
+        video.set_focus(focus)?;
+        for _ in 0..SETTLE_FRAMES {
+            video.read(frame)?;
+        }
+        let score = sharpness(frame)?;
+        println!("Focus {}: sharpness {:.2}", focus, score);
+        if score > best.1 {
+            best = (focus, score);
+        }
+        let _ = imshow("video", frame);
+        let _ = wait_key_ex(1);
+        focus += settings.sweep_step.max(1.0);
+    }
+    video.set_focus(best.0)?;
+    println!("Focus locked at {} (sharpness {:.2})", best.0, best.1);
+    Ok(best.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Events;
+    use crate::recorder::tests::{camera, recorder};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_controls_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn key(c: char) -> i32 {
+        c as i32
+    }
+
+    /// The keys of a session with every option off.
+    fn keys() -> Keys<'static> {
+        Keys {
+            keymap: Keymap::default(),
+            settings: None,
+            profile_path: PathBuf::new(),
+            pre_roll: None,
+            burst: 1,
+            hold: false,
+            countdown: false,
+            active_learning: false,
+            confidence_threshold: 0.6,
+            entropy_threshold: None,
+            detected_labels: false,
+            faces: false,
+        }
+    }
+
+    /// A recorder saving to `store` and a capture reading a mock camera, with its first frame
+    /// read into the returned frames.
+    fn session(store: &Path) -> (Recorder, Capture, Vec<Mat>) {
+        let recorder = recorder(store, "{index}");
+        let (videos, mut frames) = camera(10);
+        let mut capture = Capture::spawn(
+            videos,
+            &frames,
+            None,
+            None,
+            false,
+            None,
+            Arc::new(Events::default()),
+        )
+        .unwrap();
+        assert!(capture.next(&mut frames, Duration::from_secs(5)).unwrap());
+        (recorder, capture, frames)
+    }
+
+    #[test]
+    fn typed_keys_edit_the_label() {
+        let mut controls = Controls::default();
+        assert!(!controls.type_key(key('c')));
+        controls.typing = Some(String::new());
+        for c in "cay\x08t/1".chars() {
+            assert!(controls.type_key(key(c)));
+        }
+        assert_eq!(controls.typing.as_deref(), Some("cat/1"));
+        assert!(controls.type_key(key('\r')));
+        assert_eq!(controls.typing, None);
+        assert_eq!(controls.typed_label.as_deref(), Some("cat_1"));
+        // Esc keeps the label there was; an empty label clears it.
+        controls.typing = Some("dog".into());
+        controls.type_key(key('\x1b'));
+        assert_eq!(controls.typed_label.as_deref(), Some("cat_1"));
+        controls.typing = Some(String::new());
+        controls.type_key(key('\r'));
+        assert_eq!(controls.typed_label, None);
+    }
+
+    #[test]
+    fn action_keys_toggle_the_overlays_and_quit() {
+        let store = scratch("actions");
+        let (mut recorder, capture, mut frames) = session(&store);
+        let (keys, mut controls) = (keys(), Controls::default());
+        let mut press = |c: char, controls: &mut Controls| {
+            keys.press(key(c), controls, &mut recorder, &capture, &mut frames, &[])
+                .unwrap()
+        };
+        assert!(!press('%', &mut controls));
+        assert!(!press('\t', &mut controls));
+        assert!(!press('|', &mut controls));
+        assert!(controls.show_timing && controls.paused);
+        assert_eq!(controls.focus_peak, Some(0.0));
+        assert!(!press('%', &mut controls));
+        assert!(!controls.show_timing);
+        assert!(!press('/', &mut controls));
+        assert_eq!(controls.typing.as_deref(), Some(""));
+        // While typing, even the quit key is part of the label.
+        assert!(!press('q', &mut controls));
+        assert_eq!(controls.typing.as_deref(), Some("q"));
+        controls.typing = None;
+        assert!(press('q', &mut controls));
+        capture.stop();
+    }
+
+    #[test]
+    fn class_keys_capture_to_their_class_or_the_typed_label() {
+        let store = scratch("class_keys");
+        let (mut recorder, capture, mut frames) = session(&store);
+        let (keys, mut controls) = (keys(), Controls::default());
+        keys.press(
+            key('c'),
+            &mut controls,
+            &mut recorder,
+            &capture,
+            &mut frames,
+            &[],
+        )
+        .unwrap();
+        controls.typed_label = Some("dog".into());
+        keys.press(
+            key('c'),
+            &mut controls,
+            &mut recorder,
+            &capture,
+            &mut frames,
+            &[],
+        )
+        .unwrap();
+        // Backspace undoes the dog.
+        keys.press(
+            0x08,
+            &mut controls,
+            &mut recorder,
+            &capture,
+            &mut frames,
+            &[],
+        )
+        .unwrap();
+        recorder.writer.flush();
+        assert!(store.join("c/0.png").is_file());
+        assert!(!store.join("dog/0.png").exists());
+        assert_eq!(recorder.class_count("c"), 1);
+        assert_eq!(recorder.class_count("dog"), 0);
+        capture.stop();
+    }
+
+    #[test]
+    fn blurry_frames_need_a_second_press() {
+        let store = scratch("blurry");
+        let (mut recorder, capture, mut frames) = session(&store);
+        recorder.min_sharpness = Some(1.0);
+        let (keys, mut controls) = (keys(), Controls::default());
+        keys.press(
+            key('c'),
+            &mut controls,
+            &mut recorder,
+            &capture,
+            &mut frames,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(controls.blur_warned, Some('c'));
+        assert_eq!(recorder.class_count("c"), 0);
+        keys.press(
+            key('c'),
+            &mut controls,
+            &mut recorder,
+            &capture,
+            &mut frames,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(controls.blur_warned, None);
+        assert_eq!(recorder.class_count("c"), 1);
+        // The check is only skipped for the capture that was asked for twice.
+        assert_eq!(recorder.min_sharpness, Some(1.0));
+        capture.stop();
+    }
+
+    #[test]
+    fn countdown_and_hold_start_instead_of_capturing() {
+        let store = scratch("modes");
+        let (mut recorder, capture, mut frames) = session(&store);
+        let mut controls = Controls::default();
+        let countdown = Keys {
+            countdown: true,
+            ..keys()
+        };
+        countdown
+            .press(
+                key('c'),
+                &mut controls,
+                &mut recorder,
+                &capture,
+                &mut frames,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            controls.countdown.as_ref().map(|(label, _)| label.as_str()),
+            Some("c")
+        );
+        let hold = Keys {
+            hold: true,
+            ..keys()
+        };
+        hold.press(
+            key('d'),
+            &mut controls,
+            &mut recorder,
+            &capture,
+            &mut frames,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            controls.held.as_ref().map(|(label, _)| label.as_str()),
+            Some("d")
+        );
+        let faces = Keys {
+            faces: true,
+            ..keys()
+        };
+        faces
+            .press(
+                key('e'),
+                &mut controls,
+                &mut recorder,
+                &capture,
+                &mut frames,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(controls.active_label.as_deref(), Some("e"));
+        assert!(recorder.history.is_empty());
+        capture.stop();
+    }
+
+    #[test]
+    fn active_learning_skips_frames_the_model_is_sure_of() {
+        let keys = Keys {
+            active_learning: true,
+            ..keys()
+        };
+        assert!(!keys.uncertain(&[("cat".into(), 0.9), ("dog".into(), 0.1)]));
+        assert!(keys.uncertain(&[("cat".into(), 0.5), ("dog".into(), 0.5)]));
+        assert!(keys.uncertain(&[]));
+    }
+}
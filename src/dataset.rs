@@ -0,0 +1,215 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::Db;
+use crate::{AppError, manifest, session};
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub path: PathBuf,
+    pub label: String,
+    /// Path below the label directory, e.g. `cam0/3.png`, led by the session directory for
+    /// session captures.
+    pub relative: PathBuf,
+}
+
+impl Sample {
+    /// File name that stays unique once samples of one label share a directory.
+    pub fn flat_name(&self) -> String {
+        self.relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+}
+
+/// Every image under `store_path`, from the capture database when there is one.
+pub fn samples(store_path: &Path) -> Result<Vec<Sample>, AppError> {
+    let images = match Db::existing(store_path)? {
+        Some(db) => db.images()?,
+        None => manifest::images(store_path)?,
+    };
+    Ok(from_paths(store_path, images))
+}
+
+/// `images` labelled by their top-level directory below `store_path`, or by the directory
+/// below that for session directories.
+pub fn from_paths(store_path: &Path, images: Vec<PathBuf>) -> Vec<Sample> {
+    let sessions: BTreeSet<_> = fs::read_dir(store_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join(session::FILE).is_file())
+        .map(|entry| entry.file_name())
+        .collect();
+    let mut samples = Vec::new();
+    for path in images {
+        let relative = path.strip_prefix(store_path).unwrap_or(&path).to_path_buf();
+        let mut components = relative.components();
+        let Some(mut label) = components.next() else {
+            continue;
+        };
+        let mut rest = PathBuf::new();
+        if sessions.contains(label.as_os_str()) {
+            // Keep the session in the relative path so flat names stay unique.
+            rest.push(label);
+            let Some(class) = components.next() else {
+                continue;
+            };
+            label = class;
+        }
+        rest.push(components.as_path());
+        if components.as_path().as_os_str().is_empty() {
+            continue;
+        }
+        samples.push(Sample {
+            label: label.as_os_str().to_string_lossy().into_owned(),
+            relative: rest,
+            path,
+        });
+    }
+    samples
+}
+
+/// Sorted distinct labels; a label's position is its class id.
+pub fn classes(samples: &[Sample]) -> Vec<String> {
+    samples
+        .iter()
+        .map(|sample| sample.label.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Copies `samples` to `dir/<label>/<flat name>`.
+pub fn copy_samples(samples: &[Sample], dir: &Path) -> Result<(), AppError> {
+    for sample in samples {
+        let class_dir = dir.join(&sample.label);
+        fs::create_dir_all(&class_dir)?;
+        fs::copy(&sample.path, class_dir.join(sample.flat_name()))?;
+    }
+    Ok(())
+}
+
+/// The image plus the metadata sidecars written next to it and its copies in the mirror
+/// trees of `store_path`, whatever format they were written in.
+pub fn with_sidecars(store_path: &Path, path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf()];
+    if let Some(stem) = path.file_stem() {
+        let stem = stem.to_string_lossy();
+        for suffix in ["json", "pose.toml", "txt", "keypoints.json"] {
+            let sidecar = path.with_file_name(format!("{}.{}", stem, suffix));
+            if sidecar.is_file() {
+                paths.push(sidecar);
+            }
+        }
+    }
+    if let Ok(relative) = path.strip_prefix(store_path) {
+        for tree in manifest::MIRROR_DIRS {
+            for ext in manifest::IMAGE_EXTENSIONS {
+                let copy = store_path.join(tree).join(relative).with_extension(ext);
+                if copy.is_file() {
+                    paths.push(copy);
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// The mirror tree `path` is in, if it is a copy rather than a capture.
+pub fn mirror_tree(store_path: &Path, path: &Path) -> Option<&'static str> {
+    let relative = path.strip_prefix(store_path).ok()?;
+    let top = relative.components().next()?.as_os_str();
+    manifest::MIRROR_DIRS.into_iter().find(|tree| top == *tree)
+}
+
+/// Deletes an image and its sidecars, or moves them below `quarantine`, keeping their
+/// path relative to `store_path`.
+pub fn remove(store_path: &Path, image: &Path, quarantine: Option<&Path>) -> Result<(), AppError> {
+    if let Some(db) = Db::existing(store_path)? {
+        db.remove(image)?;
+    }
+    for path in with_sidecars(store_path, image) {
+        match quarantine {
+            Some(quarantine) => {
+                let relative = path.strip_prefix(store_path).unwrap_or(&path);
+                let target = quarantine.join(relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if fs::rename(&path, &target).is_err() {
+                    fs::copy(&path, &target)?;
+                    fs::remove_file(&path)?;
+                }
+            }
+            None => fs::remove_file(&path)?,
+        }
+    }
+    Ok(())
+}
+
+/// SplitMix64, so splits are reproducible from a seed without extra dependencies.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Shuffles `samples` and cuts them into consecutive parts sized by `ratios`.
+/// With `stratify`, every class is cut separately so each part keeps the class balance.
+pub fn split(
+    mut samples: Vec<Sample>,
+    ratios: &[f64],
+    seed: u64,
+    stratify: bool,
+) -> Vec<Vec<Sample>> {
+    let mut rng = Rng::new(seed);
+    let mut parts: Vec<Vec<Sample>> = ratios.iter().map(|_| Vec::new()).collect();
+    let groups = if stratify {
+        classes(&samples)
+            .into_iter()
+            .map(|class| {
+                let (group, rest): (Vec<_>, Vec<_>) =
+                    samples.drain(..).partition(|sample| sample.label == class);
+                samples = rest;
+                group
+            })
+            .collect()
+    } else {
+        vec![samples]
+    };
+    let total: f64 = ratios.iter().sum();
+    for mut group in groups {
+        rng.shuffle(&mut group);
+        let len = group.len();
+        let mut start = 0;
+        let mut cumulative = 0.0;
+        for (part, ratio) in parts.iter_mut().zip(ratios) {
+            cumulative += ratio;
+            let end = ((cumulative / total) * len as f64).round() as usize;
+            part.extend(group[start..end.min(len)].iter().cloned());
+            start = end.min(len);
+        }
+    }
+    parts
+}
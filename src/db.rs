@@ -0,0 +1,164 @@
+//! `--db`: every capture recorded in `captures.sqlite` at the store path with its label,
+//! capture time, camera settings and difference hash. Once the database exists the dataset
+//! commands list images from it instead of walking the directories, and `dedupe` reuses the
+//! stored hashes. WAL mode lets several processes read and write it at the same time.
+
+use rusqlite::{Connection, params};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::{AppError, dataset, manifest, timestamp};
+
+pub const FILE: &str = "captures.sqlite";
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS captures (
+        path TEXT PRIMARY KEY,
+        label TEXT NOT NULL,
+        captured TEXT,
+        camera INTEGER,
+        settings TEXT,
+        hash INTEGER
+    );
+";
+
+/// How long a write waits for another process holding the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Db {
+    conn: Connection,
+    /// The store path as given, which listed paths are joined to.
+    root: PathBuf,
+    /// The canonical store path, for paths that were canonicalized by the caller.
+    base: PathBuf,
+}
+
+impl Db {
+    /// Opens the database of `store_path`, creating it if needed.
+    pub fn open(store_path: &Path) -> Result<Self, AppError> {
+        let conn = Connection::open(store_path.join(FILE))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Db {
+            conn,
+            root: store_path.to_path_buf(),
+            base: store_path.canonicalize()?,
+        })
+    }
+
+    /// The database of `store_path`, if one was created there.
+    pub fn existing(store_path: &Path) -> Result<Option<Self>, AppError> {
+        if !store_path.join(FILE).is_file() {
+            return Ok(None);
+        }
+        Self::open(store_path).map(Some)
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .or_else(|_| path.strip_prefix(&self.base))
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Records a capture; `settings` is the camera state as JSON.
+    pub fn insert(
+        &self,
+        path: &Path,
+        label: &str,
+        captured: SystemTime,
+        camera: Option<usize>,
+        settings: Option<String>,
+        hash: Option<u64>,
+    ) -> Result<(), AppError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO captures (path, label, captured, camera, settings, hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                self.relative(path),
+                label,
+                timestamp(captured),
+                camera.map(|camera| camera as i64),
+                settings,
+                // SQLite integers are signed; the bits are what matter.
+                hash.map(|hash| hash as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Moves the record of `from` to `to`, and to `label` if given, keeping its metadata.
+    pub fn rename(&self, from: &Path, to: &Path, label: Option<&str>) -> Result<(), AppError> {
+        self.conn.execute(
+            "UPDATE captures SET path = ?2, label = COALESCE(?3, label) WHERE path = ?1",
+            params![self.relative(from), self.relative(to), label],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<(), AppError> {
+        self.conn.execute(
+            "DELETE FROM captures WHERE path = ?1",
+            params![self.relative(path)],
+        )?;
+        Ok(())
+    }
+
+    /// Every recorded image, as a path below the store path.
+    pub fn images(&self) -> Result<Vec<PathBuf>, AppError> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT path FROM captures ORDER BY path")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut images = Vec::new();
+        for row in rows {
+            images.push(self.root.join(row?));
+        }
+        Ok(images)
+    }
+
+    /// Stored difference hashes by image path.
+    pub fn hashes(&self) -> Result<HashMap<PathBuf, u64>, AppError> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT path, hash FROM captures WHERE hash IS NOT NULL")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        let mut hashes = HashMap::new();
+        for row in rows {
+            let (path, hash) = row?;
+            hashes.insert(self.root.join(path), hash as u64);
+        }
+        Ok(hashes)
+    }
+}
+
+/// Brings the database of `store_path`, if any, in line with the files after they were
+/// added or removed by other commands: vanished images are dropped and new ones recorded
+/// without settings or hash. Renames go through `Db::rename` so the records keep theirs.
+pub fn sync(store_path: &Path) -> Result<(), AppError> {
+    let Some(db) = Db::existing(store_path)? else {
+        return Ok(());
+    };
+    let known: HashSet<PathBuf> = db.images()?.into_iter().collect();
+    let present = dataset::from_paths(store_path, manifest::images(store_path)?);
+    let present_paths: HashSet<&PathBuf> = present.iter().map(|sample| &sample.path).collect();
+    for path in &known {
+        if !present_paths.contains(path) {
+            db.remove(path)?;
+        }
+    }
+    for sample in &present {
+        if !known.contains(&sample.path) {
+            let captured = fs::metadata(&sample.path)?.modified()?;
+            db.insert(&sample.path, &sample.label, captured, None, None, None)?;
+        }
+    }
+    Ok(())
+}
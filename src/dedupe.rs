@@ -0,0 +1,167 @@
+use opencv::{
+    imgcodecs::{IMREAD_COLOR, imread},
+    prelude::*,
+};
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::AppError;
+use crate::dataset::{self, Sample};
+use crate::db::Db;
+use crate::transform::dhash;
+
+/// Keeps the first image of every near-duplicate cluster within a class and deletes the
+/// rest, or moves them below `quarantine` (preserving their store-relative path).
+pub fn run(
+    store_path: &Path,
+    distance: u32,
+    dry_run: bool,
+    quarantine: Option<&PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut classes: BTreeMap<String, Vec<Sample>> = BTreeMap::new();
+    for sample in dataset::samples(store_path)? {
+        classes
+            .entry(sample.label.clone())
+            .or_default()
+            .push(sample);
+    }
+    // Hashes recorded at capture time spare decoding those images.
+    let hashes = match Db::existing(store_path)? {
+        Some(db) => db.hashes()?,
+        None => HashMap::new(),
+    };
+    let mut removed = 0;
+    for (label, samples) in classes {
+        let mut kept: Vec<u64> = Vec::new();
+        for sample in samples {
+            let hash = match hashes.get(&sample.path) {
+                Some(hash) => *hash,
+                None => {
+                    let path = sample
+                        .path
+                        .to_str()
+                        .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+                    let image = imread(path, IMREAD_COLOR)?;
+                    if image.empty() {
+                        println!("skip unreadable {:?}", sample.path);
+                        continue;
+                    }
+                    dhash(&image)?
+                }
+            };
+            if !kept
+                .iter()
+                .any(|kept| (kept ^ hash).count_ones() <= distance)
+            {
+                kept.push(hash);
+                continue;
+            }
+            removed += 1;
+            if dry_run {
+                println!("duplicate in {}: {:?}", label, sample.path);
+                continue;
+            }
+            dataset::remove(store_path, &sample.path, quarantine.map(PathBuf::as_path))?;
+            println!(
+                "{} {:?}",
+                if quarantine.is_some() {
+                    "quarantine"
+                } else {
+                    "delete"
+                },
+                sample.path
+            );
+        }
+    }
+    println!(
+        "{} duplicates {}",
+        removed,
+        if dry_run { "found" } else { "removed" }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::time::SystemTime;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_dedupe_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A store whose database records `images` as `(relative path, label, hash)`, so
+    /// nothing has to be decoded.
+    fn store(name: &str, images: &[(&str, &str, u64)]) -> PathBuf {
+        let store = scratch(name);
+        let db = Db::open(&store).unwrap();
+        for (relative, label, hash) in images {
+            let path = store.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"png").unwrap();
+            db.insert(&path, label, SystemTime::now(), None, None, Some(*hash))
+                .unwrap();
+        }
+        store
+    }
+
+    const IMAGES: [(&str, &str, u64); 4] = [
+        ("cat/0.png", "cat", 0b0000),
+        ("cat/1.png", "cat", 0b0011),
+        ("cat/2.png", "cat", 0b1111_0000),
+        ("dog/0.png", "dog", 0b0000),
+    ];
+
+    #[test]
+    fn near_duplicates_within_a_class_are_deleted() {
+        let store = store("delete", &IMAGES);
+        fs::write(store.join("cat/1.json"), b"{}").unwrap();
+        run(&store, 2, false, None).unwrap();
+        // cat/1 is two bits from cat/0; dog/0 matches cat/0 but is in another class.
+        assert!(!store.join("cat/1.png").exists());
+        assert!(!store.join("cat/1.json").exists());
+        for kept in ["cat/0.png", "cat/2.png", "dog/0.png"] {
+            assert!(store.join(kept).is_file(), "{}", kept);
+        }
+        let images = Db::existing(&store).unwrap().unwrap().images().unwrap();
+        assert_eq!(images.len(), 3);
+        assert!(!images.contains(&store.join("cat/1.png")));
+    }
+
+    #[test]
+    fn a_smaller_distance_keeps_more_images() {
+        let store = store("distance", &IMAGES);
+        run(&store, 1, false, None).unwrap();
+        assert!(store.join("cat/1.png").is_file());
+    }
+
+    #[test]
+    fn dry_run_leaves_the_store_alone() {
+        let store = store("dry_run", &IMAGES);
+        run(&store, 8, true, None).unwrap();
+        for (relative, _, _) in IMAGES {
+            assert!(store.join(relative).is_file(), "{}", relative);
+        }
+    }
+
+    #[test]
+    fn quarantine_keeps_the_store_relative_path() {
+        let store = store("quarantine", &IMAGES);
+        let quarantine = scratch("quarantine_dir");
+        run(&store, 2, false, Some(&quarantine)).unwrap();
+        assert!(!store.join("cat/1.png").exists());
+        assert!(quarantine.join("cat/1.png").is_file());
+    }
+}
@@ -0,0 +1,82 @@
+//! Dropped and duplicate frames, failed reads and failed writes of a capture run: counted for
+//! the end-of-session summary and, with `--event-log events.jsonl`, logged one JSON line per
+//! event as they happen.
+
+use serde::Serialize;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use crate::timestamp;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// Frames read but replaced by newer ones before the capture loop took them.
+    Dropped,
+    /// Captures that saved the frames of the previous capture again, as no new one arrived.
+    Duplicate,
+    ReadFailed,
+    WriteFailed,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    event: Event,
+    count: usize,
+    detail: &'a str,
+}
+
+#[derive(Default)]
+pub struct Events {
+    counts: [AtomicUsize; 4],
+    log: Option<Mutex<File>>,
+}
+
+impl Events {
+    /// Counts events, appending them to `log` if given.
+    pub fn open(log: Option<&Path>) -> io::Result<Self> {
+        let log = match log {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+        Ok(Events {
+            log,
+            ..Default::default()
+        })
+    }
+
+    /// Records `count` occurrences of `event`; logging failures are reported, not returned.
+    pub fn record(&self, event: Event, count: usize, detail: &str) {
+        self.counts[event as usize].fetch_add(count, Ordering::Relaxed);
+        let Some(log) = &self.log else {
+            return;
+        };
+        let line = Line {
+            time: timestamp(SystemTime::now()),
+            event,
+            count,
+            detail,
+        };
+        let written = serde_json::to_string(&line)
+            .map_err(io::Error::from)
+            .and_then(|line| match log.lock() {
+                Ok(mut file) => writeln!(file, "{}", line),
+                Err(_) => Ok(()),
+            });
+        if let Err(e) = written {
+            println!("cannot log {:?} event: {}", event, e);
+        }
+    }
+
+    pub fn count(&self, event: Event) -> usize {
+        self.counts[event as usize].load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,226 @@
+use std::io;
+
+const TYPE_ASCII: u16 = 2;
+const TYPE_LONG: u16 = 4;
+const TYPE_UNDEFINED: u16 = 7;
+
+const TAG_IMAGE_DESCRIPTION: u16 = 0x010E;
+const TAG_SOFTWARE: u16 = 0x0131;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_USER_COMMENT: u16 = 0x9286;
+
+pub struct ExifFields {
+    /// `YYYY:MM:DD HH:MM:SS` in UTC.
+    pub date_time: String,
+    pub description: String,
+    pub comment: String,
+}
+
+struct Entry {
+    tag: u16,
+    typ: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+fn ascii(tag: u16, text: &str) -> Entry {
+    let mut data = text.as_bytes().to_vec();
+    data.push(0);
+    Entry {
+        tag,
+        typ: TYPE_ASCII,
+        count: data.len() as u32,
+        data,
+    }
+}
+
+/// Serializes one big-endian IFD that starts at `offset` within the TIFF block.
+fn ifd(entries: &[Entry], offset: u32) -> Vec<u8> {
+    let table_len = 2 + entries.len() as u32 * 12 + 4;
+    let mut table = Vec::with_capacity(table_len as usize);
+    let mut data = Vec::new();
+    table.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for entry in entries {
+        table.extend_from_slice(&entry.tag.to_be_bytes());
+        table.extend_from_slice(&entry.typ.to_be_bytes());
+        table.extend_from_slice(&entry.count.to_be_bytes());
+        if entry.data.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..entry.data.len()].copy_from_slice(&entry.data);
+            table.extend_from_slice(&inline);
+        } else {
+            let at = offset + table_len + data.len() as u32;
+            table.extend_from_slice(&at.to_be_bytes());
+            data.extend_from_slice(&entry.data);
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+    }
+    table.extend_from_slice(&0u32.to_be_bytes());
+    table.extend_from_slice(&data);
+    table
+}
+
+fn app1(fields: &ExifFields) -> Vec<u8> {
+    let mut comment = b"ASCII\0\0\0".to_vec();
+    comment.extend_from_slice(fields.comment.as_bytes());
+    let exif_entries = [
+        ascii(TAG_DATE_TIME_ORIGINAL, &fields.date_time),
+        ascii(TAG_OFFSET_TIME_ORIGINAL, "+00:00"),
+        Entry {
+            tag: TAG_USER_COMMENT,
+            typ: TYPE_UNDEFINED,
+            count: comment.len() as u32,
+            data: comment,
+        },
+    ];
+    // IFD0 is laid out first so the Exif IFD offset is known once it is serialized.
+    let mut ifd0_entries = vec![
+        ascii(TAG_IMAGE_DESCRIPTION, &fields.description),
+        ascii(TAG_SOFTWARE, "img_collector"),
+        ascii(TAG_DATE_TIME, &fields.date_time),
+        Entry {
+            tag: TAG_EXIF_IFD,
+            typ: TYPE_LONG,
+            count: 1,
+            data: vec![0; 4],
+        },
+    ];
+    let ifd0_len = ifd(&ifd0_entries, 8).len() as u32;
+    let exif_offset = 8 + ifd0_len;
+    ifd0_entries[3].data = exif_offset.to_be_bytes().to_vec();
+
+    let mut tiff = b"MM\0\x2A\0\0\0\x08".to_vec();
+    tiff.extend(ifd(&ifd0_entries, 8));
+    tiff.extend(ifd(&exif_entries, exif_offset));
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend(tiff);
+    segment
+}
+
+/// `jpeg` with an EXIF APP1 segment inserted after any JFIF APP0 header.
+pub fn embed(jpeg: &[u8], fields: &ExifFields) -> io::Result<Vec<u8>> {
+    if jpeg.len() < 4 || jpeg[..2] != [0xFF, 0xD8] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "encoded image is not a JPEG",
+        ));
+    }
+    let mut insert_at = 2;
+    if jpeg[2..4] == [0xFF, 0xE0] && jpeg.len() >= 6 {
+        insert_at = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+    }
+    let insert_at = insert_at.min(jpeg.len());
+    let mut out = Vec::with_capacity(jpeg.len() + 256);
+    out.extend_from_slice(&jpeg[..insert_at]);
+    out.extend(app1(fields));
+    out.extend_from_slice(&jpeg[insert_at..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> ExifFields {
+        ExifFields {
+            date_time: "2024:05:06 07:08:09".to_string(),
+            description: "cat".to_string(),
+            comment: "session=morning".to_string(),
+        }
+    }
+
+    fn be16(buf: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([buf[at], buf[at + 1]])
+    }
+
+    fn be32(buf: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    /// The tags of the IFD at `offset` in `tiff`, with their values resolved.
+    fn read_ifd(tiff: &[u8], offset: usize) -> Vec<(u16, u16, Vec<u8>)> {
+        let count = be16(tiff, offset) as usize;
+        let entries = (0..count)
+            .map(|i| {
+                let at = offset + 2 + i * 12;
+                let typ = be16(tiff, at + 2);
+                let unit = if typ == TYPE_LONG { 4 } else { 1 };
+                let len = be32(tiff, at + 4) as usize * unit;
+                let data = if len <= 4 {
+                    tiff[at + 8..at + 8 + len].to_vec()
+                } else {
+                    let start = be32(tiff, at + 8) as usize;
+                    tiff[start..start + len].to_vec()
+                };
+                (be16(tiff, at), typ, data)
+            })
+            .collect();
+        assert_eq!(
+            be32(tiff, offset + 2 + count * 12),
+            0,
+            "only one IFD is chained"
+        );
+        entries
+    }
+
+    fn value(entries: &[(u16, u16, Vec<u8>)], tag: u16) -> &[u8] {
+        &entries.iter().find(|entry| entry.0 == tag).unwrap().2
+    }
+
+    #[test]
+    fn embed_rejects_data_that_is_not_a_jpeg() {
+        assert!(embed(b"\x89PNG\r\n", &fields()).is_err());
+        assert!(embed(&[0xFF, 0xD8], &fields()).is_err());
+    }
+
+    #[test]
+    fn embed_goes_after_the_jfif_header() {
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0xAA, 0xBB, 0xFF, 0xD9];
+        let out = embed(&jpeg, &fields()).unwrap();
+        assert_eq!(out[..8], jpeg[..8]);
+        assert_eq!(out[8..10], [0xFF, 0xE1]);
+        assert_eq!(out[out.len() - 2..], [0xFF, 0xD9]);
+        // Without APP0 the segment follows SOI directly.
+        let out = embed(&[0xFF, 0xD8, 0xFF, 0xD9], &fields()).unwrap();
+        assert_eq!(out[2..4], [0xFF, 0xE1]);
+    }
+
+    #[test]
+    fn app1_holds_the_fields_in_both_ifds() {
+        let segment = app1(&fields());
+        assert_eq!(segment[..2], [0xFF, 0xE1]);
+        assert_eq!(be16(&segment, 2) as usize, segment.len() - 2);
+        assert_eq!(&segment[4..10], b"Exif\0\0");
+        let tiff = &segment[10..];
+        assert_eq!(&tiff[..8], b"MM\0\x2A\0\0\0\x08");
+
+        let ifd0 = read_ifd(tiff, 8);
+        assert_eq!(value(&ifd0, TAG_IMAGE_DESCRIPTION), b"cat\0");
+        assert_eq!(value(&ifd0, TAG_SOFTWARE), b"img_collector\0");
+        assert_eq!(value(&ifd0, TAG_DATE_TIME), b"2024:05:06 07:08:09\0");
+        let exif_offset = be32(value(&ifd0, TAG_EXIF_IFD), 0) as usize;
+        // Values past the tables start on word boundaries.
+        assert_eq!(exif_offset % 2, 0);
+
+        let exif = read_ifd(tiff, exif_offset);
+        assert_eq!(
+            value(&exif, TAG_DATE_TIME_ORIGINAL),
+            b"2024:05:06 07:08:09\0"
+        );
+        assert_eq!(value(&exif, TAG_OFFSET_TIME_ORIGINAL), b"+00:00\0");
+        let comment = exif
+            .iter()
+            .find(|entry| entry.0 == TAG_USER_COMMENT)
+            .unwrap();
+        assert_eq!(comment.1, TYPE_UNDEFINED);
+        assert_eq!(comment.2, b"ASCII\0\0\0session=morning");
+    }
+}
@@ -0,0 +1,336 @@
+use clap::Subcommand;
+use opencv::{
+    imgcodecs::{IMREAD_UNCHANGED, imread},
+    prelude::*,
+};
+use serde::Serialize;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::annotate;
+use crate::dataset::{self, Sample, copy_samples};
+use crate::tfrecord::{self, Feature};
+use crate::{AppError, parquet};
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ExportFormat {
+    Yolo {
+        #[arg(long, default_value = "yolo")]
+        output: PathBuf,
+        #[arg(long, default_value = "0.2")]
+        val_ratio: f64,
+        #[arg(long, default_value = "0")]
+        seed: u64,
+        #[arg(long)]
+        detect: bool,
+    },
+    Coco {
+        #[arg(long, default_value = "coco.json")]
+        output: PathBuf,
+    },
+    Hf {
+        #[arg(long, default_value = "hf")]
+        output: PathBuf,
+        #[arg(long)]
+        parquet: bool,
+    },
+    Tfrecord {
+        #[arg(long, default_value = "tfrecord")]
+        output: PathBuf,
+        #[arg(long, default_value = "1000")]
+        shard_size: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct CocoImage {
+    id: usize,
+    file_name: String,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Serialize)]
+struct CocoCategory {
+    id: usize,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct CocoAnnotation {
+    id: usize,
+    image_id: usize,
+    category_id: usize,
+    bbox: [f64; 4],
+    area: f64,
+    iscrowd: u8,
+}
+
+#[derive(Serialize)]
+struct Coco {
+    images: Vec<CocoImage>,
+    categories: Vec<CocoCategory>,
+    annotations: Vec<CocoAnnotation>,
+}
+
+fn yaml_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Copies `samples` to `dir/images/<split>/` with their box labels in `dir/labels/<split>/`;
+/// images without boxes get an empty label file, which YOLO treats as background.
+fn copy_detect_samples(samples: &[Sample], dir: &Path, split: &str) -> Result<(), AppError> {
+    let images = dir.join("images").join(split);
+    let labels = dir.join("labels").join(split);
+    fs::create_dir_all(&images)?;
+    fs::create_dir_all(&labels)?;
+    for sample in samples {
+        let name = format!("{}_{}", sample.label, sample.flat_name());
+        fs::copy(&sample.path, images.join(&name))?;
+        let label = labels.join(Path::new(&name).with_extension("txt"));
+        let source = annotate::label_path(&sample.path);
+        if source.is_file() {
+            fs::copy(source, label)?;
+        } else {
+            fs::write(label, "")?;
+        }
+    }
+    Ok(())
+}
+
+/// Ultralytics classification layout: `{train,val}/<class>/<image>` plus `data.yaml`.
+/// With `detect`, the detection layout with the annotated boxes instead.
+fn export_yolo(
+    store_path: &Path,
+    output: &Path,
+    val_ratio: f64,
+    seed: u64,
+    detect: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !(0.0..1.0).contains(&val_ratio) {
+        return Err(
+            AppError::DatasetError(format!("val ratio {} is not in [0, 1)", val_ratio)).into(),
+        );
+    }
+    fs::create_dir_all(output)?;
+    let samples = dataset::samples(store_path)?;
+    let classes = if detect {
+        annotate::classes(store_path)?.ok_or(AppError::DatasetError(
+            "no box annotations; run `annotate` first".into(),
+        ))?
+    } else {
+        dataset::classes(&samples)
+    };
+    let parts = dataset::split(samples, &[1.0 - val_ratio, val_ratio], seed, true);
+    let (train, val) = if detect {
+        copy_detect_samples(&parts[0], output, "train")?;
+        copy_detect_samples(&parts[1], output, "val")?;
+        ("images/train", "images/val")
+    } else {
+        copy_samples(&parts[0], &output.join("train"))?;
+        copy_samples(&parts[1], &output.join("val"))?;
+        ("train", "val")
+    };
+
+    let output_abs = output.canonicalize()?;
+    let mut yaml = format!(
+        "path: {}\ntrain: {}\nval: {}\nnc: {}\nnames:\n",
+        yaml_string(&output_abs.to_string_lossy()),
+        train,
+        val,
+        classes.len()
+    );
+    for (id, name) in classes.iter().enumerate() {
+        yaml.push_str(&format!("  {}: {}\n", id, yaml_string(name)));
+    }
+    fs::write(output.join("data.yaml"), yaml)?;
+    println!(
+        "export {} train / {} val images to {:?}",
+        parts[0].len(),
+        parts[1].len(),
+        output
+    );
+    Ok(())
+}
+
+/// COCO JSON whose `file_name`s are relative to the store path. Categories are the box
+/// classes once images are annotated, the class directories otherwise.
+fn export_coco(store_path: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let samples = dataset::samples(store_path)?;
+    let classes = match annotate::classes(store_path)? {
+        Some(classes) => classes,
+        None => dataset::classes(&samples),
+    };
+    let mut images = Vec::with_capacity(samples.len());
+    let mut annotations = Vec::new();
+    for (id, sample) in samples.iter().enumerate() {
+        let path = sample
+            .path
+            .to_str()
+            .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+        let image = imread(path, IMREAD_UNCHANGED)?;
+        images.push(CocoImage {
+            id: id + 1,
+            file_name: sample
+                .path
+                .strip_prefix(store_path)
+                .unwrap_or(&sample.path)
+                .to_string_lossy()
+                .into_owned(),
+            width: image.cols(),
+            height: image.rows(),
+        });
+        for b in annotate::load(&sample.path)? {
+            let bbox = b.pixels(image.cols(), image.rows());
+            annotations.push(CocoAnnotation {
+                id: annotations.len() + 1,
+                image_id: id + 1,
+                category_id: b.class + 1,
+                bbox,
+                area: bbox[2] * bbox[3],
+                iscrowd: 0,
+            });
+        }
+    }
+    let categories = classes
+        .iter()
+        .enumerate()
+        .map(|(id, name)| CocoCategory {
+            id: id + 1,
+            name: name.clone(),
+        })
+        .collect();
+    let coco = Coco {
+        images,
+        categories,
+        annotations,
+    };
+    fs::write(output, serde_json::to_string_pretty(&coco)?)?;
+    println!(
+        "export {} images with {} boxes to {:?}",
+        coco.images.len(),
+        coco.annotations.len(),
+        output
+    );
+    Ok(())
+}
+
+const PARQUET_ROW_GROUP: usize = 64;
+
+/// Hugging Face features so `datasets` decodes the `image` column as images.
+const HF_FEATURES: &str = r#"{"info":{"features":{"image":{"_type":"Image"},"label":{"dtype":"string","_type":"Value"}}}}"#;
+
+/// `imagefolder` layout (`train/<label>/<image>`), or one Parquet file with embedded image bytes.
+fn export_hf(store_path: &Path, output: &Path, parquet: bool) -> Result<(), Box<dyn Error>> {
+    let samples = dataset::samples(store_path)?;
+    if !parquet {
+        copy_samples(&samples, &output.join("train"))?;
+        println!("export {} images to {:?}", samples.len(), output);
+        return Ok(());
+    }
+    let dir = output.join("data");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("train-00000-of-00001.parquet");
+    let columns = vec![
+        parquet::Column {
+            path: "image.bytes",
+            utf8: false,
+        },
+        parquet::Column {
+            path: "image.path",
+            utf8: true,
+        },
+        parquet::Column {
+            path: "label",
+            utf8: true,
+        },
+    ];
+    let mut writer = parquet::Writer::create(&path, vec!["image"], columns)?;
+    for chunk in samples.chunks(PARQUET_ROW_GROUP) {
+        let mut rows = Vec::with_capacity(chunk.len());
+        for sample in chunk {
+            rows.push(vec![
+                fs::read(&sample.path)?,
+                format!("{}/{}", sample.label, sample.flat_name()).into_bytes(),
+                sample.label.clone().into_bytes(),
+            ]);
+        }
+        writer.write_rows(&rows)?;
+    }
+    writer.finish(&[("huggingface", HF_FEATURES.to_string())])?;
+    println!("export {} images to {:?}", samples.len(), path);
+    Ok(())
+}
+
+/// `train.tfrecord-NNNNN-of-MMMMM` shards of `tf.train.Example`s in the usual image feature layout.
+fn export_tfrecord(
+    store_path: &Path,
+    output: &Path,
+    shard_size: usize,
+) -> Result<(), Box<dyn Error>> {
+    let samples = dataset::samples(store_path)?;
+    let classes = dataset::classes(&samples);
+    fs::create_dir_all(output)?;
+    let shard_size = shard_size.max(1);
+    let shards = samples.len().div_ceil(shard_size).max(1);
+    for (shard, chunk) in samples.chunks(shard_size).enumerate() {
+        let path = output.join(format!("train.tfrecord-{:05}-of-{:05}", shard, shards));
+        let mut out = BufWriter::new(File::create(&path)?);
+        for sample in chunk {
+            let path_str = sample
+                .path
+                .to_str()
+                .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+            let image = imread(path_str, IMREAD_UNCHANGED)?;
+            let format = sample
+                .path
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let class_id = classes.iter().position(|c| *c == sample.label).unwrap_or(0);
+            let record = tfrecord::example(&[
+                ("image/encoded", Feature::Bytes(fs::read(&sample.path)?)),
+                ("image/format", Feature::Bytes(format.into_bytes())),
+                (
+                    "image/filename",
+                    Feature::Bytes(format!("{}/{}", sample.label, sample.flat_name()).into_bytes()),
+                ),
+                ("image/height", Feature::Int64(image.rows() as i64)),
+                ("image/width", Feature::Int64(image.cols() as i64)),
+                ("image/class/label", Feature::Int64(class_id as i64)),
+                (
+                    "image/class/text",
+                    Feature::Bytes(sample.label.clone().into_bytes()),
+                ),
+            ]);
+            tfrecord::write_record(&mut out, &record)?;
+        }
+        out.flush()?;
+    }
+    println!(
+        "export {} images in {} shards to {:?}",
+        samples.len(),
+        shards,
+        output
+    );
+    Ok(())
+}
+
+pub fn run(store_path: &Path, format: &ExportFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Yolo {
+            output,
+            val_ratio,
+            seed,
+            detect,
+        } => export_yolo(store_path, output, *val_ratio, *seed, *detect),
+        ExportFormat::Coco { output } => export_coco(store_path, output),
+        ExportFormat::Hf { output, parquet } => export_hf(store_path, output, *parquet),
+        ExportFormat::Tfrecord { output, shard_size } => {
+            export_tfrecord(store_path, output, *shard_size)
+        }
+    }
+}
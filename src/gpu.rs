@@ -0,0 +1,97 @@
+//! `--gpu`: saved frames go through `UMat` for undistortion, cropping, rotation, resizing and
+//! color conversion, which OpenCV then runs through OpenCL when a device is available. Without
+//! one the same calls run on the CPU. The preview stays on the CPU: its conversion and
+//! mirroring are cheap, and its overlays are drawn on a `Mat`, so a round trip through the
+//! device on every frame would cost more than it saves.
+
+use opencv::core::{
+    Mat, Rect, Size, ToInputArray, ToInputOutputArray, ToOutputArray, UMat, have_opencl,
+    set_use_opencl,
+};
+use opencv::prelude::*;
+
+/// What the frame transforms need from a `Mat` or a `UMat`.
+pub trait Image: ToInputArray + ToOutputArray + ToInputOutputArray + Sized {
+    fn blank() -> Self;
+    fn frame_size(&self) -> opencv::Result<Size>;
+    fn channel_count(&self) -> i32;
+    fn bit_depth(&self) -> i32;
+    fn copy(&self) -> opencv::Result<Self>;
+    /// An owned copy of the `rect` region.
+    fn crop(&self, rect: Rect) -> opencv::Result<Self>;
+}
+
+impl Image for Mat {
+    fn blank() -> Self {
+        Mat::default()
+    }
+
+    fn frame_size(&self) -> opencv::Result<Size> {
+        self.size()
+    }
+
+    fn channel_count(&self) -> i32 {
+        self.channels()
+    }
+
+    fn bit_depth(&self) -> i32 {
+        self.depth()
+    }
+
+    fn copy(&self) -> opencv::Result<Self> {
+        self.try_clone()
+    }
+
+    fn crop(&self, rect: Rect) -> opencv::Result<Self> {
+        Mat::roi(self, rect)?.try_clone()
+    }
+}
+
+impl Image for UMat {
+    fn blank() -> Self {
+        UMat::new_def()
+    }
+
+    fn frame_size(&self) -> opencv::Result<Size> {
+        self.size()
+    }
+
+    fn channel_count(&self) -> i32 {
+        self.channels()
+    }
+
+    fn bit_depth(&self) -> i32 {
+        self.depth()
+    }
+
+    fn copy(&self) -> opencv::Result<Self> {
+        self.try_clone()
+    }
+
+    fn crop(&self, rect: Rect) -> opencv::Result<Self> {
+        UMat::roi(self, rect)?.try_clone()
+    }
+}
+
+/// Turns on OpenCL, reporting whether a device was found.
+pub fn enable() -> opencv::Result<()> {
+    set_use_opencl(true)?;
+    if have_opencl()? {
+        println!("processing frames with OpenCL");
+    } else {
+        println!("no OpenCL device, processing frames on the CPU");
+    }
+    Ok(())
+}
+
+pub fn upload(frame: &Mat) -> opencv::Result<UMat> {
+    let mut image = UMat::new_def();
+    frame.copy_to(&mut image)?;
+    Ok(image)
+}
+
+pub fn download(image: &UMat) -> opencv::Result<Mat> {
+    let mut frame = Mat::default();
+    image.copy_to(&mut frame)?;
+    Ok(frame)
+}
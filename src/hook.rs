@@ -0,0 +1,90 @@
+//! `--on-save "<cmd> {path} {label}"`: a command run through the shell after each capture is
+//! written, e.g. to rsync it or post a notification. Commands run on a few worker threads,
+//! `--on-save-jobs` at a time, so slow ones queue up instead of holding up the writer.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+pub struct Hooks {
+    template: String,
+    commands: Option<Sender<String>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// `value` as one shell word.
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+impl Hooks {
+    pub fn spawn(template: &str, jobs: usize) -> Self {
+        let (commands, queue) = mpsc::channel::<String>();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..jobs.max(1))
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    loop {
+                        let command = match queue.lock() {
+                            Ok(queue) => queue.recv(),
+                            Err(_) => break,
+                        };
+                        let Ok(command) = command else {
+                            break;
+                        };
+                        match shell(&command).stdin(Stdio::null()).status() {
+                            Ok(status) if status.success() => {}
+                            Ok(status) => println!("on-save command {} ({})", status, command),
+                            Err(e) => println!("cannot run on-save command {}: {}", command, e),
+                        }
+                    }
+                })
+            })
+            .collect();
+        Hooks {
+            template: template.to_string(),
+            commands: Some(commands),
+            workers,
+        }
+    }
+
+    /// Queues the command for a capture of `label` written to `path`.
+    pub fn saved(&self, path: &Path, label: &str) {
+        let command = self
+            .template
+            .replace("{path}", &quote(&path.to_string_lossy()))
+            .replace("{label}", &quote(label));
+        if let Some(commands) = &self.commands {
+            let _ = commands.send(command);
+        }
+    }
+}
+
+impl Drop for Hooks {
+    /// Waits for the queued commands.
+    fn drop(&mut self) {
+        self.commands.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
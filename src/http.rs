@@ -0,0 +1,364 @@
+//! `--listen ADDR`: a small HTTP control API for headless rigs. The server answers on its own
+//! threads and hands each call to the capture loop, which replies with JSON:
+//!
+//! - `POST /capture?label=NAME` captures to `NAME`, or to `--label` without one; 409 if the
+//!   capture was rejected by `--max-per-class`, `--min-sharpness`, `--max-clipping` or
+//!   deduplication, and 400 for hidden or reserved names
+//! - `GET /camera` lists the camera properties, `POST /camera?exposure=-6&gain=10` sets them
+//! - `GET /counts` is the number of captures per class
+//! - `POST /stop` ends the session
+//! - `GET /stream` is the preview as an MJPEG stream, for a browser or phone as a viewfinder
+//!
+//! Parameters may also come as a form body. There is no authentication, so listen on a
+//! trusted network only. The preview is only encoded while someone is watching the stream.
+
+use opencv::core::{Mat, Vector};
+use opencv::imgcodecs::{IMWRITE_JPEG_QUALITY, imencode};
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::AppError;
+
+/// How long a client may take to send its request, and a call may wait for the capture loop.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest request body read.
+const MAX_BODY: usize = 64 * 1024;
+/// JPEG quality of the streamed preview.
+const STREAM_QUALITY: i32 = 80;
+/// Separates the frames of the MJPEG stream.
+const BOUNDARY: &str = "frame";
+
+pub enum Request {
+    Capture { label: Option<String> },
+    Camera,
+    SetCamera { props: BTreeMap<String, String> },
+    Counts,
+    Stop,
+}
+
+/// A request waiting for the capture loop's answer.
+pub struct Call {
+    pub request: Request,
+    reply: Sender<(u16, String)>,
+}
+
+impl Call {
+    /// Answers with `status` and the JSON `body`.
+    pub fn reply(self, status: u16, body: String) {
+        let _ = self.reply.send((status, body));
+    }
+}
+
+/// The newest preview as a JPEG, for the stream threads.
+#[derive(Default)]
+struct Preview {
+    /// Counts published previews, and the newest one.
+    frame: Mutex<(u64, Vec<u8>)>,
+    published: Condvar,
+    viewers: AtomicUsize,
+}
+
+pub struct Server {
+    calls: Receiver<Call>,
+    preview: Arc<Preview>,
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn params(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (decode(key), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// The JSON body of an error reply.
+pub fn error(message: &str) -> String {
+    serde_json::to_string(&serde_json::json!({ "error": message })).unwrap_or_default()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Method, path and parameters of the request on `stream`.
+fn read_request(stream: &TcpStream) -> io::Result<(String, String, BTreeMap<String, String>)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad request line",
+        ));
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut body = vec![0; length.min(MAX_BODY)];
+    reader.read_exact(&mut body)?;
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let mut found = params(query);
+    found.extend(params(&String::from_utf8_lossy(&body)));
+    Ok((method, path.to_string(), found))
+}
+
+fn route(method: &str, path: &str, mut params: BTreeMap<String, String>) -> Result<Request, u16> {
+    match (method, path.trim_end_matches('/')) {
+        ("POST", "/capture") => Ok(Request::Capture {
+            label: params.remove("label").filter(|label| !label.is_empty()),
+        }),
+        ("GET", "/camera") => Ok(Request::Camera),
+        ("POST", "/camera") => Ok(Request::SetCamera { props: params }),
+        ("GET", "/counts") => Ok(Request::Counts),
+        ("POST", "/stop") => Ok(Request::Stop),
+        (_, "/capture" | "/camera" | "/counts" | "/stop" | "/stream") => Err(405),
+        _ => Err(404),
+    }
+}
+
+/// Sends every new preview to `stream` until the client goes away.
+fn stream_preview(mut stream: TcpStream, preview: &Preview) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        BOUNDARY
+    )?;
+    let mut seen = 0;
+    loop {
+        let jpeg = {
+            let frame = preview
+                .frame
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let (frame, _) = preview
+                .published
+                .wait_timeout_while(frame, TIMEOUT, |(seq, _)| *seq == seen)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if frame.0 == seen {
+                // Nothing new, e.g. while paused; the write below notices a closed client.
+                Vec::new()
+            } else {
+                seen = frame.0;
+                frame.1.clone()
+            }
+        };
+        if jpeg.is_empty() {
+            stream.write_all(b"\r\n")?;
+            continue;
+        }
+        write!(
+            stream,
+            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            BOUNDARY,
+            jpeg.len()
+        )?;
+        stream.write_all(&jpeg)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+    }
+}
+
+fn serve(mut stream: TcpStream, calls: &Sender<Call>, preview: &Preview) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let (status, body) = match read_request(&stream) {
+        Ok((method, path, _)) if method == "GET" && path.trim_end_matches('/') == "/stream" => {
+            preview.viewers.fetch_add(1, Ordering::SeqCst);
+            let streamed = stream_preview(stream, preview);
+            preview.viewers.fetch_sub(1, Ordering::SeqCst);
+            // A viewer closing the page is how a stream ends.
+            return match streamed {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => Ok(()),
+                streamed => streamed,
+            };
+        }
+        Ok((method, path, params)) => match route(&method, &path, params) {
+            Ok(request) => {
+                let (reply, answer) = mpsc::channel();
+                match calls.send(Call { request, reply }) {
+                    Ok(()) => answer
+                        .recv_timeout(TIMEOUT)
+                        .unwrap_or((503, error("the capture loop did not answer"))),
+                    Err(_) => (503, error("the session has ended")),
+                }
+            }
+            Err(status) => (status, error(reason(status))),
+        },
+        Err(e) => (400, error(&e.to_string())),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+impl Server {
+    pub fn listen(address: &str) -> Result<Self, AppError> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        println!("control API on http://{}, preview at /stream", address);
+        let (sender, calls) = mpsc::channel();
+        let preview = Arc::new(Preview::default());
+        let thread_preview = Arc::clone(&preview);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let (sender, preview) = (sender.clone(), Arc::clone(&thread_preview));
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, &sender, &preview) {
+                        println!("control API: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(Server { calls, preview })
+    }
+
+    /// Publishes `preview` to the stream, if anyone is watching.
+    pub fn show(&self, preview: &Mat) -> Result<(), AppError> {
+        if self.preview.viewers.load(Ordering::SeqCst) == 0 {
+            return Ok(());
+        }
+        let mut jpeg = Vector::<u8>::new();
+        let params = Vector::from_slice(&[IMWRITE_JPEG_QUALITY, STREAM_QUALITY]);
+        imencode(".jpg", preview, &mut jpeg, &params)?;
+        if let Ok(mut frame) = self.preview.frame.lock() {
+            *frame = (frame.0 + 1, jpeg.as_slice().to_vec());
+        }
+        self.preview.published.notify_all();
+        Ok(())
+    }
+
+    /// Calls received since the last time.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_undoes_form_encoding() {
+        assert_eq!(decode("red+fox%2Fcub"), "red fox/cub");
+        assert_eq!(decode("%C3%A9t%C3%A9"), "été");
+        // Escapes that are cut short or not hex stay as they are.
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%4"), "%4");
+        assert_eq!(decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn params_split_pairs_and_decode_both_sides() {
+        let found = params("label=red+fox&&flag&gain%20db=%3D10");
+        let expected: BTreeMap<String, String> =
+            [("label", "red fox"), ("flag", ""), ("gain db", "=10")]
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+        assert_eq!(found, expected);
+        assert!(params("").is_empty());
+    }
+
+    #[test]
+    fn route_matches_method_and_path() {
+        let label = |value: &str| BTreeMap::from([("label".to_string(), value.to_string())]);
+        assert!(matches!(
+            route("POST", "/capture/", label("cat")),
+            Ok(Request::Capture { label: Some(label) }) if label == "cat"
+        ));
+        assert!(matches!(
+            route("POST", "/capture", label("")),
+            Ok(Request::Capture { label: None })
+        ));
+        assert!(matches!(
+            route("POST", "/camera", label("x")),
+            Ok(Request::SetCamera { props }) if props.len() == 1
+        ));
+        assert!(matches!(
+            route("GET", "/capture", BTreeMap::new()),
+            Err(405)
+        ));
+        assert!(matches!(
+            route("GET", "/nowhere", BTreeMap::new()),
+            Err(404)
+        ));
+    }
+
+    #[test]
+    fn read_request_merges_query_and_form_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"POST /camera?exposure=-6 HTTP/1.1\r\nContent-Length: 7\r\n\r\ngain=10")
+            .unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (method, path, found) = read_request(&server).unwrap();
+        assert_eq!((method.as_str(), path.as_str()), ("POST", "/camera"));
+        assert_eq!(found["exposure"], "-6");
+        assert_eq!(found["gain"], "10");
+    }
+}
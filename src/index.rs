@@ -0,0 +1,390 @@
+//! Capture indices: the `{index}` in names from `--name-template`, which companions such as
+//! `<name>.json`, `<name>.pose.toml` and the `<name>_f<focus>` shots of a bracket share.
+//! Renumbering renders the new index into the same template, so padding and the other parts of
+//! a name are kept.
+//!
+//! The next index of every capture directory is kept in `.index.json` at the store path, so
+//! startup doesn't scan the dataset, and tools that renumber files remove it. Recorders reserve
+//! ranges of indices under an advisory lock on `.lock`, re-reading and saving the state file
+//! only once per range, so recorders sharing a store never hand out the same index twice. The
+//! state is saved before any index of a range is used, so a crash can leave a gap but never a
+//! reused index; a clean exit hands the unused rest of a range back.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::{self, Sample};
+use crate::db::Db;
+use crate::naming::{Fields, NameTemplate};
+use crate::sink::write_atomic;
+use crate::{AppError, db, manifest, session};
+
+pub const STATE_FILE: &str = ".index.json";
+pub const LOCK_FILE: &str = ".lock";
+
+/// The saved next indices by capture directory, or `None` if there is no usable state file.
+pub fn load_state(store_path: &Path) -> Result<Option<HashMap<String, i32>>, AppError> {
+    let path = store_path.join(STATE_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let saved: HashMap<String, i32> = match serde_json::from_str(&fs::read_to_string(&path)?) {
+        Ok(saved) => saved,
+        Err(e) => {
+            println!("ignore {:?}: {}", path, e);
+            return Ok(None);
+        }
+    };
+    let base = store_path.canonicalize()?;
+    Ok(Some(
+        saved
+            .into_iter()
+            .map(|(dir, next)| (base.join(dir).to_string_lossy().into_owned(), next))
+            .collect(),
+    ))
+}
+
+/// Saves the next indices with directories relative to `store_path`.
+pub fn save_state(store_path: &Path, indices: &HashMap<String, i32>) -> Result<(), AppError> {
+    let base = store_path.canonicalize()?;
+    let relative: BTreeMap<String, i32> = indices
+        .iter()
+        .map(|(dir, next)| {
+            let dir = Path::new(dir);
+            let dir = dir.strip_prefix(&base).unwrap_or(dir);
+            (dir.to_string_lossy().into_owned(), *next)
+        })
+        .collect();
+    write_atomic(
+        &store_path.join(STATE_FILE),
+        serde_json::to_string_pretty(&relative)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Exclusive hold on a store's indices while they are read, changed and saved.
+pub struct StoreLock(File);
+
+impl StoreLock {
+    pub fn open(store_path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(store_path.join(LOCK_FILE))?;
+        Ok(StoreLock(file))
+    }
+
+    /// Under the lock, raises `indices` to what other recorders have saved, applies `f` and
+    /// saves the result.
+    pub fn update<T>(
+        &self,
+        store_path: &Path,
+        indices: &mut HashMap<String, i32>,
+        f: impl FnOnce(&mut HashMap<String, i32>) -> T,
+    ) -> Result<T, AppError> {
+        self.0.lock()?;
+        let result = refresh(store_path, indices).and_then(|()| {
+            let value = f(indices);
+            save_state(store_path, indices)?;
+            Ok(value)
+        });
+        self.0.unlock()?;
+        result
+    }
+}
+
+fn refresh(store_path: &Path, indices: &mut HashMap<String, i32>) -> Result<(), AppError> {
+    for (dir, next) in load_state(store_path)?.unwrap_or_default() {
+        let current = indices.entry(dir).or_insert(0);
+        *current = (*current).max(next);
+    }
+    Ok(())
+}
+
+/// Removes the state files of `store_path` and its sessions after files were renumbered or
+/// added behind the recorder's back; the next capture run rebuilds them from the files.
+pub fn invalidate(store_path: &Path) -> io::Result<()> {
+    let mut paths = vec![store_path.join(STATE_FILE)];
+    if store_path.is_dir() {
+        for entry in fs::read_dir(store_path)? {
+            paths.push(entry?.path().join(STATE_FILE));
+        }
+    }
+    for path in paths {
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Index of a capture file name from `template`, with the rest of the name after it.
+pub fn split_name<'n>(name: &'n str, template: &NameTemplate) -> Option<(u64, &'n str)> {
+    match template.parse(name)? {
+        (Some(index), rest) => Some((index, rest)),
+        (None, _) => None,
+    }
+}
+
+/// Renumbers the indexed files in `dir` to `0..n`, keeping their order and companions together,
+/// and their records in `db`. Returns the number of indices in use afterwards.
+pub fn compact(dir: &Path, template: &NameTemplate, db: Option<&Db>) -> Result<usize, AppError> {
+    let mut groups: BTreeMap<u64, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some((index, _)) = split_name(&name, template) {
+            let name = name.into_owned();
+            groups.entry(index).or_default().push((path, name));
+        }
+    }
+    // Indices only ever move down, onto slots that were vacated earlier in the loop.
+    for (new_index, (index, files)) in groups.iter().enumerate() {
+        if *index == new_index as u64 {
+            continue;
+        }
+        for (path, name) in files {
+            if let Some(renamed) = template.rename(name, new_index as u64, None) {
+                let target = dir.join(renamed);
+                fs::rename(path, &target)?;
+                if let Some(db) = db {
+                    db.rename(path, &target, None)?;
+                }
+            }
+        }
+    }
+    Ok(groups.len())
+}
+
+/// One past the highest index used in `dir`, or 0 if it has no indexed files.
+pub fn next_free(dir: &Path, template: &NameTemplate) -> io::Result<u64> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let mut next = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some((index, _)) = split_name(&entry.file_name().to_string_lossy(), template) {
+            next = next.max(index + 1);
+        }
+    }
+    Ok(next)
+}
+
+/// Renumbers every directory under `store_path` to `0..n`: indexed captures keep their
+/// order, and images with other names are named from `template` with the indices after them.
+pub fn reindex(store_path: &Path, template: &NameTemplate) -> Result<(), AppError> {
+    invalidate(store_path)?;
+    let db = Db::existing(store_path)?;
+    let mut dirs: BTreeMap<PathBuf, Vec<Sample>> = BTreeMap::new();
+    for sample in dataset::from_paths(store_path, manifest::images(store_path)?) {
+        if let Some(parent) = sample.path.parent() {
+            dirs.entry(parent.to_path_buf()).or_default().push(sample);
+        }
+    }
+    for (dir, samples) in dirs {
+        let mut next = compact(&dir, template, db.as_ref())?;
+        for sample in samples {
+            let name = sample
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            if template.parse(&name).is_some() {
+                continue;
+            }
+            let session = sample
+                .path
+                .strip_prefix(store_path)
+                .ok()
+                .and_then(|relative| relative.components().next())
+                .map(|first| first.as_os_str().to_string_lossy())
+                .filter(|first| {
+                    store_path
+                        .join(first.as_ref())
+                        .join(session::FILE)
+                        .is_file()
+                });
+            let stem = template.render(&Fields {
+                label: &sample.label,
+                session: session.as_deref(),
+                index: next as i32,
+                camera: 0,
+            });
+            let ext = sample
+                .path
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let target = dir.join(format!("{}.{}", stem, ext));
+            fs::rename(&sample.path, &target)?;
+            if let Some(db) = &db {
+                db.rename(&sample.path, &target, None)?;
+            }
+            next += 1;
+        }
+        println!("reindex {} files in {:?}", next, dir);
+    }
+    db::sync(store_path)
+}
+
+pub(crate) trait FileIndice {
+    fn from_data_path(path: &str, template: &NameTemplate) -> Result<Self, AppError>
+    where
+        Self: Sized;
+}
+
+trait FileIndiceHashMapAllowTypes {}
+impl FileIndiceHashMapAllowTypes for i32 {}
+impl FileIndiceHashMapAllowTypes for i64 {}
+
+impl<T> FileIndice for HashMap<String, T>
+where
+    T: FileIndiceHashMapAllowTypes + From<i32> + PartialOrd + Clone,
+{
+    /// Next free index of every directory under `path`, found without touching any file.
+    fn from_data_path(path: &str, template: &NameTemplate) -> Result<Self, AppError> {
+        let mut result = Self::with_capacity(100);
+        let base_path = PathBuf::from(path).canonicalize()?;
+        for entry in glob::glob(&base_path.join("**/*").to_string_lossy())? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().map(|name| name.to_string_lossy()) else {
+                continue;
+            };
+            let Some((index, _)) = split_name(&name, template) else {
+                continue;
+            };
+            let parent = entry
+                .parent()
+                .ok_or(AppError::PathError("Missing parent directory".into()))?;
+            let parent_str = parent
+                .to_str()
+                .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+            let next = i32::try_from(index + 1)
+                .map_err(|_| AppError::PathError(format!("Index too large: {:?}", entry)))?;
+            let count = result.entry(parent_str.into()).or_insert(0.into());
+            if T::from(next) > *count {
+                *count = next.into();
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_index_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn template(text: &str) -> NameTemplate {
+        text.parse().unwrap()
+    }
+
+    fn touch(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn split_name_reads_the_index_and_rest() {
+        let plain = template("{index}");
+        assert_eq!(split_name("12.png", &plain), Some((12, ".png")));
+        assert_eq!(split_name("12_ev-1.png", &plain), Some((12, "_ev-1.png")));
+        assert_eq!(split_name("12", &plain), Some((12, "")));
+        assert_eq!(split_name("img12.png", &plain), None);
+        let padded = template("{label}_{index:05}");
+        assert_eq!(split_name("cat_00012.json", &padded), Some((12, ".json")));
+        assert_eq!(split_name("cat.png", &padded), None);
+        assert_eq!(split_name("a1b2.png", &template("{uuid}")), None);
+    }
+
+    #[test]
+    fn compact_closes_gaps_and_keeps_names() {
+        let dir = scratch("compact");
+        touch(
+            &dir,
+            &[
+                "cat_00003.png",
+                "cat_00003.json",
+                "cat_00007.png",
+                "cat_00007_f10.png",
+                "notes.txt",
+            ],
+        );
+        let used = compact(&dir, &template("{label}_{index:05}"), None).unwrap();
+        assert_eq!(used, 2);
+        assert_eq!(
+            names(&dir),
+            [
+                "cat_00000.json",
+                "cat_00000.png",
+                "cat_00001.png",
+                "cat_00001_f10.png",
+                "notes.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn next_free_is_one_past_the_highest_index() {
+        let dir = scratch("next_free");
+        let plain = template("{index}");
+        assert_eq!(next_free(&dir.join("missing"), &plain).unwrap(), 0);
+        touch(&dir, &["0.png", "4.png", "4.json", "x9.png"]);
+        assert_eq!(next_free(&dir, &plain).unwrap(), 5);
+    }
+
+    #[test]
+    fn store_lock_update_raises_indices_to_what_others_saved() {
+        let store = scratch("store_lock");
+        let lock = StoreLock::open(&store).unwrap();
+        let dir = store
+            .canonicalize()
+            .unwrap()
+            .join("cat")
+            .to_string_lossy()
+            .into_owned();
+        // Another recorder already handed out 0..5 in the directory.
+        let mut other = HashMap::from([(dir.clone(), 5)]);
+        lock.update(&store, &mut other, |_| ()).unwrap();
+
+        let mut ours = HashMap::from([(dir.clone(), 2)]);
+        let taken = lock
+            .update(&store, &mut ours, |indices| {
+                let next = indices.entry(dir.clone()).or_insert(0);
+                *next += 1;
+                *next - 1
+            })
+            .unwrap();
+        assert_eq!(taken, 5);
+        assert_eq!(ours[&dir], 6);
+        assert_eq!(load_state(&store).unwrap().unwrap()[&dir], 6);
+    }
+}
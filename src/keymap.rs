@@ -0,0 +1,411 @@
+//! Capture window key bindings, remappable with `--keymap keymap.toml`: `action = "key"` or
+//! `action = ["key", ...]`, e.g. `focus_up = "f"` or `undo = ["u", "backspace"]`; an empty
+//! list unbinds the action. Keys are single characters or one of `space`, `enter`, `tab`,
+//! `backspace`, `esc`, `left`, `right`, `up`, `down`, `pageup`, `pagedown` and `f1`..`f12`.
+//! Actions left out keep their default keys.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::AppError;
+use crate::ui::{KEY_DOWN, KEY_LEFT, KEY_PAGE_DOWN, KEY_PAGE_UP, KEY_RIGHT, KEY_UP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    SavePrediction,
+    SavePreRoll,
+    TypeLabel,
+    Undo,
+    Pause,
+    FocusDown,
+    FocusUp,
+    ExposureDown,
+    ExposureUp,
+    GainDown,
+    GainUp,
+    BrightnessDown,
+    BrightnessUp,
+    WbDown,
+    WbUp,
+    PanLeft,
+    PanRight,
+    TiltUp,
+    TiltDown,
+    ZoomIn,
+    ZoomOut,
+    PrintSettings,
+    FocusSweep,
+    SaveProfile,
+    Counts,
+    Timing,
+    Sharpness,
+    Histogram,
+    Zebra,
+    Grid,
+    KeepThumbnail,
+    CopyFrame,
+    Fullscreen,
+    Help,
+    Quit,
+}
+
+/// File name, default keys and help text of every action, in help order. Consecutive
+/// actions with the same help text share a help line.
+const ACTIONS: &[(Action, &str, &[&str], &str)] = &[
+    (
+        Action::SavePrediction,
+        "save_prediction",
+        &["space"],
+        "save to the top model prediction",
+    ),
+    (
+        Action::SavePreRoll,
+        "save_pre_roll",
+        &["!"],
+        "save the --pre-roll frames from before the key",
+    ),
+    (
+        Action::TypeLabel,
+        "type_label",
+        &["/"],
+        "type a label, Enter to set, Esc to cancel",
+    ),
+    (
+        Action::Undo,
+        "undo",
+        &["backspace"],
+        "undo the last capture",
+    ),
+    (Action::Pause, "pause", &["tab"], "pause / resume"),
+    (Action::FocusDown, "focus_down", &["-"], "focus"),
+    (Action::FocusUp, "focus_up", &["+"], "focus"),
+    (Action::ExposureDown, "exposure_down", &["["], "exposure"),
+    (Action::ExposureUp, "exposure_up", &["]"], "exposure"),
+    (Action::GainDown, "gain_down", &["{"], "gain"),
+    (Action::GainUp, "gain_up", &["}"], "gain"),
+    (
+        Action::BrightnessDown,
+        "brightness_down",
+        &["("],
+        "brightness",
+    ),
+    (Action::BrightnessUp, "brightness_up", &[")"], "brightness"),
+    (Action::WbDown, "wb_down", &["<"], "white balance"),
+    (Action::WbUp, "wb_up", &[">"], "white balance"),
+    (Action::PanLeft, "pan_left", &["left"], "pan"),
+    (Action::PanRight, "pan_right", &["right"], "pan"),
+    (Action::TiltUp, "tilt_up", &["up"], "tilt"),
+    (Action::TiltDown, "tilt_down", &["down"], "tilt"),
+    (Action::ZoomIn, "zoom_in", &["pageup"], "zoom"),
+    (Action::ZoomOut, "zoom_out", &["pagedown"], "zoom"),
+    (
+        Action::PrintSettings,
+        "print_settings",
+        &["enter"],
+        "print camera settings",
+    ),
+    (Action::FocusSweep, "focus_sweep", &["#"], "focus sweep"),
+    (
+        Action::SaveProfile,
+        "save_profile",
+        &["*"],
+        "save camera profile",
+    ),
+    (Action::Counts, "counts", &["="], "class counts"),
+    (Action::Timing, "timing", &["%"], "fps and save rate"),
+    (Action::Sharpness, "sharpness", &["|"], "sharpness"),
+    (Action::Histogram, "histogram", &["~"], "histogram"),
+    (Action::Zebra, "zebra", &["^"], "zebra"),
+    (Action::Grid, "grid", &[";"], "grid"),
+    (
+        Action::KeepThumbnail,
+        "keep_thumbnail",
+        &[","],
+        "keep thumbnail",
+    ),
+    (
+        Action::CopyFrame,
+        "copy_frame",
+        &["@"],
+        "copy frame to clipboard",
+    ),
+    (Action::Fullscreen, "fullscreen", &["f"], "fullscreen"),
+    (Action::Help, "help", &["h", "?"], "this help"),
+    (Action::Quit, "quit", &["q", "esc"], "quit"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    /// Raw `wait_key_ex` codes of a special key on GTK, Windows and macOS.
+    Codes([i32; 3]),
+}
+
+impl Key {
+    fn parse(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Some(Key::Char(c));
+        }
+        let key = match name.to_ascii_lowercase().as_str() {
+            "space" => Key::Char(' '),
+            "enter" => Key::Char('\r'),
+            "tab" => Key::Char('\t'),
+            "backspace" => Key::Char('\x08'),
+            "esc" => Key::Char('\x1b'),
+            "left" => Key::Codes(KEY_LEFT),
+            "right" => Key::Codes(KEY_RIGHT),
+            "up" => Key::Codes(KEY_UP),
+            "down" => Key::Codes(KEY_DOWN),
+            "pageup" => Key::Codes(KEY_PAGE_UP),
+            "pagedown" => Key::Codes(KEY_PAGE_DOWN),
+            name => {
+                let n: i32 = name.strip_prefix('f')?.parse().ok()?;
+                if !(1..=12).contains(&n) {
+                    return None;
+                }
+                Key::Codes([0xFFBE + n - 1, 0x700000 + ((n - 1) << 16), 0xF704 + n - 1])
+            }
+        };
+        Some(key)
+    }
+
+    fn matches(&self, key: i32) -> bool {
+        match self {
+            Key::Char(c) => key_char(key) == Some(*c),
+            Key::Codes(codes) => codes.contains(&key),
+        }
+    }
+}
+
+/// The character typed for a raw `wait_key_ex` code. GTK reports Backspace and the like as
+/// key symbols with the character in the low byte; other special keys have no character.
+pub fn key_char(key: i32) -> Option<char> {
+    match key {
+        0..=0xFF | 0xFF08 | 0xFF09 | 0xFF0D | 0xFF1B => char::from_u32((key & 0xFF) as u32),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Binding {
+    action: Action,
+    name: &'static str,
+    help: &'static str,
+    /// Parsed keys with the names they were given as, for the help overlay.
+    keys: Vec<(Key, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = ACTIONS
+            .iter()
+            .map(|&(action, name, keys, help)| Binding {
+                action,
+                name,
+                help,
+                keys: keys
+                    .iter()
+                    .filter_map(|key| Some((Key::parse(key)?, key.to_string())))
+                    .collect(),
+            })
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    /// The default bindings with the actions in `path` rebound. A key may trigger only one
+    /// action, so taking an action's default key for another means rebinding both.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let table: BTreeMap<String, Keys> = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut keymap = Self::default();
+        for (name, keys) in table {
+            let Some(binding) = keymap.bindings.iter_mut().find(|b| b.name == name) else {
+                return Err(AppError::KeymapError(format!(
+                    "unknown action {:?} in {:?}",
+                    name, path
+                ))
+                .into());
+            };
+            let keys = match keys {
+                Keys::One(key) => vec![key],
+                Keys::Many(keys) => keys,
+            };
+            binding.keys = keys
+                .into_iter()
+                .map(|key| match Key::parse(&key) {
+                    Some(parsed) => Ok((parsed, key)),
+                    None => Err(AppError::KeymapError(format!(
+                        "unknown key {:?} for {} in {:?}",
+                        key, name, path
+                    ))),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        for (i, binding) in keymap.bindings.iter().enumerate() {
+            for (key, key_name) in &binding.keys {
+                if let Some(other) = keymap.bindings[i + 1..]
+                    .iter()
+                    .find(|other| other.keys.iter().any(|(k, _)| k == key))
+                {
+                    return Err(AppError::KeymapError(format!(
+                        "key {:?} is bound to both {} and {} in {:?}",
+                        key_name, binding.name, other.name, path
+                    ))
+                    .into());
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// The action bound to the raw `wait_key_ex` code `key`.
+    pub fn action(&self, key: i32) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.keys.iter().any(|(k, _)| k.matches(key)))
+            .map(|binding| binding.action)
+    }
+
+    /// The name of the action bound to the character `key`, which then isn't a class key.
+    pub fn bound(&self, key: char) -> Option<&'static str> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.keys.iter().any(|(k, _)| *k == Key::Char(key)))
+            .map(|binding| binding.name)
+    }
+
+    /// `keys  help` lines for every bound action, for the help overlay.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<(String, &str)> = Vec::new();
+        for binding in &self.bindings {
+            if binding.keys.is_empty() {
+                continue;
+            }
+            let keys = binding
+                .keys
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            match lines.last_mut() {
+                Some((line_keys, help)) if *help == binding.help => {
+                    line_keys.push(' ');
+                    line_keys.push_str(&keys);
+                }
+                _ => lines.push((keys, binding.help)),
+            }
+        }
+        lines
+            .into_iter()
+            .map(|(keys, help)| format!("{:<13}{}", keys, help))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// `toml` written to a keymap file of its own.
+    fn keymap_file(name: &str, toml: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "img_collector_keymap_{}_{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, toml).unwrap();
+        path
+    }
+
+    #[test]
+    fn keys_parse_characters_names_and_function_keys() {
+        assert_eq!(Key::parse("x"), Some(Key::Char('x')));
+        assert_eq!(Key::parse("Space"), Some(Key::Char(' ')));
+        assert_eq!(Key::parse("backspace"), Some(Key::Char('\x08')));
+        assert_eq!(Key::parse("left"), Some(Key::Codes(KEY_LEFT)));
+        assert_eq!(
+            Key::parse("f1"),
+            Some(Key::Codes([0xFFBE, 0x700000, 0xF704]))
+        );
+        for bad in ["f0", "f13", "fx", "enterr", ""] {
+            assert_eq!(Key::parse(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn key_char_reads_gtk_key_symbols() {
+        assert_eq!(key_char('a' as i32), Some('a'));
+        assert_eq!(key_char(0xFF08), Some('\x08'));
+        assert_eq!(key_char(0xFF0D), Some('\r'));
+        assert_eq!(key_char(KEY_LEFT[0]), None);
+        assert_eq!(key_char(-1), None);
+    }
+
+    #[test]
+    fn default_bindings_match_raw_codes() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.action(' ' as i32), Some(Action::SavePrediction));
+        assert_eq!(keymap.action(0xFF08), Some(Action::Undo));
+        assert_eq!(keymap.action(0x1b), Some(Action::Quit));
+        assert_eq!(keymap.action(KEY_RIGHT[2]), Some(Action::PanRight));
+        assert_eq!(keymap.action('c' as i32), None);
+        assert_eq!(keymap.bound('?'), Some("help"));
+        assert_eq!(keymap.bound('c'), None);
+    }
+
+    #[test]
+    fn load_rebinds_and_unbinds_actions() {
+        let path = keymap_file("rebind", "undo = [\"u\", \"f2\"]\nfullscreen = []\n");
+        let keymap = Keymap::load(&path).unwrap();
+        assert_eq!(keymap.action('u' as i32), Some(Action::Undo));
+        assert_eq!(keymap.action(0xFFBF), Some(Action::Undo));
+        assert_eq!(keymap.action(0xFF08), None);
+        assert_eq!(keymap.bound('f'), None);
+        // Actions left out keep their defaults.
+        assert_eq!(keymap.action('q' as i32), Some(Action::Quit));
+    }
+
+    #[test]
+    fn load_rejects_unknown_names_and_shared_keys() {
+        for (name, toml) in [
+            ("action", "jump = \"j\"\n"),
+            ("key", "undo = \"hyper\"\n"),
+            // `q` still quits, so it can't undo too.
+            ("shared", "undo = \"q\"\n"),
+        ] {
+            let path = keymap_file(name, toml);
+            assert!(Keymap::load(&path).is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn describe_joins_actions_that_share_help() {
+        let lines = Keymap::default().describe();
+        let focus = lines.iter().find(|line| line.ends_with("focus")).unwrap();
+        assert_eq!(focus, &format!("{:<13}focus", "- +"));
+        assert!(
+            lines
+                .iter()
+                .any(|line| line == &format!("{:<13}this help", "h ?"))
+        );
+    }
+}
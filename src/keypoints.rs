@@ -0,0 +1,232 @@
+//! Ordered landmark annotation: `<image stem>.keypoints.json` next to every annotated image,
+//! holding `[x, y, visibility]` per skeleton keypoint in COCO's convention (pixels; 0 not
+//! labelled, 1 labelled but occluded, 2 visible).
+
+use opencv::{
+    core::{CV_8UC3, Mat, Point, Scalar},
+    highgui::{
+        EVENT_LBUTTONDOWN, EVENT_RBUTTONDOWN, WINDOW_AUTOSIZE, imshow, named_window,
+        set_mouse_callback, wait_key_ex,
+    },
+    imgcodecs::{IMREAD_UNCHANGED, imread},
+    imgproc::{FILLED, LINE_AA, circle, line},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::ui::{KEY_LEFT, KEY_RIGHT, draw_text, to_display};
+use crate::{AppError, dataset};
+
+const WINDOW: &str = "keypoints";
+
+/// Keypoint names in click order, and the index pairs drawn as limbs.
+#[derive(Debug, Deserialize)]
+pub struct Skeleton {
+    pub keypoints: Vec<String>,
+    #[serde(default)]
+    pub skeleton: Vec<[usize; 2]>,
+}
+
+impl Skeleton {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let skeleton: Skeleton = toml::from_str(&fs::read_to_string(path)?)?;
+        if let Some([a, b]) = skeleton
+            .skeleton
+            .iter()
+            .find(|[a, b]| *a >= skeleton.keypoints.len() || *b >= skeleton.keypoints.len())
+        {
+            return Err(
+                AppError::DatasetError(format!("skeleton edge {}-{} out of range", a, b)).into(),
+            );
+        }
+        Ok(skeleton)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Keypoints {
+    keypoints: Vec<[f64; 3]>,
+}
+
+/// The keypoint file that belongs to `image`.
+pub fn keypoints_path(image: &Path) -> PathBuf {
+    image.with_extension("keypoints.json")
+}
+
+fn load(image: &Path) -> Result<Vec<[f64; 3]>, AppError> {
+    let path = keypoints_path(image);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let keypoints: Keypoints = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(keypoints.keypoints)
+}
+
+fn save(image: &Path, keypoints: &[[f64; 3]]) -> Result<(), AppError> {
+    let path = keypoints_path(image);
+    if keypoints.is_empty() {
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let keypoints = Keypoints {
+        keypoints: keypoints.to_vec(),
+    };
+    fs::write(path, serde_json::to_string_pretty(&keypoints)?)?;
+    Ok(())
+}
+
+/// Left-click places the next keypoint, right-click skips it as not labelled.
+fn track_clicks(clicks: &Arc<Mutex<Vec<(Point, bool)>>>) -> opencv::Result<()> {
+    let clicks = Arc::clone(clicks);
+    set_mouse_callback(
+        WINDOW,
+        Some(Box::new(move |event, x, y, _flags| {
+            let Ok(mut clicks) = clicks.lock() else {
+                return;
+            };
+            match event {
+                EVENT_LBUTTONDOWN => clicks.push((Point::new(x, y), true)),
+                EVENT_RBUTTONDOWN => clicks.push((Point::new(x, y), false)),
+                _ => {}
+            }
+        })),
+    )
+}
+
+/// Steps through the saved images, placing the skeleton's keypoints in order. `o` toggles
+/// the last point between visible and occluded, Backspace removes it, arrows navigate, Esc
+/// quits. Keypoints are written on every change.
+pub fn run(store_path: &Path, skeleton: &Skeleton) -> Result<(), Box<dyn Error>> {
+    let samples = dataset::samples(store_path)?;
+    if samples.is_empty() || skeleton.keypoints.is_empty() {
+        println!("nothing to annotate under {:?}", store_path);
+        return Ok(());
+    }
+    named_window(WINDOW, WINDOW_AUTOSIZE)?;
+    let clicks = Arc::new(Mutex::new(Vec::new()));
+    track_clicks(&clicks)?;
+
+    let mut current = 0;
+    let mut loaded = None;
+    let mut image = Mat::default();
+    let mut keypoints = Vec::new();
+    loop {
+        let sample = &samples[current];
+        if loaded != Some(current) {
+            let path = sample
+                .path
+                .to_str()
+                .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+            let decoded = imread(path, IMREAD_UNCHANGED)?;
+            image = if decoded.empty() {
+                Mat::new_rows_cols_with_default(240, 320, CV_8UC3, Scalar::all(0.0))?
+            } else {
+                to_display(&decoded)?
+            };
+            keypoints = load(&sample.path)?;
+            loaded = Some(current);
+            if let Ok(mut clicks) = clicks.lock() {
+                clicks.clear();
+            }
+        }
+
+        let new_clicks: Vec<(Point, bool)> = clicks
+            .lock()
+            .map(|mut clicks| clicks.drain(..).collect())
+            .unwrap_or_default();
+        let mut changed = false;
+        for (point, labelled) in new_clicks {
+            if keypoints.len() < skeleton.keypoints.len() {
+                keypoints.push(if labelled {
+                    [point.x as f64, point.y as f64, 2.0]
+                } else {
+                    [0.0, 0.0, 0.0]
+                });
+                changed = true;
+            }
+        }
+        if changed {
+            save(&sample.path, &keypoints)?;
+        }
+
+        let mut preview = image.try_clone()?;
+        let point = |k: &[f64; 3]| Point::new(k[0] as i32, k[1] as i32);
+        for [a, b] in &skeleton.skeleton {
+            if let (Some(a), Some(b)) = (keypoints.get(*a), keypoints.get(*b))
+                && a[2] > 0.0
+                && b[2] > 0.0
+            {
+                line(
+                    &mut preview,
+                    point(a),
+                    point(b),
+                    Scalar::new(255.0, 255.0, 0.0, 0.0),
+                    2,
+                    LINE_AA,
+                    0,
+                )?;
+            }
+        }
+        for (k, name) in keypoints.iter().zip(&skeleton.keypoints) {
+            if k[2] == 0.0 {
+                continue;
+            }
+            let color = if k[2] == 2.0 {
+                Scalar::new(0.0, 255.0, 0.0, 0.0)
+            } else {
+                Scalar::new(0.0, 0.0, 255.0, 0.0)
+            };
+            circle(&mut preview, point(k), 4, color, FILLED, LINE_AA, 0)?;
+            draw_text(&mut preview, name, point(k) + Point::new(6, -6), 0.4)?;
+        }
+        let next = skeleton
+            .keypoints
+            .get(keypoints.len())
+            .map_or("done", String::as_str);
+        let status = format!(
+            "{} ({}/{}) next: {}",
+            sample.label,
+            current + 1,
+            samples.len(),
+            next
+        );
+        draw_text(&mut preview, &status, Point::new(10, 30), 0.8)?;
+        if imshow(WINDOW, &preview).is_err() {
+            break;
+        }
+
+        let key = wait_key_ex(30).unwrap_or(-1);
+        if key == -1 {
+            continue;
+        }
+        if KEY_LEFT.contains(&key) {
+            current = current.saturating_sub(1);
+            continue;
+        }
+        if KEY_RIGHT.contains(&key) {
+            current = (current + 1).min(samples.len() - 1);
+            continue;
+        }
+        match char::from_u32((key & 0xFF) as u32) {
+            Some('\x1b') => break,
+            Some('\x08') if keypoints.pop().is_some() => save(&sample.path, &keypoints)?,
+            Some('o') => {
+                if let Some(last) = keypoints.last_mut()
+                    && last[2] > 0.0
+                {
+                    last[2] = if last[2] == 2.0 { 1.0 } else { 2.0 };
+                    save(&sample.path, &keypoints)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
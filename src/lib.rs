@@ -0,0 +1,1635 @@
+//! Captures labelled image datasets from cameras and prepares them for training. `run` is the
+//! command line and the capture loop; `source` reads frames, `controls` answers the keys,
+//! `recorder` saves captures through `transform` and `sink`, `index` numbers them and `ui`
+//! draws the preview windows.
+
+use opencv::{
+    calib3d::{SOLVEPNP_IPPE_SQUARE, solve_pnp},
+    core::{
+        CV_8UC3, CV_32F, FileStorage, FileStorage_Mode, Point, Point2f, Point3f, Ptr, Rect, Scalar,
+        Size, Vector, count_non_zero,
+    },
+    dnn::{Net, blob_from_image, read_net_from_onnx},
+    highgui::{WINDOW_KEEPRATIO, WINDOW_NORMAL, imshow, named_window, resize_window, wait_key_ex},
+    imgcodecs::{IMWRITE_JPEG_QUALITY, IMWRITE_PNG_COMPRESSION, IMWRITE_WEBP_QUALITY},
+    imgproc::{LINE_8, LINE_AA, rectangle},
+    objdetect::{
+        ArucoDetector, CascadeClassifier, DetectorParameters, PredefinedDictionaryType,
+        QRCodeDetector, RefineParameters, get_predefined_dictionary,
+    },
+    prelude::*,
+    video::{
+        BackgroundSubtractorMOG2, BackgroundSubtractorMOG2Trait, create_background_subtractor_mog2,
+    },
+    videoio::CAP_PROP_FORMAT,
+};
+
+mod annotate;
+mod balance;
+mod bench;
+mod calibrate;
+mod capture;
+mod classes;
+mod clipboard;
+mod config;
+mod controls;
+mod dataset;
+mod db;
+mod dedupe;
+mod events;
+mod exif;
+mod export;
+mod gpu;
+mod hook;
+mod http;
+pub mod index;
+mod keymap;
+mod keypoints;
+mod manifest;
+mod merge;
+mod mqtt;
+mod naming;
+mod notify;
+mod overlay;
+mod parquet;
+pub mod pattern;
+mod recorder;
+mod review;
+mod s3;
+mod session;
+pub mod sink;
+pub mod source;
+mod split;
+mod stats;
+mod tfrecord;
+mod transform;
+pub mod ui;
+mod validate;
+mod webhook;
+mod writer;
+
+use capture::Capture;
+use clap::{FromArgMatches, Parser, Subcommand, ValueEnum};
+use controls::{Controls, Keys};
+use db::Db;
+use events::Events;
+use keymap::Keymap;
+use naming::NameTemplate;
+use serde_json::json;
+use std::boxed::Box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use writer::Writer;
+
+use index::FileIndice;
+use recorder::{MarkerPose, Recorder};
+use sink::{FrameSink, LocalDir, create_data_dir};
+use source::{
+    Backend, FrameSource, PROFILE_FILE, PROPS, VideoSize, VideoSource, list_devices, open_source,
+    read_props,
+};
+use transform::{ColorSpace, Fit, FlipAxis, OutSize, Rotation, sharpness, to_gray};
+use ui::{HELP, RoiSelection, draw_text, mirror, rect_between, tile_frames, to_display, track_roi};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ImageFormat {
+    Png,
+    Jpg,
+    Webp,
+    Tiff,
+}
+
+impl ImageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Tiff => "tiff",
+        }
+    }
+
+    fn params(&self, quality: Option<i32>, png_compression: Option<i32>) -> Vector<i32> {
+        let mut params = Vector::<i32>::new();
+        match self {
+            ImageFormat::Png => {
+                if let Some(level) = png_compression {
+                    params.push(IMWRITE_PNG_COMPRESSION);
+                    params.push(level);
+                }
+            }
+            ImageFormat::Jpg => {
+                if let Some(quality) = quality {
+                    params.push(IMWRITE_JPEG_QUALITY);
+                    params.push(quality);
+                }
+            }
+            ImageFormat::Webp => {
+                if let Some(quality) = quality {
+                    params.push(IMWRITE_WEBP_QUALITY);
+                    params.push(quality);
+                }
+            }
+            ImageFormat::Tiff => {}
+        }
+        params
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    #[command(flatten)]
+    Source(VideoSource),
+    Devices {
+        #[arg(long, default_value = "10")]
+        max_index: i32,
+        #[arg(long, value_enum, default_value = "any")]
+        backend: Backend,
+    },
+    Calibrate {
+        #[arg(long, default_value = "9")]
+        cols: i32,
+        #[arg(long, default_value = "6")]
+        rows: i32,
+        #[arg(long, default_value = "0.025")]
+        square_size: f32,
+        #[arg(long, default_value = "calibration.yaml")]
+        output: PathBuf,
+        #[arg(long, default_value = "10")]
+        min_views: usize,
+        #[command(subcommand)]
+        source: VideoSource,
+    },
+    Manifest {
+        #[arg(long, default_value = "manifest.csv")]
+        output: PathBuf,
+    },
+    Export {
+        #[command(subcommand)]
+        format: export::ExportFormat,
+    },
+    Split {
+        #[arg(long, value_delimiter = ',', default_value = "0.8,0.1,0.1")]
+        ratios: Vec<f64>,
+        #[arg(long, default_value = "0")]
+        seed: u64,
+        #[arg(long)]
+        stratify: bool,
+        #[arg(long, default_value = "splits")]
+        output: PathBuf,
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    Stats {
+        #[arg(long)]
+        json: bool,
+    },
+    Dedupe {
+        #[arg(long, default_value = "4")]
+        distance: u32,
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long)]
+        quarantine: Option<PathBuf>,
+    },
+    Validate {
+        #[arg(long)]
+        delete: bool,
+        #[arg(long)]
+        reindex: bool,
+    },
+    Reindex,
+    Review,
+    Annotate {
+        #[arg(long)]
+        keypoints: Option<PathBuf>,
+    },
+    Balance {
+        #[arg(long)]
+        target: Option<usize>,
+        #[arg(long, default_value = "0")]
+        seed: u64,
+        #[arg(long)]
+        prune: bool,
+        #[arg(long)]
+        quarantine: Option<PathBuf>,
+    },
+    Merge {
+        src: PathBuf,
+        dst: PathBuf,
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    Bench {
+        #[arg(long, default_value = "120")]
+        frames: usize,
+        #[command(subcommand)]
+        source: VideoSource,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about=None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    #[arg(long, default_value = "data")]
+    store_path: String,
+
+    /// `s3://bucket/prefix` to upload captures to; the store path then only spools them.
+    #[arg(long, conflicts_with = "append_manifest")]
+    store: Option<String>,
+
+    #[arg(long, default_value = "1")]
+    burst: u32,
+
+    #[arg(long)]
+    hold: bool,
+
+    #[arg(long)]
+    countdown: Option<f64>,
+
+    #[arg(long)]
+    pre_roll: Option<f64>,
+
+    #[arg(long, requires = "label")]
+    interval: Option<f64>,
+
+    #[arg(long)]
+    label: Option<String>,
+
+    #[arg(long, requires = "interval")]
+    count: Option<u32>,
+
+    #[arg(long, requires = "label")]
+    motion_trigger: bool,
+
+    #[arg(long, default_value = "0.01")]
+    motion_threshold: f64,
+
+    #[arg(long, default_value = "2")]
+    motion_cooldown: f64,
+
+    #[arg(long)]
+    face: Option<PathBuf>,
+
+    #[arg(long, requires = "face")]
+    face_crop: bool,
+
+    #[arg(long, default_value = "1")]
+    face_cooldown: f64,
+
+    #[arg(long)]
+    qr_label: bool,
+
+    #[arg(long, requires = "qr_label")]
+    qr_capture: bool,
+
+    #[arg(long)]
+    aruco: bool,
+
+    #[arg(long, default_value = "0.05")]
+    marker_length: f64,
+
+    #[arg(long)]
+    calibration: Option<PathBuf>,
+
+    #[arg(long)]
+    model: Option<PathBuf>,
+
+    #[arg(long, requires = "model")]
+    classes: Option<PathBuf>,
+
+    /// TOML file naming the class of each key, e.g. `c = "cat"`.
+    #[arg(long)]
+    class_map: Option<PathBuf>,
+
+    #[arg(long, default_value = "3")]
+    top_k: usize,
+
+    #[arg(long, default_value = "224")]
+    model_size: i32,
+
+    #[arg(long, requires = "model")]
+    active_learning: bool,
+
+    #[arg(long, default_value = "0.6")]
+    confidence_threshold: f32,
+
+    #[arg(long)]
+    entropy_threshold: Option<f32>,
+
+    #[arg(long)]
+    min_sharpness: Option<f64>,
+
+    #[arg(long)]
+    max_clipping: Option<f64>,
+
+    #[arg(long)]
+    dedupe_distance: Option<u32>,
+
+    #[arg(long)]
+    out_size: Option<OutSize>,
+
+    #[arg(long, value_enum, default_value = "stretch")]
+    fit: Fit,
+
+    #[arg(long, value_enum, default_value = "bgr")]
+    color: ColorSpace,
+
+    #[arg(long, value_enum)]
+    rotate: Option<Rotation>,
+
+    #[arg(long, value_enum)]
+    flip: Option<FlipAxis>,
+
+    #[arg(long)]
+    mirror_preview: bool,
+
+    #[arg(long)]
+    watermark: Option<String>,
+
+    #[arg(long, requires = "watermark")]
+    keep_clean: bool,
+
+    #[arg(long)]
+    thumbs: Option<OutSize>,
+
+    #[arg(long)]
+    keep_raw: bool,
+
+    #[arg(long)]
+    sidecar: bool,
+
+    #[arg(long)]
+    exif: bool,
+
+    #[arg(long)]
+    session: Option<Option<String>>,
+
+    #[arg(long, value_enum, default_value = "png")]
+    format: ImageFormat,
+
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..=100))]
+    quality: Option<i32>,
+
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..=9))]
+    png_compression: Option<i32>,
+
+    #[arg(long)]
+    append_manifest: Option<PathBuf>,
+
+    /// Command run after each capture is written, with `{path}` and `{label}` filled in. An
+    /// s3 `--store` deletes the local file once it is uploaded, so there is no path to pass.
+    #[arg(long, conflicts_with = "store")]
+    on_save: Option<String>,
+
+    #[arg(long, default_value = "4", requires = "on_save")]
+    on_save_jobs: usize,
+
+    /// URL to POST a JSON event to for each capture and at session start and end.
+    #[arg(long)]
+    webhook: Option<String>,
+
+    /// `tcp://host:port` of an MQTT broker to publish the same events to.
+    #[arg(long)]
+    mqtt: Option<String>,
+
+    #[arg(long, default_value = "imgcollector/events", requires = "mqtt")]
+    topic: String,
+
+    /// Topic whose messages each capture to the label they carry, or to --label if empty.
+    #[arg(long, requires = "mqtt")]
+    mqtt_trigger: Option<String>,
+
+    /// Address for the HTTP control API and the /stream preview, e.g. `127.0.0.1:8080`.
+    #[arg(long)]
+    listen: Option<String>,
+
+    #[arg(long)]
+    target: Option<usize>,
+
+    #[arg(long)]
+    max_per_class: Option<usize>,
+
+    #[arg(long)]
+    window_size: Option<OutSize>,
+
+    #[arg(long, default_value = "250")]
+    zebra_threshold: u8,
+
+    #[arg(long)]
+    grid: Option<OutSize>,
+
+    #[arg(long)]
+    crosshair: bool,
+
+    #[arg(long)]
+    beep: bool,
+
+    #[arg(long)]
+    flash: bool,
+
+    #[arg(long)]
+    keymap: Option<PathBuf>,
+
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[arg(long, default_value = "{index}")]
+    name_template: NameTemplate,
+
+    #[arg(long)]
+    db: bool,
+
+    #[arg(long)]
+    gpu: bool,
+
+    #[arg(long)]
+    event_log: Option<PathBuf>,
+}
+
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("Path error: {0}")]
+    PathError(String),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Glob error: {0}")]
+    GlobError(#[from] glob::GlobError),
+
+    #[error("GlobPattern error: {0}")]
+    GlobPatternError(#[from] glob::PatternError),
+
+    #[error("Stream error: {0}")]
+    StreamError(String),
+
+    #[error("Source error: {0}")]
+    SourceError(String),
+
+    #[error("Invalid FOURCC: {0}")]
+    FourccError(String),
+
+    #[error("OpenCV error: {0}")]
+    OpenCvError(#[from] opencv::Error),
+
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("TOML error: {0}")]
+    TomlError(#[from] toml::ser::Error),
+
+    #[error("Dataset error: {0}")]
+    DatasetError(String),
+
+    #[error("Calibration error: {0}")]
+    CalibrationError(String),
+
+    #[error("Clipboard error: {0}")]
+    ClipboardError(String),
+
+    #[error("Keymap error: {0}")]
+    KeymapError(String),
+
+    #[error("Config error: {0}")]
+    ConfigError(String),
+
+    #[error("Database error: {0}")]
+    DbError(#[from] rusqlite::Error),
+}
+
+struct Calibration {
+    camera_matrix: Mat,
+    dist_coeffs: Mat,
+}
+
+impl Calibration {
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = path
+            .to_str()
+            .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+        let storage = FileStorage::new(path, FileStorage_Mode::READ as i32, "")?;
+        if !storage.is_opened()? {
+            return Err(AppError::CalibrationError(format!("Cannot open {}", path)).into());
+        }
+        let camera_matrix = storage.get("camera_matrix")?.mat()?;
+        // OpenCV's calibration sample names the coefficients `distortion_coefficients`.
+        let mut dist_coeffs = storage.get("dist_coeffs")?.mat()?;
+        if dist_coeffs.empty() {
+            dist_coeffs = storage.get("distortion_coefficients")?.mat()?;
+        }
+        if camera_matrix.empty() {
+            return Err(AppError::CalibrationError(format!("No camera_matrix in {}", path)).into());
+        }
+        Ok(Calibration {
+            camera_matrix,
+            dist_coeffs,
+        })
+    }
+}
+
+struct Classifier {
+    net: Net,
+    classes: Vec<String>,
+    size: i32,
+}
+
+impl Classifier {
+    fn load(model: &Path, classes: Option<&Path>, size: i32) -> Result<Self, Box<dyn Error>> {
+        let net = read_net_from_onnx(
+            model
+                .to_str()
+                .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?,
+        )?;
+        let classes = match classes {
+            Some(path) => fs::read_to_string(path)?
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Classifier { net, classes, size })
+    }
+
+    fn class_name(&self, index: usize) -> String {
+        self.classes
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| index.to_string())
+    }
+
+    /// Class probabilities for `frame`, best first.
+    fn predict(&mut self, frame: &Mat) -> opencv::Result<Vec<(String, f32)>> {
+        let blob = blob_from_image(
+            frame,
+            1.0 / 255.0,
+            Size::new(self.size, self.size),
+            Scalar::default(),
+            true,
+            false,
+            CV_32F,
+        )?;
+        self.net.set_input_def(&blob)?;
+        let output = self.net.forward_single_def()?;
+        let mut scores = output.data_typed::<f32>()?.to_vec();
+        // Models exported without a softmax layer produce raw logits.
+        let sum: f32 = scores.iter().sum();
+        if scores.iter().any(|score| *score < 0.0) || (sum - 1.0).abs() > 0.01 {
+            let max = scores.iter().cloned().fold(f32::MIN, f32::max);
+            let total: f32 = scores.iter().map(|score| (score - max).exp()).sum();
+            for score in scores.iter_mut() {
+                *score = (*score - max).exp() / total;
+            }
+        }
+        let mut ranked: Vec<(usize, f32)> = scores.into_iter().enumerate().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(ranked
+            .into_iter()
+            .map(|(index, score)| (self.class_name(index), score))
+            .collect())
+    }
+}
+
+fn entropy(predictions: &[(String, f32)]) -> f32 {
+    predictions
+        .iter()
+        .filter(|(_, p)| *p > 0.0)
+        .map(|(_, p)| -p * p.ln())
+        .sum()
+}
+
+fn is_uncertain(
+    predictions: &[(String, f32)],
+    confidence_threshold: f32,
+    entropy_threshold: Option<f32>,
+) -> bool {
+    let confidence = predictions.first().map_or(0.0, |(_, p)| *p);
+    confidence < confidence_threshold
+        || entropy_threshold.is_some_and(|threshold| entropy(predictions) > threshold)
+}
+
+fn detect_faces(classifier: &mut CascadeClassifier, frame: &Mat) -> opencv::Result<Vector<Rect>> {
+    let mut faces = Vector::<Rect>::new();
+    classifier.detect_multi_scale_def(&to_gray(frame)?, &mut faces)?;
+    Ok(faces)
+}
+
+fn sanitize_label(label: &str) -> String {
+    label
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// `label` made safe as the class of a capture another program asked for, or why it can't be
+/// one: empty, hidden like `.` and `..`, or a directory the store keeps for itself.
+fn remote_label(label: &str) -> Result<String, String> {
+    let label = sanitize_label(label);
+    if label.is_empty() {
+        return Err("empty label".into());
+    }
+    if label.starts_with('.') {
+        return Err(format!("label {:?} starts with a dot", label));
+    }
+    if manifest::MIRROR_DIRS
+        .iter()
+        .any(|dir| dir.eq_ignore_ascii_case(&label))
+    {
+        return Err(format!("label {:?} is reserved for the store", label));
+    }
+    Ok(label)
+}
+
+fn decode_qr_label(detector: &QRCodeDetector, frame: &Mat) -> opencv::Result<Option<String>> {
+    let decoded = detector.detect_and_decode_def(frame)?;
+    let label = sanitize_label(&String::from_utf8_lossy(&decoded));
+    Ok(if label.is_empty() { None } else { Some(label) })
+}
+
+fn vec3(mat: &Mat) -> opencv::Result<[f64; 3]> {
+    Ok([*mat.at::<f64>(0)?, *mat.at::<f64>(1)?, *mat.at::<f64>(2)?])
+}
+
+fn detect_markers(
+    detector: &ArucoDetector,
+    frame: &Mat,
+    calibration: Option<&Calibration>,
+    marker_length: f64,
+) -> opencv::Result<Vec<MarkerPose>> {
+    let mut corners = Vector::<Vector<Point2f>>::new();
+    let mut ids = Vector::<i32>::new();
+    detector.detect_markers_def(frame, &mut corners, &mut ids)?;
+    let half = (marker_length / 2.0) as f32;
+    let object_points = Vector::<Point3f>::from_slice(&[
+        Point3f::new(-half, half, 0.0),
+        Point3f::new(half, half, 0.0),
+        Point3f::new(half, -half, 0.0),
+        Point3f::new(-half, -half, 0.0),
+    ]);
+    let mut markers = Vec::with_capacity(ids.len());
+    for (id, corners) in ids.iter().zip(corners.iter()) {
+        let mut marker = MarkerPose {
+            id,
+            rvec: None,
+            tvec: None,
+        };
+        if let Some(calibration) = calibration {
+            let mut rvec = Mat::default();
+            let mut tvec = Mat::default();
+            if solve_pnp(
+                &object_points,
+                &corners,
+                &calibration.camera_matrix,
+                &calibration.dist_coeffs,
+                &mut rvec,
+                &mut tvec,
+                false,
+                SOLVEPNP_IPPE_SQUARE,
+            )? {
+                marker.rvec = Some(vec3(&rvec)?);
+                marker.tvec = Some(vec3(&tvec)?);
+            }
+        }
+        markers.push(marker);
+    }
+    markers.sort_by_key(|marker| marker.id);
+    Ok(markers)
+}
+
+const HOLD_RELEASE: Duration = Duration::from_millis(600);
+const NOTICE_DURATION: Duration = Duration::from_secs(2);
+const FLASH_DURATION: Duration = Duration::from_millis(200);
+/// Longest the capture loop waits for a new frame before handling keys anyway.
+const FRAME_WAIT: Duration = Duration::from_millis(100);
+/// Smoothed read rate and read latency for the timing overlay.
+#[derive(Debug, Default)]
+struct FrameTiming {
+    last_read: Option<Instant>,
+    fps: f64,
+    read_ms: f64,
+}
+
+impl FrameTiming {
+    const SMOOTHING: f64 = 0.1;
+
+    fn record(&mut self, read_started: Instant) {
+        let now = Instant::now();
+        let read_ms = (now - read_started).as_secs_f64() * 1000.0;
+        self.read_ms += (read_ms - self.read_ms) * Self::SMOOTHING;
+        if let Some(last) = self.last_read {
+            let fps = 1.0 / (now - last).as_secs_f64().max(1e-6);
+            self.fps += (fps - self.fps) * Self::SMOOTHING;
+        }
+        self.last_read = Some(now);
+    }
+}
+
+fn motion_fraction(
+    subtractor: &mut Ptr<BackgroundSubtractorMOG2>,
+    frame: &Mat,
+) -> opencv::Result<f64> {
+    let mut mask = Mat::default();
+    BackgroundSubtractorMOG2Trait::apply(subtractor, frame, &mut mask, -1.0)?;
+    let total = (mask.rows() * mask.cols()).max(1);
+    Ok(count_non_zero(&mask)? as f64 / total as f64)
+}
+
+/// UTC `YYYY-MM-DD HH:MM:SS` for `time`.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Parses the command line and runs the command it names: by default, a capture session.
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let matches = config::command().get_matches_from(config::args()?);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Command::Source(source) = &mut args.command
+        && let Some(settings) = source.settings_mut()
+        && settings.profile.is_none()
+    {
+        settings.profile = Some(PathBuf::from(&args.store_path).join(PROFILE_FILE));
+    }
+    let source = match &args.command {
+        Command::Source(source) => source,
+        Command::Devices { max_index, backend } => return list_devices(*max_index, *backend),
+        Command::Calibrate {
+            cols,
+            rows,
+            square_size,
+            output,
+            min_views,
+            source,
+        } => {
+            let board = calibrate::Board {
+                cols: *cols,
+                rows: *rows,
+                square_size: *square_size,
+            };
+            return calibrate::run(source, &board, output, *min_views);
+        }
+        Command::Manifest { output } => {
+            return manifest::write(Path::new(&args.store_path), output);
+        }
+        Command::Export { format } => return export::run(Path::new(&args.store_path), format),
+        Command::Stats { json } => return stats::run(Path::new(&args.store_path), *json),
+        Command::Dedupe {
+            distance,
+            dry_run,
+            quarantine,
+        } => {
+            return dedupe::run(
+                Path::new(&args.store_path),
+                *distance,
+                *dry_run,
+                quarantine.as_ref(),
+            );
+        }
+        Command::Validate { delete, reindex } => {
+            return validate::run(
+                Path::new(&args.store_path),
+                &args.name_template,
+                *delete,
+                *reindex,
+            );
+        }
+        Command::Annotate { keypoints } => {
+            let store_path = Path::new(&args.store_path);
+            return match keypoints {
+                Some(path) => keypoints::run(store_path, &keypoints::Skeleton::load(path)?),
+                None => annotate::run(store_path),
+            };
+        }
+        Command::Review => return review::run(Path::new(&args.store_path), &args.name_template),
+        Command::Reindex => {
+            return Ok(index::reindex(
+                Path::new(&args.store_path),
+                &args.name_template,
+            )?);
+        }
+        Command::Balance {
+            target,
+            seed,
+            prune,
+            quarantine,
+        } => {
+            return balance::run(
+                Path::new(&args.store_path),
+                *target,
+                *seed,
+                *prune,
+                quarantine.as_ref(),
+            );
+        }
+        Command::Bench { frames, source } => return bench::run(source, *frames),
+        Command::Merge { src, dst, manifest } => {
+            return merge::run(src, dst, &args.name_template, manifest.as_ref());
+        }
+        Command::Split {
+            ratios,
+            seed,
+            stratify,
+            output,
+            manifest,
+        } => {
+            return split::run(
+                Path::new(&args.store_path),
+                ratios,
+                *seed,
+                *stratify,
+                output,
+                manifest.as_ref(),
+            );
+        }
+    };
+
+    if args.gpu {
+        gpu::enable()?;
+    }
+    let videos = open_source(source)?;
+    let cam_names: Vec<String> = match source {
+        VideoSource::Multicam {
+            stereo: true,
+            devices,
+            ..
+        } => {
+            if devices.len() != 2 {
+                return Err(AppError::SourceError(format!(
+                    "A stereo pair needs exactly 2 devices, got {}",
+                    devices.len()
+                ))
+                .into());
+            }
+            vec!["left".into(), "right".into()]
+        }
+        VideoSource::Multicam { devices, .. } => {
+            devices.iter().map(|d| format!("cam{}", d)).collect()
+        }
+        VideoSource::Depth { .. } => vec!["color".into(), "depth".into()],
+        _ => Vec::new(),
+    };
+    let depth = match source {
+        VideoSource::Depth { sensor, .. } => Some(*sensor),
+        _ => None,
+    };
+    let profile_path = source
+        .settings()
+        .and_then(|settings| settings.profile.clone())
+        .unwrap_or_else(|| PathBuf::from(&args.store_path).join(PROFILE_FILE));
+    // The class map outlives sessions, so it is looked up in the store path itself.
+    let root_path = PathBuf::from(&args.store_path);
+    let mut session = args
+        .session
+        .as_ref()
+        .map(|name| session::Session::start(name.as_deref()));
+    if let Some(session) = &session {
+        args.store_path = root_path.join(&session.name).to_string_lossy().into_owned();
+        println!("session {}", session.name);
+    }
+    let _ = create_data_dir(&args.store_path);
+    if let Some(session) = &session {
+        session.save(Path::new(&args.store_path))?;
+    }
+    let lock = index::StoreLock::open(Path::new(&args.store_path))?;
+    let mut indice_map = match index::load_state(Path::new(&args.store_path))? {
+        Some(indice_map) => indice_map,
+        None => HashMap::<String, i32>::from_data_path(&args.store_path, &args.name_template)?,
+    };
+    lock.update(Path::new(&args.store_path), &mut indice_map, |_| ())?;
+    println!("{:?}", indice_map);
+
+    let mut store_imgs = Vec::with_capacity(videos.len());
+    for video in &videos {
+        let width = video.width()?;
+        let height = video.height()?;
+        // Backends report the decoded Mat type through CAP_PROP_FORMAT, or -1 when unknown.
+        let format = video.get(CAP_PROP_FORMAT)? as i32;
+        let typ = if format > 0 { format } else { CV_8UC3 };
+        store_imgs.push(Mat::new_size_with_default(
+            Size::new(width, height),
+            typ,
+            Scalar::all(0.0),
+        )?);
+    }
+    if depth.is_some() {
+        store_imgs.push(Mat::default());
+    }
+    let calibration = match &args.calibration {
+        Some(path) => Some(Calibration::load(path)?),
+        None => None,
+    };
+    let class_map = match &args.class_map {
+        Some(path) => {
+            let class_map = classes::ClassMap::load(path)?;
+            let copy = root_path.join(classes::STORE_FILE);
+            if copy.canonicalize().ok() != Some(path.canonicalize()?) {
+                fs::copy(path, copy)?;
+            }
+            class_map
+        }
+        None => classes::ClassMap::from_store(&root_path)?,
+    };
+    let keymap = match &args.keymap {
+        Some(path) => Keymap::load(path)?,
+        None => Keymap::default(),
+    };
+    // Actions take their keys first, so a class on one of them could never be captured.
+    if let Some((key, action)) = class_map
+        .keys()
+        .find_map(|key| Some((key, keymap.bound(key)?)))
+    {
+        return Err(AppError::KeymapError(format!(
+            "class key {:?} is bound to {}; rebind it with --keymap or pick another key",
+            key, action
+        ))
+        .into());
+    }
+    let mut notifier = notify::Notifier::default();
+    if let Some(url) = &args.webhook {
+        notifier.add(webhook::Webhook::spawn(url));
+    }
+    let mut triggers = None;
+    if let Some(url) = &args.mqtt {
+        let (host, port) = mqtt::broker(url)?;
+        notifier.add(mqtt::Publisher::spawn(&host, &port, &args.topic));
+        if let Some(topic) = &args.mqtt_trigger {
+            triggers = Some(mqtt::Subscription::spawn(&host, &port, topic)?);
+        }
+    }
+    let server = match &args.listen {
+        Some(address) => Some(http::Server::listen(address)?),
+        None => None,
+    };
+    let sink: Arc<dyn FrameSink> = match &args.store {
+        Some(url) => Arc::new(s3::S3Sink::open(url, &root_path)?),
+        None => Arc::new(LocalDir),
+    };
+    let devices: Vec<Option<i32>> = match source {
+        VideoSource::Capture { device, .. } => vec![Some(*device)],
+        VideoSource::Multicam { devices, .. } => devices.iter().map(|d| Some(*d)).collect(),
+        VideoSource::Depth { device, .. } => vec![Some(*device)],
+        _ => vec![None],
+    };
+    let mut recorder = Recorder {
+        store_path: args.store_path.clone(),
+        cam_names,
+        indice_map,
+        reserved: HashMap::new(),
+        ext: args.format.extension(),
+        compression_params: args.format.params(args.quality, args.png_compression),
+        png_params: ImageFormat::Png.params(None, args.png_compression),
+        markers: Vec::new(),
+        min_sharpness: args.min_sharpness,
+        max_clipping: args.max_clipping,
+        dedupe_distance: args.dedupe_distance,
+        last_hash: None,
+        roi: None,
+        out_size: args.out_size,
+        fit: args.fit,
+        color: args.color,
+        rotate: args.rotate,
+        flip: args.flip,
+        gpu: args.gpu,
+        calibration,
+        depth,
+        watermark: args.watermark.clone(),
+        keep_clean: args.keep_clean,
+        thumbs: args.thumbs,
+        keep_raw: args.keep_raw,
+        root: root_path.canonicalize()?,
+        sidecar: args.sidecar,
+        cameras: Vec::new(),
+        devices,
+        exif: args.exif,
+        session: session.as_ref().map(|session| session.name.clone()),
+        manifest: args.append_manifest.clone(),
+        on_save: args
+            .on_save
+            .as_ref()
+            .map(|template| Arc::new(hook::Hooks::spawn(template, args.on_save_jobs))),
+        notifier: (!notifier.is_empty()).then(|| Arc::new(notifier)),
+        class_map,
+        target: args.target,
+        counts: HashMap::new(),
+        max_per_class: args.max_per_class,
+        notice: None,
+        history: Vec::new(),
+        save_times: VecDeque::new(),
+        thumbnail: None,
+        beep: args.beep,
+        flash: None,
+        name_template: args.name_template.clone(),
+        // Once a store has a database, every capture run keeps it current.
+        db: if args.db {
+            Some(Db::open(&root_path)?)
+        } else {
+            Db::existing(&root_path)?
+        },
+        lock,
+        sink: Arc::clone(&sink),
+        writer: Writer::spawn(sink),
+        events: Arc::new(Events::open(args.event_log.as_deref())?),
+        started: Instant::now(),
+        saved: BTreeMap::new(),
+    };
+    if let Some(notifier) = &recorder.notifier {
+        notifier.send(
+            "session_start",
+            &notify::SessionStart {
+                session: recorder.session.as_deref(),
+                store_path: &args.store_path,
+                cameras: &recorder.cam_names,
+            },
+        );
+    }
+    recorder.seed_counts()?;
+    named_window("video", WINDOW_NORMAL | WINDOW_KEEPRATIO)?;
+    if let Some(size) = args.window_size {
+        resize_window("video", size.width, size.height)?;
+    }
+    let roi_selection = Arc::new(Mutex::new(RoiSelection::default()));
+    track_roi(&roi_selection)?;
+    let mut last_interval_save = Instant::now();
+    let mut interval_saved = 0;
+    let mut motion = if args.motion_trigger {
+        Some(create_background_subtractor_mog2(500, 16.0, false)?)
+    } else {
+        None
+    };
+    let mut last_motion_save: Option<Instant> = None;
+    let mut face_classifier = match &args.face {
+        Some(path) => Some(CascadeClassifier::new(
+            path.to_str()
+                .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?,
+        )?),
+        None => None,
+    };
+    let mut last_face_save: Option<Instant> = None;
+    let mut faces = Vector::<Rect>::new();
+    let qr_detector = if args.qr_label {
+        Some(QRCodeDetector::default()?)
+    } else {
+        None
+    };
+    let aruco_detector = if args.aruco {
+        Some(ArucoDetector::new(
+            &get_predefined_dictionary(PredefinedDictionaryType::DICT_4X4_50)?,
+            &DetectorParameters::default()?,
+            RefineParameters::new_def()?,
+        )?)
+    } else {
+        None
+    };
+    let mut classifier = match &args.model {
+        Some(model) => Some(Classifier::load(
+            model,
+            args.classes.as_deref(),
+            args.model_size,
+        )?),
+        None => None,
+    };
+    let mut predictions: Vec<(String, f32)> = Vec::new();
+    let mut controls = Controls {
+        show_grid: args.grid.is_some() || args.crosshair,
+        active_label: args.label.clone(),
+        ..Controls::default()
+    };
+    // Ctrl-C and SIGTERM end the loop between frames, so no capture is cut off mid-write; a
+    // second signal exits right away.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = Arc::clone(&interrupted);
+        ctrlc::set_handler(move || {
+            if interrupted.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+        })?;
+    }
+    let reconnect = match source {
+        VideoSource::Stream {
+            url,
+            timeout_ms,
+            reconnect_attempts,
+        } => Some((url.clone(), *timeout_ms, *reconnect_attempts)),
+        _ => None,
+    };
+    let file = matches!(source, VideoSource::File { .. });
+    let pre_roll = args.pre_roll.map(Duration::from_secs_f64);
+    let keys = Keys {
+        keymap,
+        settings: source.settings(),
+        profile_path,
+        pre_roll,
+        burst: args.burst,
+        hold: args.hold,
+        countdown: args.countdown.is_some(),
+        active_learning: args.active_learning,
+        confidence_threshold: args.confidence_threshold,
+        entropy_threshold: args.entropy_threshold,
+        detected_labels: qr_detector.is_some() || aruco_detector.is_some(),
+        faces: face_classifier.is_some(),
+    };
+    let mut capture = Capture::spawn(
+        videos,
+        &store_imgs,
+        depth,
+        reconnect,
+        file,
+        pre_roll,
+        Arc::clone(&recorder.events),
+    )?;
+    // Errors end the loop like the quit key does, so the shutdown below still runs.
+    let outcome = (|| -> Result<(), Box<dyn Error>> {
+        loop {
+            if interrupted.load(Ordering::SeqCst) {
+                println!("interrupted, shutting down");
+                break;
+            }
+            recorder.check_writes()?;
+            // Frame-driven work (detection, automatic captures) only runs on frames it hasn't seen.
+            let fresh = if controls.paused {
+                capture.skip();
+                false
+            } else {
+                capture.next(&mut store_imgs, FRAME_WAIT)?
+            };
+            if let (Some(seconds), Some((label, started))) = (args.countdown, &controls.countdown)
+                && started.elapsed().as_secs_f64() >= seconds
+            {
+                let label = label.clone();
+                capture.saving();
+                recorder.save_burst(&label, &mut capture.videos(), &mut store_imgs, args.burst)?;
+                controls.countdown = None;
+            }
+            for message in triggers.iter().flat_map(|triggers| triggers.received()) {
+                let label = match (message.trim(), &args.label) {
+                    ("", Some(label)) => label.clone(),
+                    ("", None) => {
+                        println!("remote capture without a label, skipping");
+                        continue;
+                    }
+                    (message, _) => match remote_label(message) {
+                        Ok(label) => label,
+                        Err(reason) => {
+                            println!("skipping remote capture: {}", reason);
+                            continue;
+                        }
+                    },
+                };
+                capture.saving();
+                recorder.save_burst(&label, &mut capture.videos(), &mut store_imgs, args.burst)?;
+            }
+            let mut stop = false;
+            for call in server.iter().flat_map(|server| server.calls()) {
+                let (status, body) = match &call.request {
+                    http::Request::Capture { label } => {
+                        let label = match (label.as_deref(), &args.label) {
+                            (Some(label), _) => remote_label(label),
+                            (None, Some(label)) => Ok(label.clone()),
+                            (None, None) => Err("no label and no --label".to_string()),
+                        };
+                        match label {
+                            Ok(label) => {
+                                capture.saving();
+                                // A failed capture is the caller's to retry; the session goes on.
+                                match recorder.save_burst(
+                                    &label,
+                                    &mut capture.videos(),
+                                    &mut store_imgs,
+                                    args.burst,
+                                ) {
+                                    Ok(true) => {
+                                        let count = recorder.class_count(&label);
+                                        (
+                                            200,
+                                            serde_json::to_string(
+                                                &json!({ "label": label, "count": count }),
+                                            )?,
+                                        )
+                                    }
+                                    Ok(false) => (
+                                        409,
+                                        http::error(
+                                            "not saved: the class is full or the frame was \
+                                             rejected as blurry, badly exposed or a duplicate",
+                                        ),
+                                    ),
+                                    Err(e) => {
+                                        println!("remote capture failed: {}", e);
+                                        (500, http::error(&e.to_string()))
+                                    }
+                                }
+                            }
+                            Err(reason) => (400, http::error(&reason)),
+                        }
+                    }
+                    http::Request::Camera => {
+                        (200, serde_json::to_string(&read_props(&capture.videos()))?)
+                    }
+                    http::Request::SetCamera { props } => {
+                        // Checked up front, so a bad request leaves the cameras as they were.
+                        let settings: Result<Vec<_>, String> = props
+                            .iter()
+                            .map(|(name, value)| {
+                                let prop = PROPS.iter().find(|(known, _, _)| known == name);
+                                match (prop, value.parse::<f64>()) {
+                                    (Some((_, _, set)), Ok(parsed)) => Ok((name, *set, parsed)),
+                                    _ => Err(format!("cannot set {} to {:?}", name, value)),
+                                }
+                            })
+                            .collect();
+                        match settings {
+                            Ok(settings) => {
+                                let mut videos = capture.videos();
+                                let mut failed = None;
+                                for (name, set, value) in settings {
+                                    for video in videos.iter_mut() {
+                                        if let Err(e) = set(&mut **video, value) {
+                                            failed = Some(format!("cannot set {}: {}", name, e));
+                                        }
+                                    }
+                                }
+                                match failed {
+                                    Some(message) => (400, http::error(&message)),
+                                    None => (200, serde_json::to_string(&read_props(&videos))?),
+                                }
+                            }
+                            Err(message) => (400, http::error(&message)),
+                        }
+                    }
+                    http::Request::Counts => {
+                        let counts: BTreeMap<_, _> = recorder.counts.iter().collect();
+                        (200, serde_json::to_string(&counts)?)
+                    }
+                    http::Request::Stop => {
+                        stop = true;
+                        (200, serde_json::to_string(&json!({ "stopping": true }))?)
+                    }
+                };
+                call.reply(status, body);
+            }
+            if stop {
+                println!("stop requested over HTTP");
+                break;
+            }
+            if fresh && let (Some(subtractor), Some(label)) = (&mut motion, &args.label) {
+                let fraction = motion_fraction(subtractor, &store_imgs[0])?;
+                let cooled_down = last_motion_save
+                    .is_none_or(|saved| saved.elapsed().as_secs_f64() >= args.motion_cooldown);
+                if fraction >= args.motion_threshold && cooled_down {
+                    println!("motion detected ({:.3})", fraction);
+                    recorder.read_cameras(&capture.videos());
+                    recorder.save_frames(label, &store_imgs)?;
+                    last_motion_save = Some(Instant::now());
+                }
+            }
+            if let Some(detector) = &qr_detector
+                && let Some(label) = decode_qr_label(detector, &store_imgs[0])?
+                && controls.active_label.as_ref() != Some(&label)
+            {
+                println!("QR label {}", label);
+                if args.qr_capture {
+                    capture.saving();
+                    recorder.read_cameras(&capture.videos());
+                    recorder.save_frames(&label, &store_imgs)?;
+                }
+                controls.active_label = Some(label);
+            }
+            if let Some(detector) = &aruco_detector {
+                recorder.markers = detect_markers(
+                    detector,
+                    &store_imgs[0],
+                    recorder.calibration.as_ref(),
+                    args.marker_length,
+                )?;
+                if let Some(marker) = recorder.markers.first() {
+                    let label = marker.id.to_string();
+                    if controls.active_label.as_ref() != Some(&label) {
+                        println!("ArUco label {}", label);
+                        controls.active_label = Some(label);
+                    }
+                }
+            }
+            if fresh && let Some(classifier) = &mut face_classifier {
+                faces = detect_faces(classifier, &store_imgs[0])?;
+                let cooled_down = last_face_save
+                    .is_none_or(|saved| saved.elapsed().as_secs_f64() >= args.face_cooldown);
+                if let Some(label) = &controls.active_label
+                    && !faces.is_empty()
+                    && cooled_down
+                {
+                    recorder.read_cameras(&capture.videos());
+                    if args.face_crop {
+                        recorder.save_faces(label, &store_imgs, |frame| {
+                            detect_faces(classifier, frame)
+                        })?;
+                    } else {
+                        recorder.save_frames(label, &store_imgs)?;
+                    }
+                    last_face_save = Some(Instant::now());
+                }
+            }
+            if fresh && let Some(classifier) = &mut classifier {
+                predictions = classifier.predict(&store_imgs[0])?;
+            }
+            let mut preview = if store_imgs.len() > 1 {
+                mirror(tile_frames(&store_imgs)?, args.mirror_preview)?
+            } else {
+                mirror(to_display(&store_imgs[0])?, args.mirror_preview)?
+            };
+            // Mouse positions and overlays live in preview space; map them back when mirrored.
+            let preview_cols = preview.cols();
+            let to_preview = |rect: Rect| {
+                if args.mirror_preview {
+                    Rect::new(
+                        preview_cols - rect.x - rect.width,
+                        rect.y,
+                        rect.width,
+                        rect.height,
+                    )
+                } else {
+                    rect
+                }
+            };
+            // The histogram reads the frame, so it goes in before the zebra stripes.
+            if controls.show_histogram {
+                overlay::histogram(&mut preview)?;
+            }
+            if controls.show_zebra {
+                overlay::zebra(&mut preview, args.zebra_threshold)?;
+            }
+            if controls.show_grid {
+                let grid = args.grid.unwrap_or(OutSize {
+                    width: 3,
+                    height: 3,
+                });
+                overlay::grid(&mut preview, grid.width, grid.height, args.crosshair)?;
+            }
+            for (rank, (name, score)) in predictions.iter().take(args.top_k).enumerate() {
+                let org = Point::new(10, 30 + 30 * rank as i32);
+                let text = format!("{}. {} {:.2}", rank + 1, name, score);
+                draw_text(&mut preview, &text, org, 0.8)?;
+            }
+            let uncertain = keys.uncertain(&predictions);
+            if args.active_learning && uncertain {
+                let org = Point::new(10, preview.rows() - 20);
+                draw_text(&mut preview, "uncertain - label this frame", org, 0.8)?;
+            }
+            // Classes with a target always show their progress; `=` adds every other class.
+            let mut progress: Vec<(&String, usize, Option<usize>)> = recorder
+                .counts
+                .iter()
+                .map(|(label, count)| (label, *count, recorder.target(label)))
+                .filter(|(_, _, target)| controls.show_counts || target.is_some())
+                .collect();
+            progress.sort();
+            for (row, (label, count, target)) in progress.into_iter().enumerate() {
+                let text = match target {
+                    Some(target) => format!("{}: {}/{}", label, count, target),
+                    None => format!("{}: {}", label, count),
+                };
+                let org = Point::new(preview.cols() - 220, 30 + 25 * row as i32);
+                draw_text(&mut preview, &text, org, 0.6)?;
+            }
+            if let Some(peak) = &mut controls.focus_peak {
+                let score = sharpness(&store_imgs[0])?;
+                *peak = peak.max(score);
+                let text = format!("sharpness {:.1} (peak {:.1})", score, peak);
+                let org = Point::new(10, preview.rows() - 110);
+                draw_text(&mut preview, &text, org, 0.6)?;
+            }
+            if controls.show_timing {
+                let (fps, read_ms) = capture.timing();
+                let text = format!(
+                    "{:.1} fps  read {:.1} ms  saves {:.1}/s",
+                    fps,
+                    read_ms,
+                    recorder.save_rate()
+                );
+                let org = Point::new(10, preview.rows() - 80);
+                draw_text(&mut preview, &text, org, 0.6)?;
+            }
+            if let Some((frame, saved)) = &recorder.thumbnail
+                && (controls.keep_thumbnail || saved.elapsed() < NOTICE_DURATION)
+            {
+                overlay::thumbnail(&mut preview, frame)?;
+            }
+            if controls.paused {
+                let org = Point::new(preview.cols() - 120, preview.rows() - 20);
+                draw_text(&mut preview, "PAUSED", org, 0.8)?;
+            }
+            if let Some((text, shown)) = &recorder.notice {
+                if shown.elapsed() < NOTICE_DURATION {
+                    let org = Point::new(10, preview.rows() / 2);
+                    draw_text(&mut preview, text, org, 1.0)?;
+                } else {
+                    recorder.notice = None;
+                }
+            }
+            let label_text = match (&controls.typing, &controls.typed_label) {
+                (Some(buffer), _) => Some(format!("label: {}_", buffer)),
+                (None, Some(label)) => Some(format!("label: {}", label)),
+                (None, None) => None,
+            };
+            if let Some(text) = label_text {
+                let org = Point::new(10, preview.rows() - 50);
+                draw_text(&mut preview, &text, org, 0.8)?;
+            }
+            if let (Some(seconds), Some((label, started))) = (args.countdown, &controls.countdown) {
+                let remaining = (seconds - started.elapsed().as_secs_f64()).ceil().max(1.0);
+                let org = Point::new(preview.cols() / 2 - 60, preview.rows() / 2);
+                draw_text(&mut preview, &format!("{} {}", label, remaining), org, 2.0)?;
+            }
+            if store_imgs.len() == 1 {
+                for face in &faces {
+                    rectangle(
+                        &mut preview,
+                        to_preview(face),
+                        Scalar::new(0.0, 255.0, 0.0, 0.0),
+                        2,
+                        LINE_AA,
+                        0,
+                    )?;
+                }
+            }
+            if let Ok(selection) = roi_selection.lock() {
+                recorder.roi = selection.roi.map(to_preview);
+                if let Some(roi) = selection.roi {
+                    rectangle(
+                        &mut preview,
+                        roi,
+                        Scalar::new(255.0, 0.0, 0.0, 0.0),
+                        2,
+                        LINE_AA,
+                        0,
+                    )?;
+                }
+                if let Some(anchor) = selection.anchor {
+                    rectangle(
+                        &mut preview,
+                        rect_between(anchor, selection.current),
+                        Scalar::new(0.0, 255.0, 255.0, 0.0),
+                        1,
+                        LINE_AA,
+                        0,
+                    )?;
+                }
+            }
+            if let Some((saved, at)) = recorder.flash
+                && args.flash
+                && at.elapsed() < FLASH_DURATION
+            {
+                let color = if saved {
+                    Scalar::new(0.0, 255.0, 0.0, 0.0)
+                } else {
+                    Scalar::new(0.0, 0.0, 255.0, 0.0)
+                };
+                let border = Rect::new(0, 0, preview.cols(), preview.rows());
+                rectangle(&mut preview, border, color, 12, LINE_8, 0)?;
+            }
+            if controls.show_help {
+                let mut lines: Vec<String> = HELP.iter().map(|line| line.to_string()).collect();
+                lines.extend(keys.keymap.describe());
+                lines.extend(recorder.class_map.describe());
+                overlay::help(&mut preview, &lines)?;
+            }
+            if imshow("video", &preview).is_err() {
+                break;
+            }
+            if fresh && let Some(server) = &server {
+                server.show(&preview)?;
+            }
+
+            if fresh && let Some((label, seen)) = &controls.held {
+                if seen.elapsed() < HOLD_RELEASE {
+                    recorder.read_cameras(&capture.videos());
+                    recorder.save_unique(label, &store_imgs)?;
+                } else {
+                    println!("stop recording {}", label);
+                    controls.held = None;
+                }
+            }
+
+            if !controls.paused
+                && let (Some(interval), Some(label)) = (args.interval, &args.label)
+                && last_interval_save.elapsed().as_secs_f64() >= interval
+            {
+                capture.saving();
+                recorder.read_cameras(&capture.videos());
+                recorder.save_frames(label, &store_imgs)?;
+                last_interval_save = Instant::now();
+                interval_saved += 1;
+                if args.count.is_some_and(|count| interval_saved >= count) {
+                    break;
+                }
+            }
+
+            // New frames pace the loop; while paused there are none to wait for.
+            if let Ok(key) = wait_key_ex(if controls.paused { 100 } else { 1 })
+                && key != -1
+                && keys.press(
+                    key,
+                    &mut controls,
+                    &mut recorder,
+                    &capture,
+                    &mut store_imgs,
+                    &predictions,
+                )?
+            {
+                break;
+            }
+        }
+        Ok(())
+    })();
+    if let Err(e) = &outcome {
+        println!("stopping: {}", e);
+    }
+    for video in capture.stop().iter_mut() {
+        let _ = video.release();
+    }
+    // Captures and manifest lines are written as they happen, so only the queue and the
+    // summary are left.
+    recorder.writer.flush();
+    recorder.check_writes()?;
+    recorder.release_indices()?;
+    recorder.summary();
+    if let Some(notifier) = &recorder.notifier {
+        notifier.send(
+            "session_end",
+            &notify::SessionEnd {
+                session: recorder.session.as_deref(),
+                seconds: recorder.started.elapsed().as_secs_f64(),
+                captures: &recorder.saved,
+            },
+        );
+    }
+    if let Some(session) = &mut session {
+        session.finish(Path::new(&args.store_path), &recorder.saved)?;
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_label_replaces_path_and_control_characters() {
+        assert_eq!(sanitize_label("  red/blue: a*b  "), "red_blue_ a_b");
+        assert_eq!(sanitize_label("tab\there"), "tab_here");
+        assert_eq!(sanitize_label("café"), "café");
+    }
+
+    #[test]
+    fn remote_label_refuses_hidden_and_reserved_labels() {
+        assert_eq!(remote_label(" cat ").unwrap(), "cat");
+        assert_eq!(remote_label("red/blue").unwrap(), "red_blue");
+        for bad in [
+            "", "   ", ".", "..", "../etc", ".spool", "thumbs", "RAW", "Clean",
+        ] {
+            assert!(remote_label(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::{self, Sample};
+use crate::{AppError, timestamp};
+
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "tiff"];
+
+/// `--thumbs` previews, `--keep-raw` originals and `--keep-clean` copies without the
+/// watermark, kept out of the dataset.
+pub const THUMBS_DIR: &str = "thumbs";
+pub const RAW_DIR: &str = "raw";
+pub const CLEAN_DIR: &str = "clean";
+pub const MIRROR_DIRS: [&str; 3] = [THUMBS_DIR, RAW_DIR, CLEAN_DIR];
+/// Captures waiting to be uploaded to an `s3://` store.
+pub const SPOOL_DIR: &str = ".spool";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub path: String,
+    pub label: String,
+    pub timestamp: String,
+    pub sha256: String,
+}
+
+impl Entry {
+    pub fn from_sample(store_path: &Path, sample: &Sample) -> Result<Self, AppError> {
+        let path = &sample.path;
+        let relative = path.strip_prefix(store_path).unwrap_or(path);
+        let modified = fs::metadata(path)?.modified()?;
+        let sha256 = Sha256::digest(fs::read(path)?)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Ok(Entry {
+            path: relative.to_string_lossy().into_owned(),
+            label: sample.label.clone(),
+            timestamp: timestamp(modified),
+            sha256,
+        })
+    }
+
+    fn csv_row(&self) -> String {
+        [&self.path, &self.label, &self.timestamp, &self.sha256]
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+const CSV_HEADER: &str = "path,label,timestamp,sha256";
+
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn is_jsonl(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "jsonl" || ext == "json")
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Every image under `store_path` outside the mirror trees and `.spool/`, sorted by path.
+pub fn images(store_path: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mirrors = [THUMBS_DIR, RAW_DIR, CLEAN_DIR, SPOOL_DIR].map(|dir| store_path.join(dir));
+    let mut images = Vec::new();
+    for entry in glob::glob(&store_path.join("**/*").to_string_lossy())? {
+        let entry = entry?;
+        if entry.is_file() && is_image(&entry) && !mirrors.iter().any(|dir| entry.starts_with(dir))
+        {
+            images.push(entry);
+        }
+    }
+    images.sort();
+    Ok(images)
+}
+
+fn line(entry: &Entry, jsonl: bool) -> Result<String, AppError> {
+    if jsonl {
+        Ok(serde_json::to_string(entry)?)
+    } else {
+        Ok(entry.csv_row())
+    }
+}
+
+/// Writes a CSV or JSONL (chosen by `output`'s extension) listing of `store_path`.
+pub fn write(store_path: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let jsonl = is_jsonl(output);
+    let mut text = String::new();
+    if !jsonl {
+        text.push_str(CSV_HEADER);
+        text.push('\n');
+    }
+    let samples = dataset::from_paths(store_path, images(store_path)?);
+    for sample in &samples {
+        text.push_str(&line(&Entry::from_sample(store_path, sample)?, jsonl)?);
+        text.push('\n');
+    }
+    fs::write(output, text)?;
+    println!("write {} entries to {:?}", samples.len(), output);
+    Ok(())
+}
+
+/// Appends one image to the manifest at `output`, creating it if needed.
+pub fn append(store_path: &Path, output: &Path, image: &Path) -> Result<(), AppError> {
+    let store_path = store_path.canonicalize()?;
+    let Some(sample) = dataset::from_paths(&store_path, vec![image.to_path_buf()]).pop() else {
+        return Ok(());
+    };
+    let jsonl = is_jsonl(output);
+    let new_file = !output.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(output)?;
+    if new_file && !jsonl {
+        writeln!(file, "{}", CSV_HEADER)?;
+    }
+    writeln!(
+        file,
+        "{}",
+        line(&Entry::from_sample(&store_path, &sample)?, jsonl)?
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_manifest_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn put(store: &Path, relative: &str, data: &[u8]) {
+        let path = store.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("cat/0.png"), "cat/0.png");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn write_lists_images_with_label_and_checksum() {
+        let store = scratch("write");
+        put(&store, "cat/0.png", b"abc");
+        put(&store, "cat/0.json", b"{}");
+        put(&store, "thumbs/cat/0.png", b"abc");
+        let output = store.join("manifest.csv");
+        write(&store, &output).unwrap();
+        let text = fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(fields[0], Path::new("cat").join("0.png").to_string_lossy());
+        assert_eq!(fields[1], "cat");
+        assert_eq!(
+            fields[3],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn write_labels_session_captures_by_their_class() {
+        let store = scratch("session");
+        put(&store, "2024-05-01_12-00-00/session.toml", b"");
+        put(&store, "2024-05-01_12-00-00/cat/0.png", b"abc");
+        put(&store, "dog/0.png", b"abc");
+        let output = store.join("manifest.jsonl");
+        write(&store, &output).unwrap();
+        let labels: Vec<String> = fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+                entry["label"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(labels, ["cat", "dog"]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::naming::NameTemplate;
+use crate::{db, index, manifest, session};
+
+/// `name` in `dir`, or `<stem>_<n>.<ext>` with the first `n` that is not taken.
+fn free_name(dir: &Path, name: &str) -> PathBuf {
+    let target = dir.join(name);
+    if !target.exists() {
+        return target;
+    }
+    let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    (1..)
+        .map(|n| dir.join(format!("{}_{}.{}", stem, n, ext)))
+        .find(|target| !target.exists())
+        .unwrap_or(target)
+}
+
+/// Copies every image (and its sidecars) from `src` into the same class directories under
+/// `dst`. Captures named from `template` are renumbered after the last index already in the
+/// target directory, keeping the rest of their names; other names get a numeric suffix when
+/// they clash. Session directories keep
+/// their `session.toml`, so they stay sessions rather than becoming classes.
+pub fn run(
+    src: &Path,
+    dst: &Path,
+    template: &NameTemplate,
+    manifest: Option<&PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    index::invalidate(dst)?;
+    let mut dirs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for entry in glob::glob(&src.join("**/*").to_string_lossy())? {
+        let entry = entry?;
+        if !entry.is_file() {
+            continue;
+        }
+        if let Some(parent) = entry.parent() {
+            dirs.entry(parent.to_path_buf()).or_default().push(entry);
+        }
+    }
+
+    let mut copied = 0;
+    for (dir, files) in dirs {
+        let target_dir = dst.join(dir.strip_prefix(src).unwrap_or(&dir));
+        let mut groups: BTreeMap<u64, Vec<(PathBuf, String)>> = BTreeMap::new();
+        let mut others = Vec::new();
+        let mut marker = None;
+        for file in files {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            if name == session::FILE && dir.parent() == Some(src) {
+                marker = Some(file);
+            } else if let Some((index, _)) = index::split_name(&name, template) {
+                let name = name.into_owned();
+                groups.entry(index).or_default().push((file, name));
+            } else if manifest::is_image(&file) {
+                others.push(file);
+            }
+        }
+        if groups.is_empty() && others.is_empty() && marker.is_none() {
+            continue;
+        }
+        fs::create_dir_all(&target_dir)?;
+        if let Some(marker) = marker
+            && !target_dir.join(session::FILE).exists()
+        {
+            fs::copy(marker, target_dir.join(session::FILE))?;
+        }
+        let first = index::next_free(&target_dir, template)?;
+        for (next, files) in (first..).zip(groups.values()) {
+            for (file, name) in files {
+                let renamed = template.rename(name, next, None).unwrap_or(name.clone());
+                fs::copy(file, target_dir.join(renamed))?;
+                if manifest::is_image(file) {
+                    copied += 1;
+                }
+            }
+        }
+        for file in others {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            fs::copy(&file, free_name(&target_dir, &name))?;
+            copied += 1;
+        }
+    }
+    println!("merge {} images from {:?} into {:?}", copied, src, dst);
+
+    db::sync(dst)?;
+    if let Some(output) = manifest {
+        manifest::write(dst, output)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! `--mqtt tcp://host:1883`: capture and session events are published to `--topic`, and with
+//! `--mqtt-trigger TOPIC` each message on that topic captures to the label it carries. Both go
+//! through the mosquitto clients, `mosquitto_pub` and `mosquitto_sub`, each keeping one
+//! connection to the broker.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::AppError;
+use crate::notify::Listener;
+
+/// Host and port of a `tcp://` or `mqtt://` broker URL.
+pub fn broker(url: &str) -> Result<(String, String), AppError> {
+    let address = url
+        .strip_prefix("tcp://")
+        .or_else(|| url.strip_prefix("mqtt://"))
+        .unwrap_or(url)
+        .trim_end_matches('/');
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port),
+        None => (address, "1883"),
+    };
+    if host.is_empty() || port.parse::<u16>().is_err() {
+        return Err(AppError::ConfigError(format!(
+            "invalid MQTT broker {}",
+            url
+        )));
+    }
+    Ok((host.to_string(), port.to_string()))
+}
+
+pub struct Publisher {
+    messages: Option<Sender<String>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// A `mosquitto_pub` publishing each line of its input to `topic`.
+fn publish(host: &str, port: &str, topic: &str) -> std::io::Result<(Child, ChildStdin)> {
+    let mut child = Command::new("mosquitto_pub")
+        .args(["-h", host, "-p", port, "-t", topic, "-l"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or(std::io::Error::other("no stdin"))?;
+    Ok((child, stdin))
+}
+
+impl Publisher {
+    pub fn spawn(host: &str, port: &str, topic: &str) -> Self {
+        let (messages, queue) = mpsc::channel::<String>();
+        let (host, port, topic) = (host.to_string(), port.to_string(), topic.to_string());
+        let handle = thread::spawn(move || {
+            let mut client: Option<(Child, ChildStdin)> = None;
+            for message in queue {
+                // A client that lost the broker has exited; the next message starts another.
+                if let Some((_, stdin)) = &mut client
+                    && writeln!(stdin, "{}", message).is_ok()
+                {
+                    continue;
+                }
+                if let Some((mut child, _)) = client.take() {
+                    let _ = child.wait();
+                }
+                match publish(&host, &port, &topic) {
+                    Ok((child, mut stdin)) => {
+                        if let Err(e) = writeln!(stdin, "{}", message) {
+                            println!("cannot publish to {}: {}", topic, e);
+                        }
+                        client = Some((child, stdin));
+                    }
+                    Err(e) => println!("cannot run mosquitto_pub: {}", e),
+                }
+            }
+            if let Some((mut child, stdin)) = client {
+                drop(stdin);
+                let _ = child.wait();
+            }
+        });
+        Publisher {
+            messages: Some(messages),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Listener for Publisher {
+    fn send(&self, body: String) {
+        if let Some(messages) = &self.messages {
+            let _ = messages.send(body);
+        }
+    }
+}
+
+impl Drop for Publisher {
+    /// Publishes the queued messages, so the session end gets out.
+    fn drop(&mut self) {
+        self.messages.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Messages arriving on a trigger topic.
+pub struct Subscription {
+    child: Child,
+    messages: Receiver<String>,
+}
+
+impl Subscription {
+    pub fn spawn(host: &str, port: &str, topic: &str) -> Result<Self, AppError> {
+        let mut child = Command::new("mosquitto_sub")
+            .args(["-h", host, "-p", port, "-t", topic])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| AppError::ConfigError(format!("cannot run mosquitto_sub: {}", e)))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(AppError::ConfigError("mosquitto_sub has no output".into()))?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("captures triggered by messages on {}", topic);
+        Ok(Subscription { child, messages })
+    }
+
+    /// Messages received since the last call.
+    pub fn received(&self) -> Vec<String> {
+        self.messages.try_iter().collect()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broker_reads_host_and_port() {
+        let broker = |url| broker(url).unwrap();
+        assert_eq!(
+            broker("tcp://localhost:1884"),
+            ("localhost".into(), "1884".into())
+        );
+        assert_eq!(
+            broker("mqtt://10.0.0.2/"),
+            ("10.0.0.2".into(), "1883".into())
+        );
+        assert_eq!(broker("broker:8883"), ("broker".into(), "8883".into()));
+        for bad in ["tcp://", "tcp://:1883", "tcp://host:port", "host:70000"] {
+            assert!(super::broker(bad).is_err(), "{}", bad);
+        }
+    }
+}
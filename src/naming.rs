@@ -0,0 +1,257 @@
+//! `--name-template`: capture file names built from `{label}`, `{session}`, `{index}` (or
+//! `{index:05}` for zero-padding), `{timestamp}`, `{uuid}` and `{camera}`, e.g.
+//! `{label}_{session}_{index:05}_{timestamp}`. The extension and sidecar suffixes are appended
+//! to the rendered name, after the `_f<focus>`, `_ev<ev>` or `_hdr` of bracket shots.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::timestamp;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Label,
+    Session,
+    Index { width: usize },
+    Timestamp,
+    Uuid,
+    Camera,
+}
+
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+/// What a capture's name can be built from.
+pub struct Fields<'a> {
+    pub label: &'a str,
+    pub session: Option<&'a str>,
+    pub index: i32,
+    pub camera: usize,
+}
+
+/// `timestamp` with the characters file systems dislike replaced, e.g. `2024-05-01_12-30-00`.
+pub fn file_timestamp(time: SystemTime) -> String {
+    timestamp(time).replace(' ', "_").replace(':', "-")
+}
+
+/// A random version 4 UUID.
+fn uuid() -> String {
+    let random = || RandomState::new().build_hasher().finish();
+    let (high, low) = (random(), random());
+    let high = (high & !0xF000) | 0x4000;
+    let low = (low & !(0xC << 60)) | (0x8 << 60);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xFFFF,
+        high & 0xFFFF,
+        low >> 48,
+        low & 0xFFFF_FFFF_FFFF
+    )
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed {{ in {}", s))?;
+            let placeholder = &rest[start + 1..start + end];
+            let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+            parts.push(match (name, spec) {
+                ("label", "") => Part::Label,
+                ("session", "") => Part::Session,
+                ("index", spec) => Part::Index {
+                    width: if spec.is_empty() {
+                        0
+                    } else {
+                        spec.parse()
+                            .map_err(|_| format!("bad index width {:?}", spec))?
+                    },
+                },
+                ("timestamp", "") => Part::Timestamp,
+                ("uuid", "") => Part::Uuid,
+                ("camera", "") => Part::Camera,
+                _ => return Err(format!("unknown placeholder {{{}}}", placeholder)),
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        if !parts
+            .iter()
+            .any(|part| matches!(part, Part::Index { .. } | Part::Uuid))
+        {
+            return Err(format!(
+                "{} needs {{index}} or {{uuid}} to keep names unique",
+                s
+            ));
+        }
+        if parts.iter().any(|part| match part {
+            Part::Literal(text) => text.contains(['/', '\\', '.']),
+            _ => false,
+        }) {
+            return Err(format!("{} may not contain path separators or dots", s));
+        }
+        Ok(NameTemplate { parts })
+    }
+}
+
+/// The text each of `parts` matched, if all of `name` matches them.
+fn match_parts<'n>(parts: &[Part], name: &'n str) -> Option<Vec<&'n str>> {
+    let Some((part, tail)) = parts.split_first() else {
+        return name.is_empty().then(Vec::new);
+    };
+    let lengths: Vec<usize> = match part {
+        Part::Literal(text) if name.starts_with(text.as_str()) => vec![text.len()],
+        Part::Literal(_) => return None,
+        Part::Index { .. } => {
+            let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            (1..=digits).rev().collect()
+        }
+        _ => (0..=name.len())
+            .filter(|&at| name.is_char_boundary(at))
+            .collect(),
+    };
+    lengths.into_iter().find_map(|len| {
+        let mut pieces = match_parts(tail, &name[len..])?;
+        pieces.insert(0, &name[..len]);
+        Some(pieces)
+    })
+}
+
+impl NameTemplate {
+    /// The file name of a capture, without extension.
+    pub fn render(&self, fields: &Fields) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Label => fields.label.to_string(),
+                Part::Session => fields.session.unwrap_or_default().to_string(),
+                Part::Index { width } => format!("{:0width$}", fields.index, width = width),
+                Part::Timestamp => file_timestamp(SystemTime::now()),
+                Part::Uuid => uuid(),
+                Part::Camera => fields.camera.to_string(),
+            })
+            .collect()
+    }
+
+    /// Splits `name` into the capture name rendered from this template, what each part of
+    /// the template matched there, and the rest: the bracket or sidecar suffix and extension,
+    /// which follow the capture name after a `_` or `.`.
+    fn split<'n>(&self, name: &'n str) -> Option<(Vec<&'n str>, &'n str)> {
+        (1..=name.len())
+            .filter(|&at| name.is_char_boundary(at))
+            .filter(|&at| matches!(name[at..].chars().next(), None | Some('.' | '_')))
+            .find_map(|at| Some((match_parts(&self.parts, &name[..at])?, &name[at..])))
+    }
+
+    /// Whether `name` is a capture from this template or a companion of one, with the
+    /// capture's index when the template has one and the rest of the name after it.
+    pub fn parse<'n>(&self, name: &'n str) -> Option<(Option<u64>, &'n str)> {
+        let (pieces, rest) = self.split(name)?;
+        let index = self
+            .parts
+            .iter()
+            .zip(&pieces)
+            .find(|(part, _)| matches!(part, Part::Index { .. }))
+            .and_then(|(_, piece)| piece.parse().ok());
+        Some((index, rest))
+    }
+
+    /// `name` moved to `index`, and to `label` if given, keeping its other parts, its
+    /// padding and its suffix.
+    pub fn rename(&self, name: &str, index: u64, label: Option<&str>) -> Option<String> {
+        let (pieces, rest) = self.split(name)?;
+        let mut renamed: String = self
+            .parts
+            .iter()
+            .zip(pieces)
+            .map(|(part, piece)| match (part, label) {
+                (Part::Index { width }, _) => format!("{:0width$}", index, width = width),
+                (Part::Label, Some(label)) => label.to_string(),
+                _ => piece.to_string(),
+            })
+            .collect();
+        renamed.push_str(rest);
+        Some(renamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(text: &str) -> NameTemplate {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn render_fills_in_the_fields() {
+        let fields = Fields {
+            label: "cat",
+            session: Some("s1"),
+            index: 12,
+            camera: 1,
+        };
+        assert_eq!(template("{index}").render(&fields), "12");
+        assert_eq!(
+            template("{label}_{session}_{index:05}_{camera}").render(&fields),
+            "cat_s1_00012_1"
+        );
+    }
+
+    #[test]
+    fn parse_finds_the_index_and_what_follows_it() {
+        let plain = template("{index}");
+        assert_eq!(plain.parse("12.png"), Some((Some(12), ".png")));
+        assert_eq!(plain.parse("12_f30.png"), Some((Some(12), "_f30.png")));
+        assert_eq!(plain.parse("12.pose.toml"), Some((Some(12), ".pose.toml")));
+        assert_eq!(plain.parse("IMG_12.png"), None);
+        let full = template("{label}_{session}_{index:03}");
+        assert_eq!(
+            full.parse("cat_s1_012_hdr.png"),
+            Some((Some(12), "_hdr.png"))
+        );
+        assert_eq!(full.parse("cat.png"), None);
+        assert_eq!(template("{uuid}").parse("a1b2.png"), Some((None, ".png")));
+    }
+
+    #[test]
+    fn rename_keeps_padding_suffix_and_other_parts() {
+        let padded = template("{label}_{index:05}");
+        assert_eq!(
+            padded.rename("cat_00012_f10.png", 3, None).as_deref(),
+            Some("cat_00003_f10.png")
+        );
+        assert_eq!(
+            padded.rename("cat_00012.json", 3, Some("dog")).as_deref(),
+            Some("dog_00003.json")
+        );
+        assert_eq!(padded.rename("notes.txt", 3, None), None);
+    }
+
+    #[test]
+    fn from_str_rejects_bad_templates() {
+        assert!("{label}".parse::<NameTemplate>().is_err());
+        assert!("{index}.x".parse::<NameTemplate>().is_err());
+        assert!("{index}/{label}".parse::<NameTemplate>().is_err());
+        assert!("{nope}_{index}".parse::<NameTemplate>().is_err());
+        assert!("{index".parse::<NameTemplate>().is_err());
+        assert!("{index:x}".parse::<NameTemplate>().is_err());
+    }
+}
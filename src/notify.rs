@@ -0,0 +1,77 @@
+//! Capture and session events for outside listeners, `--webhook` and `--mqtt`. Every event is
+//! one JSON body, `{"event": ..., "timestamp": ..., "data": {...}}`, handed to each listener.
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use crate::recorder::CameraState;
+use crate::timestamp;
+
+pub trait Listener: Send + Sync {
+    /// Queues `body` for delivery without waiting for it.
+    fn send(&self, body: String);
+}
+
+#[derive(Serialize)]
+struct Post<'a, T: Serialize> {
+    event: &'a str,
+    timestamp: String,
+    data: &'a T,
+}
+
+#[derive(Serialize)]
+pub struct SessionStart<'a> {
+    pub session: Option<&'a str>,
+    pub store_path: &'a str,
+    pub cameras: &'a [String],
+}
+
+#[derive(Serialize)]
+pub struct Capture<'a> {
+    /// The image file, or its object URL with an s3 `--store`.
+    pub path: &'a str,
+    pub label: &'a str,
+    pub session: Option<&'a str>,
+    pub camera: Option<&'a CameraState>,
+}
+
+#[derive(Serialize)]
+pub struct SessionEnd<'a> {
+    pub session: Option<&'a str>,
+    pub seconds: f64,
+    pub captures: &'a BTreeMap<String, usize>,
+}
+
+#[derive(Default)]
+pub struct Notifier {
+    listeners: Vec<Box<dyn Listener>>,
+}
+
+impl Notifier {
+    pub fn add(&mut self, listener: impl Listener + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Hands `data` as an `event` to every listener.
+    pub fn send(&self, event: &str, data: &impl Serialize) {
+        let post = Post {
+            event,
+            timestamp: timestamp(SystemTime::now()),
+            data,
+        };
+        match serde_json::to_string(&post) {
+            Ok(body) => {
+                for listener in &self.listeners {
+                    listener.send(body.clone());
+                }
+            }
+            Err(e) => println!("cannot encode {} event: {}", event, e),
+        }
+    }
+}
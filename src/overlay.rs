@@ -0,0 +1,255 @@
+//! Exposure and framing aids drawn onto the 8-bit preview.
+
+use opencv::{
+    core::{
+        CV_8UC1, Mat, Point, Rect, Scalar, Vector, bitwise_and_def, in_range, min_max_loc, no_array,
+    },
+    imgproc::{
+        COLOR_BGR2GRAY, COLOR_GRAY2BGR, INTER_AREA, LINE_8, LINE_AA, calc_hist_def, cvt_color_def,
+        line, polylines, rectangle, resize,
+    },
+    prelude::*,
+};
+
+use crate::transform::to_gray;
+use crate::ui::{draw_text, to_display};
+
+const HISTOGRAM_WIDTH: i32 = 256;
+const HISTOGRAM_HEIGHT: i32 = 100;
+const MARGIN: i32 = 10;
+const ZEBRA_PERIOD: i32 = 12;
+const THUMBNAIL_WIDTH: i32 = 160;
+const HELP_LINE_HEIGHT: i32 = 20;
+
+/// 256-bin histogram of one channel, scaled so the tallest bin is 1.
+fn channel_histogram(image: &Mat, channel: i32) -> opencv::Result<Vec<f32>> {
+    let mut hist = Mat::default();
+    calc_hist_def(
+        &Vector::<Mat>::from(vec![image.try_clone()?]),
+        &Vector::from(vec![channel]),
+        &no_array(),
+        &mut hist,
+        &Vector::from(vec![HISTOGRAM_WIDTH]),
+        &Vector::from(vec![0.0f32, 256.0]),
+    )?;
+    let mut max = 0.0;
+    min_max_loc(&hist, None, Some(&mut max), None, None, &no_array())?;
+    let max = max.max(1.0) as f32;
+    (0..HISTOGRAM_WIDTH)
+        .map(|bin| Ok(*hist.at::<f32>(bin)? / max))
+        .collect()
+}
+
+/// Luminance histogram, plus one curve per channel for color previews, in the bottom-right
+/// corner.
+pub fn histogram(preview: &mut Mat) -> opencv::Result<()> {
+    let area = Rect::new(
+        preview.cols() - HISTOGRAM_WIDTH - MARGIN,
+        preview.rows() - HISTOGRAM_HEIGHT - MARGIN,
+        HISTOGRAM_WIDTH,
+        HISTOGRAM_HEIGHT,
+    );
+    if area.x < 0 || area.y < 0 {
+        return Ok(());
+    }
+    let mut curves = vec![(
+        channel_histogram(&to_gray(preview)?, 0)?,
+        Scalar::new(255.0, 255.0, 255.0, 0.0),
+    )];
+    if preview.channels() == 3 {
+        let colors = [
+            Scalar::new(255.0, 0.0, 0.0, 0.0),
+            Scalar::new(0.0, 255.0, 0.0, 0.0),
+            Scalar::new(0.0, 0.0, 255.0, 0.0),
+        ];
+        for (channel, color) in colors.into_iter().enumerate() {
+            curves.push((channel_histogram(preview, channel as i32)?, color));
+        }
+    }
+
+    {
+        let mut background = Mat::roi_mut(preview, area)?;
+        let mut darkened = Mat::default();
+        background.convert_to(&mut darkened, -1, 0.3, 0.0)?;
+        darkened.copy_to(&mut background)?;
+    }
+    for (hist, color) in curves {
+        let points: Vector<Point> = hist
+            .iter()
+            .enumerate()
+            .map(|(bin, value)| {
+                Point::new(
+                    area.x + bin as i32,
+                    area.y + HISTOGRAM_HEIGHT - (value * HISTOGRAM_HEIGHT as f32) as i32,
+                )
+            })
+            .collect();
+        polylines(
+            preview,
+            &Vector::<Vector<Point>>::from(vec![points]),
+            false,
+            color,
+            1,
+            LINE_AA,
+            0,
+        )?;
+    }
+    Ok(())
+}
+
+/// Diagonal black stripes over every pixel whose luminance is at least `threshold`.
+pub fn zebra(preview: &mut Mat, threshold: u8) -> opencv::Result<()> {
+    let mut bright = Mat::default();
+    in_range(
+        &to_gray(preview)?,
+        &Scalar::all(threshold as f64),
+        &Scalar::all(255.0),
+        &mut bright,
+    )?;
+    let (rows, cols) = (preview.rows(), preview.cols());
+    let mut stripes = Mat::new_rows_cols_with_default(rows, cols, CV_8UC1, Scalar::all(0.0))?;
+    for start in (-rows..cols).step_by(ZEBRA_PERIOD as usize) {
+        line(
+            &mut stripes,
+            Point::new(start, 0),
+            Point::new(start + rows, rows),
+            Scalar::all(255.0),
+            ZEBRA_PERIOD / 3,
+            LINE_8,
+            0,
+        )?;
+    }
+    let mut mask = Mat::default();
+    bitwise_and_def(&bright, &stripes, &mut mask)?;
+    preview.set_to(&Scalar::all(0.0), &mask)?;
+    Ok(())
+}
+
+/// `cols` x `rows` framing grid (3x3 is the rule of thirds), optionally with a center cross.
+pub fn grid(preview: &mut Mat, cols: i32, rows: i32, crosshair: bool) -> opencv::Result<()> {
+    let color = Scalar::new(200.0, 200.0, 200.0, 0.0);
+    let (width, height) = (preview.cols(), preview.rows());
+    for col in 1..cols {
+        let x = width * col / cols;
+        line(
+            preview,
+            Point::new(x, 0),
+            Point::new(x, height),
+            color,
+            1,
+            LINE_AA,
+            0,
+        )?;
+    }
+    for row in 1..rows {
+        let y = height * row / rows;
+        line(
+            preview,
+            Point::new(0, y),
+            Point::new(width, y),
+            color,
+            1,
+            LINE_AA,
+            0,
+        )?;
+    }
+    if crosshair {
+        let center = Point::new(width / 2, height / 2);
+        let arm = width.min(height) / 20;
+        let color = Scalar::new(0.0, 255.0, 255.0, 0.0);
+        line(
+            preview,
+            center - Point::new(arm, 0),
+            center + Point::new(arm, 0),
+            color,
+            1,
+            LINE_AA,
+            0,
+        )?;
+        line(
+            preview,
+            center - Point::new(0, arm),
+            center + Point::new(0, arm),
+            color,
+            1,
+            LINE_AA,
+            0,
+        )?;
+    }
+    Ok(())
+}
+
+/// `frame` shrunk into the right edge, above the histogram corner.
+pub fn thumbnail(preview: &mut Mat, frame: &Mat) -> opencv::Result<()> {
+    if frame.cols() == 0 {
+        return Ok(());
+    }
+    let height = (frame.rows() * THUMBNAIL_WIDTH / frame.cols()).max(1);
+    let area = Rect::new(
+        preview.cols() - THUMBNAIL_WIDTH - MARGIN,
+        preview.rows() - HISTOGRAM_HEIGHT - height - 2 * MARGIN,
+        THUMBNAIL_WIDTH,
+        height,
+    );
+    if area.x < 0 || area.y < 0 {
+        return Ok(());
+    }
+    let mut small = Mat::default();
+    resize(
+        &to_display(frame)?,
+        &mut small,
+        area.size(),
+        0.0,
+        0.0,
+        INTER_AREA,
+    )?;
+    let small = match (small.channels(), preview.channels()) {
+        (1, 3) => {
+            let mut color = Mat::default();
+            cvt_color_def(&small, &mut color, COLOR_GRAY2BGR)?;
+            color
+        }
+        (3, 1) => {
+            let mut gray = Mat::default();
+            cvt_color_def(&small, &mut gray, COLOR_BGR2GRAY)?;
+            gray
+        }
+        _ => small,
+    };
+    if small.typ() != preview.typ() {
+        return Ok(());
+    }
+    small.copy_to(&mut Mat::roi_mut(preview, area)?)?;
+    rectangle(
+        preview,
+        area,
+        Scalar::new(255.0, 255.0, 255.0, 0.0),
+        1,
+        LINE_8,
+        0,
+    )?;
+    Ok(())
+}
+
+/// `lines` over a darkened panel in the top-left corner.
+pub fn help(preview: &mut Mat, lines: &[String]) -> opencv::Result<()> {
+    let area = Rect::new(
+        0,
+        0,
+        preview.cols().min(460),
+        preview
+            .rows()
+            .min(HELP_LINE_HEIGHT * lines.len() as i32 + 2 * MARGIN),
+    );
+    {
+        let mut background = Mat::roi_mut(preview, area)?;
+        let mut darkened = Mat::default();
+        background.convert_to(&mut darkened, -1, 0.3, 0.0)?;
+        darkened.copy_to(&mut background)?;
+    }
+    for (row, text) in lines.iter().enumerate() {
+        let org = Point::new(MARGIN, MARGIN + HELP_LINE_HEIGHT * (row as i32 + 1) - 5);
+        draw_text(preview, text, org, 0.5)?;
+    }
+    Ok(())
+}
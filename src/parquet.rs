@@ -0,0 +1,529 @@
+//! Minimal Parquet writer for flat tables of required byte-array columns.
+//!
+//! Pages are PLAIN encoded and uncompressed; metadata uses the Thrift compact protocol.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"PAR1";
+
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REPETITION_REQUIRED: i32 = 0;
+const CONVERTED_UTF8: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const PAGE_DATA: i32 = 0;
+const CODEC_UNCOMPRESSED: i32 = 0;
+
+const CT_I32: u8 = 5;
+const CT_I64: u8 = 6;
+const CT_BINARY: u8 = 8;
+const CT_LIST: u8 = 9;
+const CT_STRUCT: u8 = 12;
+
+/// Thrift compact protocol encoder.
+#[derive(Default)]
+struct Thrift {
+    buf: Vec<u8>,
+    last_field: Vec<i16>,
+}
+
+impl Thrift {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn zigzag(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, typ: u8) {
+        let last = self.last_field.last().copied().unwrap_or(0);
+        let delta = id - last;
+        if (1..=15).contains(&delta) {
+            self.buf.push(((delta as u8) << 4) | typ);
+        } else {
+            self.buf.push(typ);
+            self.zigzag(id as i64);
+        }
+        if let Some(last) = self.last_field.last_mut() {
+            *last = id;
+        }
+    }
+
+    fn begin(&mut self) {
+        self.last_field.push(0);
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last_field.pop();
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, CT_I32);
+        self.zigzag(value as i64);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, CT_I64);
+        self.zigzag(value);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, CT_BINARY);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn list(&mut self, id: i16, elem: u8, len: usize) {
+        self.field(id, CT_LIST);
+        if len < 15 {
+            self.buf.push(((len as u8) << 4) | elem);
+        } else {
+            self.buf.push(0xF0 | elem);
+            self.varint(len as u64);
+        }
+    }
+
+    fn list_binary(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn list_i32(&mut self, value: i32) {
+        self.zigzag(value as i64);
+    }
+}
+
+pub struct Column {
+    /// Dotted path, e.g. `image.bytes`; the parent group must be declared in `groups`.
+    pub path: &'static str,
+    pub utf8: bool,
+}
+
+struct ChunkMeta {
+    offset: i64,
+    size: i64,
+    values: i64,
+}
+
+pub struct Writer {
+    out: BufWriter<File>,
+    offset: i64,
+    columns: Vec<Column>,
+    groups: Vec<&'static str>,
+    row_groups: Vec<(i64, Vec<ChunkMeta>)>,
+}
+
+impl Writer {
+    pub fn create(
+        path: &Path,
+        groups: Vec<&'static str>,
+        columns: Vec<Column>,
+    ) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        Ok(Writer {
+            out,
+            offset: MAGIC.len() as i64,
+            columns,
+            groups,
+            row_groups: Vec::new(),
+        })
+    }
+
+    /// Writes one row group; `rows[i][c]` is the value of column `c` in row `i`.
+    pub fn write_rows(&mut self, rows: &[Vec<Vec<u8>>]) -> io::Result<()> {
+        let mut chunks = Vec::with_capacity(self.columns.len());
+        for column in 0..self.columns.len() {
+            let mut page = Vec::new();
+            for row in rows {
+                let value = &row[column];
+                page.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                page.extend_from_slice(value);
+            }
+            let mut header = Thrift::default();
+            header.begin();
+            header.i32(1, PAGE_DATA);
+            header.i32(2, page.len() as i32);
+            header.i32(3, page.len() as i32);
+            header.field(5, CT_STRUCT);
+            header.begin();
+            header.i32(1, rows.len() as i32);
+            header.i32(2, ENCODING_PLAIN);
+            header.i32(3, ENCODING_RLE);
+            header.i32(4, ENCODING_RLE);
+            header.end();
+            header.end();
+            self.out.write_all(&header.buf)?;
+            self.out.write_all(&page)?;
+            let size = (header.buf.len() + page.len()) as i64;
+            chunks.push(ChunkMeta {
+                offset: self.offset,
+                size,
+                values: rows.len() as i64,
+            });
+            self.offset += size;
+        }
+        self.row_groups.push((rows.len() as i64, chunks));
+        Ok(())
+    }
+
+    fn schema(&self, meta: &mut Thrift) {
+        let top_level: Vec<&str> = self
+            .columns
+            .iter()
+            .map(|c| c.path.split('.').next().unwrap_or(c.path))
+            .fold(Vec::new(), |mut names, name| {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+                names
+            });
+        let mut elements = 1 + top_level.len();
+        elements += self.columns.iter().filter(|c| c.path.contains('.')).count();
+        meta.list(2, CT_STRUCT, elements);
+        meta.begin();
+        meta.binary(4, b"schema");
+        meta.i32(5, top_level.len() as i32);
+        meta.end();
+        for name in top_level {
+            let children: Vec<&Column> = self
+                .columns
+                .iter()
+                .filter(|c| c.path.starts_with(&format!("{}.", name)))
+                .collect();
+            if self.groups.contains(&name) {
+                meta.begin();
+                meta.i32(3, REPETITION_REQUIRED);
+                meta.binary(4, name.as_bytes());
+                meta.i32(5, children.len() as i32);
+                meta.end();
+                for child in children {
+                    leaf(
+                        meta,
+                        child.path.rsplit('.').next().unwrap_or(child.path),
+                        child.utf8,
+                    );
+                }
+            } else if let Some(column) = self.columns.iter().find(|c| c.path == name) {
+                leaf(meta, name, column.utf8);
+            }
+        }
+    }
+
+    /// Writes the footer; `metadata` becomes the file's key/value metadata.
+    pub fn finish(mut self, metadata: &[(&str, String)]) -> io::Result<()> {
+        let num_rows: i64 = self.row_groups.iter().map(|(rows, _)| rows).sum();
+        let mut meta = Thrift::default();
+        meta.begin();
+        meta.i32(1, 1);
+        self.schema(&mut meta);
+        meta.i64(3, num_rows);
+        meta.list(4, CT_STRUCT, self.row_groups.len());
+        for (rows, chunks) in &self.row_groups {
+            meta.begin();
+            meta.list(1, CT_STRUCT, chunks.len());
+            for (column, chunk) in self.columns.iter().zip(chunks) {
+                meta.begin();
+                meta.i64(2, chunk.offset);
+                meta.field(3, CT_STRUCT);
+                meta.begin();
+                meta.i32(1, TYPE_BYTE_ARRAY);
+                meta.list(2, CT_I32, 2);
+                meta.list_i32(ENCODING_PLAIN);
+                meta.list_i32(ENCODING_RLE);
+                let path: Vec<&str> = column.path.split('.').collect();
+                meta.list(3, CT_BINARY, path.len());
+                for part in path {
+                    meta.list_binary(part.as_bytes());
+                }
+                meta.i32(4, CODEC_UNCOMPRESSED);
+                meta.i64(5, chunk.values);
+                meta.i64(6, chunk.size);
+                meta.i64(7, chunk.size);
+                meta.i64(9, chunk.offset);
+                meta.end();
+                meta.end();
+            }
+            meta.i64(2, chunks.iter().map(|c| c.size).sum());
+            meta.i64(3, *rows);
+            meta.end();
+        }
+        if !metadata.is_empty() {
+            meta.list(5, CT_STRUCT, metadata.len());
+            for (key, value) in metadata {
+                meta.begin();
+                meta.binary(1, key.as_bytes());
+                meta.binary(2, value.as_bytes());
+                meta.end();
+            }
+        }
+        meta.binary(6, b"img_collector");
+        meta.end();
+        self.out.write_all(&meta.buf)?;
+        self.out.write_all(&(meta.buf.len() as u32).to_le_bytes())?;
+        self.out.write_all(MAGIC)?;
+        self.out.flush()
+    }
+}
+
+fn leaf(meta: &mut Thrift, name: &str, utf8: bool) {
+    meta.begin();
+    meta.i32(1, TYPE_BYTE_ARRAY);
+    meta.i32(3, REPETITION_REQUIRED);
+    meta.binary(4, name.as_bytes());
+    if utf8 {
+        meta.i32(6, CONVERTED_UTF8);
+    }
+    meta.end();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_parquet_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A decoded Thrift compact value; structs keep their fields in wire order.
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Value>),
+        Struct(Vec<(i16, Value)>),
+    }
+
+    impl Value {
+        fn field(&self, id: i16) -> &Value {
+            match self {
+                Value::Struct(fields) => &fields.iter().find(|(at, _)| *at == id).unwrap().1,
+                _ => panic!("not a struct: {:?}", self),
+            }
+        }
+
+        fn int(&self) -> i64 {
+            match self {
+                Value::Int(value) => *value,
+                _ => panic!("not an integer: {:?}", self),
+            }
+        }
+
+        fn bytes(&self) -> &[u8] {
+            match self {
+                Value::Binary(value) => value,
+                _ => panic!("not binary: {:?}", self),
+            }
+        }
+
+        fn items(&self) -> &[Value] {
+            match self {
+                Value::List(items) => items,
+                _ => panic!("not a list: {:?}", self),
+            }
+        }
+    }
+
+    fn read_varint(buf: &[u8], at: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = buf[*at];
+            *at += 1;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_zigzag(buf: &[u8], at: &mut usize) -> i64 {
+        let value = read_varint(buf, at);
+        (value >> 1) as i64 ^ -((value & 1) as i64)
+    }
+
+    fn read_value(typ: u8, buf: &[u8], at: &mut usize) -> Value {
+        match typ {
+            CT_I32 | CT_I64 => Value::Int(read_zigzag(buf, at)),
+            CT_BINARY => {
+                let len = read_varint(buf, at) as usize;
+                *at += len;
+                Value::Binary(buf[*at - len..*at].to_vec())
+            }
+            CT_LIST => {
+                let header = buf[*at];
+                *at += 1;
+                let mut len = (header >> 4) as usize;
+                if len == 15 {
+                    len = read_varint(buf, at) as usize;
+                }
+                Value::List(
+                    (0..len)
+                        .map(|_| read_value(header & 0x0F, buf, at))
+                        .collect(),
+                )
+            }
+            CT_STRUCT => {
+                let mut fields = Vec::new();
+                let mut last = 0;
+                loop {
+                    let header = buf[*at];
+                    *at += 1;
+                    if header == 0 {
+                        return Value::Struct(fields);
+                    }
+                    let delta = (header >> 4) as i16;
+                    last = if delta == 0 {
+                        read_zigzag(buf, at) as i16
+                    } else {
+                        last + delta
+                    };
+                    fields.push((last, read_value(header & 0x0F, buf, at)));
+                }
+            }
+            _ => panic!("unexpected compact type {}", typ),
+        }
+    }
+
+    #[test]
+    fn thrift_uses_field_deltas_and_zigzag_integers() {
+        let mut thrift = Thrift::default();
+        thrift.begin();
+        thrift.i32(1, 1);
+        thrift.i64(3, -3);
+        // A jump of more than 15 spells the field id out.
+        thrift.binary(20, b"x");
+        thrift.end();
+        assert_eq!(
+            thrift.buf,
+            [0x15, 0x02, 0x26, 0x05, 0x08, 0x28, 0x01, b'x', 0x00]
+        );
+        assert_eq!(
+            read_value(CT_STRUCT, &thrift.buf, &mut 0),
+            Value::Struct(vec![
+                (1, Value::Int(1)),
+                (3, Value::Int(-3)),
+                (20, Value::Binary(b"x".to_vec())),
+            ])
+        );
+    }
+
+    #[test]
+    fn long_lists_put_their_length_after_the_header() {
+        let mut thrift = Thrift::default();
+        thrift.begin();
+        thrift.list(1, CT_I32, 20);
+        for value in 0..20 {
+            thrift.list_i32(value);
+        }
+        thrift.end();
+        assert_eq!(thrift.buf[..3], [0x19, 0xF5, 20]);
+        let decoded = read_value(CT_STRUCT, &thrift.buf, &mut 0);
+        let items: Vec<i64> = decoded.field(1).items().iter().map(Value::int).collect();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn footer_describes_the_pages_written() {
+        let path = scratch("footer").join("table.parquet");
+        let columns = vec![
+            Column {
+                path: "image.bytes",
+                utf8: false,
+            },
+            Column {
+                path: "label",
+                utf8: true,
+            },
+        ];
+        let mut writer = Writer::create(&path, vec!["image"], columns).unwrap();
+        let rows = vec![
+            vec![vec![1, 2, 3], b"cat".to_vec()],
+            vec![vec![4], b"dog".to_vec()],
+        ];
+        writer.write_rows(&rows).unwrap();
+        writer.finish(&[("source", "test".to_string())]).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(&data[..4], MAGIC);
+        assert_eq!(&data[data.len() - 4..], MAGIC);
+        let footer_len =
+            u32::from_le_bytes(data[data.len() - 8..data.len() - 4].try_into().unwrap()) as usize;
+        let footer_start = data.len() - 8 - footer_len;
+        let mut at = footer_start;
+        let meta = read_value(CT_STRUCT, &data, &mut at);
+        assert_eq!(at, data.len() - 8);
+
+        assert_eq!(meta.field(3).int(), 2);
+        let names: Vec<&[u8]> = meta
+            .field(2)
+            .items()
+            .iter()
+            .map(|element| element.field(4).bytes())
+            .collect();
+        assert_eq!(names, [&b"schema"[..], b"image", b"bytes", b"label"]);
+        let pairs = meta.field(5).items();
+        assert_eq!(pairs[0].field(1).bytes(), b"source");
+        assert_eq!(pairs[0].field(2).bytes(), b"test");
+
+        let row_group = &meta.field(4).items()[0];
+        assert_eq!(row_group.field(3).int(), 2);
+        let chunks = row_group.field(1).items();
+        assert_eq!(chunks.len(), 2);
+        // Each chunk points at a page header followed by the PLAIN values of its column.
+        let mut values = Vec::new();
+        for (column, chunk) in chunks.iter().enumerate() {
+            let chunk_meta = chunk.field(3);
+            let path: Vec<&[u8]> = chunk_meta
+                .field(3)
+                .items()
+                .iter()
+                .map(Value::bytes)
+                .collect();
+            assert_eq!(
+                path,
+                if column == 0 {
+                    vec![&b"image"[..], b"bytes"]
+                } else {
+                    vec![&b"label"[..]]
+                }
+            );
+            let mut at = chunk_meta.field(9).int() as usize;
+            let header = read_value(CT_STRUCT, &data, &mut at);
+            assert_eq!(header.field(5).field(1).int(), 2);
+            let page_len = header.field(2).int() as usize;
+            assert_eq!(
+                at + page_len - chunk_meta.field(9).int() as usize,
+                chunk_meta.field(7).int() as usize
+            );
+            let mut page = &data[at..at + page_len];
+            while !page.is_empty() {
+                let len = u32::from_le_bytes(page[..4].try_into().unwrap()) as usize;
+                values.push(page[4..4 + len].to_vec());
+                page = &page[4 + len..];
+            }
+        }
+        assert_eq!(
+            values,
+            [vec![1, 2, 3], vec![4], b"cat".to_vec(), b"dog".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! The `test` source: frames drawn in Rust rather than read from a device, so the capture
+//! pipeline runs on any OpenCV build without a camera. Every pattern moves from frame to frame
+//! and is stamped with the frame number in the top-left corner.
+
+use opencv::core::{CV_8UC3, Mat, Scalar};
+use opencv::prelude::MatTraitManual;
+use opencv::videoio::{
+    CAP_PROP_FPS, CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH, CAP_PROP_POS_FRAMES,
+};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::source::{FrameSource, TestPattern};
+
+/// SMPTE-style bars, left to right, in BGR.
+const BARS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [0, 255, 255],
+    [255, 255, 0],
+    [0, 255, 0],
+    [255, 0, 255],
+    [0, 0, 255],
+    [255, 0, 0],
+    [0, 0, 0],
+];
+/// How far the bars scroll per frame, in pixels.
+const BAR_SPEED: usize = 4;
+/// 3x5 digit glyphs, one bit per dot, top row in the highest bits.
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+/// Generates `pattern` frames of the given size, handing them out at `fps` like a live camera.
+pub struct TestSource {
+    pattern: TestPattern,
+    width: usize,
+    height: usize,
+    fps: f64,
+    frame: u64,
+    due: Option<Instant>,
+    pixels: Vec<u8>,
+    rng: u64,
+}
+
+impl TestSource {
+    pub fn new(pattern: TestPattern, width: i32, height: i32, fps: i32) -> Self {
+        TestSource {
+            pattern,
+            width: width.max(1) as usize,
+            height: height.max(1) as usize,
+            fps: fps.max(1) as f64,
+            frame: 0,
+            due: None,
+            pixels: Vec::new(),
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+}
+
+impl FrameSource for TestSource {
+    fn grab(&mut self) -> opencv::Result<bool> {
+        let now = Instant::now();
+        let due = self.due.unwrap_or(now);
+        if due > now {
+            thread::sleep(due - now);
+        }
+        self.due = Some(due.max(now) + Duration::from_secs_f64(1.0 / self.fps));
+        self.pixels = render(
+            self.pattern,
+            self.width,
+            self.height,
+            self.frame,
+            &mut self.rng,
+        );
+        self.frame += 1;
+        Ok(true)
+    }
+
+    fn retrieve(&mut self, frame: &mut Mat, _flag: i32) -> opencv::Result<bool> {
+        if self.pixels.is_empty() {
+            return Ok(false);
+        }
+        let mut image = Mat::new_rows_cols_with_default(
+            self.height as i32,
+            self.width as i32,
+            CV_8UC3,
+            Scalar::all(0.0),
+        )?;
+        image.data_bytes_mut()?.copy_from_slice(&self.pixels);
+        *frame = image;
+        Ok(true)
+    }
+
+    fn get(&self, prop: i32) -> opencv::Result<f64> {
+        Ok(match prop {
+            CAP_PROP_FRAME_WIDTH => self.width as f64,
+            CAP_PROP_FRAME_HEIGHT => self.height as f64,
+            CAP_PROP_FPS => self.fps,
+            CAP_PROP_POS_FRAMES => self.frame as f64,
+            _ => 0.0,
+        })
+    }
+
+    fn set(&mut self, prop: i32, value: f64) -> opencv::Result<bool> {
+        if value < 1.0 {
+            return Ok(false);
+        }
+        match prop {
+            // A grabbed frame of the old size can't be retrieved any more.
+            CAP_PROP_FRAME_WIDTH => (self.width, self.pixels) = (value as usize, Vec::new()),
+            CAP_PROP_FRAME_HEIGHT => (self.height, self.pixels) = (value as usize, Vec::new()),
+            CAP_PROP_FPS => self.fps = value,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn release(&mut self) -> opencv::Result<()> {
+        self.pixels.clear();
+        Ok(())
+    }
+}
+
+/// Xorshift, enough to make snow.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// Frame number `frame` of `pattern` as packed BGR rows.
+fn render(pattern: TestPattern, width: usize, height: usize, frame: u64, rng: &mut u64) -> Vec<u8> {
+    let mut pixels = vec![0; width * height * 3];
+    match pattern {
+        TestPattern::Bars => {
+            let shift = frame as usize * BAR_SPEED;
+            for (i, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+                let x = (i % width + shift) % width;
+                pixel.copy_from_slice(&BARS[x * BARS.len() / width]);
+            }
+        }
+        TestPattern::Noise => {
+            for pixel in pixels.chunks_exact_mut(3) {
+                pixel.fill(next_random(rng) as u8);
+            }
+        }
+        TestPattern::Ball => {
+            let radius = (width.min(height) / 10).max(1);
+            let bounce = |span: usize, speed: u64| {
+                let span = span.saturating_sub(2 * radius).max(1) as u64;
+                let step = (frame * speed) % (2 * span);
+                radius + step.min(2 * span - step) as usize
+            };
+            let (cx, cy) = (bounce(width, 5), bounce(height, 3));
+            for (i, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+                let (dx, dy) = ((i % width).abs_diff(cx), (i / width).abs_diff(cy));
+                if dx * dx + dy * dy <= radius * radius {
+                    pixel.fill(255);
+                }
+            }
+        }
+    }
+    stamp(&mut pixels, width, height, frame);
+    pixels
+}
+
+/// Writes `number` white on a black box in the top-left corner.
+fn stamp(pixels: &mut [u8], width: usize, height: usize, number: u64) {
+    let dot = (height / 60).max(1);
+    let digits: Vec<usize> = number
+        .to_string()
+        .bytes()
+        .map(|digit| (digit - b'0') as usize)
+        .collect();
+    // Each glyph is 3 dots wide with a dot of space after it, inside a dot of margin.
+    let box_width = ((digits.len() * 4 + 1) * dot).min(width);
+    let box_height = (7 * dot).min(height);
+    for y in 0..box_height {
+        for x in 0..box_width {
+            let (col, row) = (x / dot, y / dot);
+            // Dot `col` of the box is column `(col - 1) % 4` of glyph `(col - 1) / 4`.
+            let lit = (1..=5).contains(&row)
+                && col >= 1
+                && (col - 1) % 4 < 3
+                && DIGITS[digits[(col - 1) / 4]] >> (14 - (row - 1) * 3 - (col - 1) % 4) & 1 == 1;
+            let at = (y * width + x) * 3;
+            pixels[at..at + 3].fill(if lit { 255 } else { 0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> &[u8] {
+        &pixels[(y * width + x) * 3..][..3]
+    }
+
+    #[test]
+    fn frames_are_packed_bgr_of_the_requested_size() {
+        let mut rng = 1;
+        for pattern in [TestPattern::Bars, TestPattern::Noise, TestPattern::Ball] {
+            assert_eq!(render(pattern, 64, 48, 0, &mut rng).len(), 64 * 48 * 3);
+        }
+    }
+
+    #[test]
+    fn bars_scroll_from_frame_to_frame() {
+        let mut rng = 1;
+        let first = render(TestPattern::Bars, 80, 60, 0, &mut rng);
+        // Below the frame number, the first bar is white and the last black.
+        assert_eq!(pixel(&first, 80, 0, 30), [255, 255, 255]);
+        assert_eq!(pixel(&first, 80, 79, 30), [0, 0, 0]);
+        let second = render(TestPattern::Bars, 80, 60, 1, &mut rng);
+        assert_eq!(pixel(&second, 80, 0, 30), pixel(&first, 80, BAR_SPEED, 30));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn noise_and_ball_move() {
+        let mut rng = 1;
+        for pattern in [TestPattern::Noise, TestPattern::Ball] {
+            let first = render(pattern, 64, 48, 0, &mut rng);
+            let second = render(pattern, 64, 48, 1, &mut rng);
+            assert_ne!(first, second);
+        }
+    }
+
+    #[test]
+    fn stamp_draws_the_frame_number() {
+        // One dot per pixel: a 9x7 box holding "10" after a dot of margin.
+        let (width, height) = (12, 12);
+        let mut pixels = vec![128; width * height * 3];
+        stamp(&mut pixels, width, height, 10);
+        let lit = |x, y| pixel(&pixels, width, x, y)[0] == 255;
+        // The margin stays dark, the glyphs light up row by row.
+        assert!(!lit(0, 0) && !lit(1, 0));
+        assert_eq!([lit(1, 1), lit(2, 1), lit(3, 1)], [false, true, false]);
+        assert_eq!([lit(5, 1), lit(6, 1), lit(7, 1)], [true, true, true]);
+        assert_eq!([lit(5, 2), lit(6, 2), lit(7, 2)], [true, false, true]);
+        // Outside the box the frame is untouched.
+        assert_eq!(pixel(&pixels, width, 10, 10), [128, 128, 128]);
+    }
+}
@@ -0,0 +1,1269 @@
+//! `Recorder` saves captures: it prepares the frames, checks them, names them, queues them on
+//! the writer thread with their companion files, and keeps what the preview shows about them.
+
+use opencv::calib3d::undistort_def;
+use opencv::core::{CV_8U, Mat, Point, Rect, Size, Vector};
+use opencv::imgcodecs::IMWRITE_JPEG_QUALITY;
+use opencv::imgproc::{INTER_AREA, resize};
+use opencv::photo::{MergeMertensTrait, create_merge_mertens_def};
+use opencv::prelude::*;
+use opencv::videoio::CAP_PROP_AUTOFOCUS;
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::db::Db;
+use crate::events::{Event, Events};
+use crate::gpu::{self, Image};
+use crate::naming::{Fields, NameTemplate};
+use crate::sink::FrameSink;
+use crate::source::{CaptureSettings, DepthSensor, FrameSource, VideoProp, read_source};
+use crate::transform::{
+    ColorSpace, Fit, FlipAxis, OutSize, Rotation, clipping, convert_color, dhash, fit_frame,
+    orient_frame, sharpness,
+};
+use crate::ui::{draw_text, to_display};
+use crate::writer::{Job, Writer};
+use crate::{
+    AppError, Calibration, ImageFormat, classes, dataset, exif, hook, index, manifest, notify,
+    timestamp,
+};
+
+pub const SETTLE_FRAMES: usize = 3;
+/// Indices a recorder reserves in a directory at a time.
+const INDEX_RESERVATION: i32 = 64;
+const SAVE_RATE_WINDOW: Duration = Duration::from_secs(5);
+const THUMB_QUALITY: i32 = 80;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarkerPose {
+    pub id: i32,
+    pub rvec: Option<[f64; 3]>,
+    pub tvec: Option<[f64; 3]>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CameraState {
+    pub device: Option<i32>,
+    pub focus: Option<f64>,
+    pub exposure: Option<f64>,
+    pub gain: Option<f64>,
+}
+
+impl CameraState {
+    pub fn read(cap: &dyn FrameSource, device: Option<i32>) -> Self {
+        CameraState {
+            device,
+            focus: cap.focus().ok(),
+            exposure: cap.exposure().ok(),
+            gain: cap.gain().ok(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FrameSidecar<'a> {
+    timestamp: String,
+    unix_time: f64,
+    label: &'a str,
+    width: i32,
+    height: i32,
+    #[serde(flatten)]
+    camera: &'a CameraState,
+}
+
+#[derive(Serialize)]
+struct PoseSidecar<'a> {
+    markers: &'a [MarkerPose],
+}
+
+pub struct Recorder {
+    pub store_path: String,
+    pub cam_names: Vec<String>,
+    pub indice_map: HashMap<String, i32>,
+    /// End of the index range this recorder holds in each directory.
+    pub reserved: HashMap<String, i32>,
+    pub ext: &'static str,
+    pub compression_params: Vector<i32>,
+    /// Parameters for frames `ext` cannot hold, which are written as PNG instead.
+    pub png_params: Vector<i32>,
+    pub markers: Vec<MarkerPose>,
+    pub min_sharpness: Option<f64>,
+    pub max_clipping: Option<f64>,
+    pub dedupe_distance: Option<u32>,
+    pub last_hash: Option<u64>,
+    pub roi: Option<Rect>,
+    pub out_size: Option<OutSize>,
+    pub fit: Fit,
+    pub color: ColorSpace,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<FlipAxis>,
+    pub gpu: bool,
+    pub calibration: Option<Calibration>,
+    pub depth: Option<DepthSensor>,
+    pub watermark: Option<String>,
+    pub keep_clean: bool,
+    pub thumbs: Option<OutSize>,
+    pub keep_raw: bool,
+    /// The canonical store path, whose `thumbs/` and `raw/` trees mirror the captures.
+    pub root: PathBuf,
+    pub sidecar: bool,
+    /// Settings of each camera as of the last capture, read by `read_cameras`.
+    pub cameras: Vec<CameraState>,
+    pub devices: Vec<Option<i32>>,
+    pub exif: bool,
+    pub session: Option<String>,
+    pub manifest: Option<PathBuf>,
+    pub on_save: Option<Arc<hook::Hooks>>,
+    pub notifier: Option<Arc<notify::Notifier>>,
+    pub class_map: classes::ClassMap,
+    pub target: Option<usize>,
+    pub counts: HashMap<String, usize>,
+    pub max_per_class: Option<usize>,
+    pub notice: Option<(String, Instant)>,
+    pub history: Vec<SavedCapture>,
+    pub save_times: VecDeque<Instant>,
+    pub thumbnail: Option<(Mat, Instant)>,
+    pub beep: bool,
+    pub flash: Option<(bool, Instant)>,
+    pub name_template: NameTemplate,
+    pub db: Option<Db>,
+    pub lock: index::StoreLock,
+    pub sink: Arc<dyn FrameSink>,
+    pub writer: Writer,
+    pub events: Arc<Events>,
+    pub started: Instant,
+    pub saved: BTreeMap<String, usize>,
+}
+
+/// One exposure of every camera in a capture: the frames as read and as prepared for saving,
+/// and what the camera's file names add after the capture's name, such as `_f120`.
+pub struct Shot<'a> {
+    pub suffixes: Vec<String>,
+    pub raw: &'a [Mat],
+    pub frames: Vec<Mat>,
+}
+
+/// Files written by one `save_shots` call, so it can be undone.
+pub struct SavedCapture {
+    pub label: String,
+    /// Images per camera: one, or one per shot of a bracket.
+    pub shots: usize,
+    pub indices: Vec<(PathBuf, i32)>,
+    pub files: Vec<PathBuf>,
+}
+
+impl Recorder {
+    fn capture_dir(&self, label: &str, cam: usize) -> Result<PathBuf, AppError> {
+        let mut dir = PathBuf::from(&self.store_path).join(label);
+        if let Some(name) = self.cam_names.get(cam) {
+            dir = dir.join(name);
+        }
+        let _ = fs::create_dir_all(&dir);
+        Ok(dir.canonicalize()?)
+    }
+
+    /// The next index in `dir`, from a range of `INDEX_RESERVATION` indices reserved under
+    /// the store lock, so the lock and state file are only touched once per range.
+    fn take_index(&mut self, dir: &Path) -> Result<i32, AppError> {
+        let dir = dir
+            .to_str()
+            .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+        let next = self.indice_map.get(dir).copied().unwrap_or(0);
+        if self.reserved.get(dir).is_none_or(|end| next >= *end) {
+            let (start, end) =
+                self.lock
+                    .update(Path::new(&self.store_path), &mut HashMap::new(), |saved| {
+                        let end = saved.entry(dir.to_string()).or_insert(0);
+                        let start = (*end).max(next);
+                        *end = start + INDEX_RESERVATION;
+                        (start, *end)
+                    })?;
+            self.indice_map.insert(dir.to_string(), start);
+            self.reserved.insert(dir.to_string(), end);
+        }
+        let next = self.indice_map.entry(dir.to_string()).or_insert(0);
+        *next += 1;
+        Ok(*next - 1)
+    }
+
+    /// Hands back the unused rest of this recorder's index ranges, unless another recorder
+    /// has reserved indices after them since.
+    pub fn release_indices(&self) -> Result<(), AppError> {
+        if self.reserved.is_empty() {
+            return Ok(());
+        }
+        self.lock
+            .update(Path::new(&self.store_path), &mut HashMap::new(), |saved| {
+                for (dir, end) in &self.reserved {
+                    if let (Some(saved), Some(next)) =
+                        (saved.get_mut(dir), self.indice_map.get(dir))
+                        && *saved == *end
+                    {
+                        *saved = *next;
+                    }
+                }
+            })
+    }
+
+    /// Lowest sharpness among the prepared `frames` when it is under `min_sharpness`.
+    pub fn blurry(&self, frames: &[Mat]) -> Result<Option<f64>, AppError> {
+        let Some(min_sharpness) = self.min_sharpness else {
+            return Ok(None);
+        };
+        let mut lowest = f64::MAX;
+        for frame in frames {
+            lowest = lowest.min(sharpness(frame)?);
+        }
+        Ok(if lowest < min_sharpness {
+            Some(lowest)
+        } else {
+            None
+        })
+    }
+
+    /// Whether camera `cam` is the depth map of a depth sensor.
+    pub fn is_depth(&self, cam: usize) -> bool {
+        self.depth.is_some() && cam + 1 == self.cam_names.len()
+    }
+
+    /// Why a frame in `frames` is over or underexposed, if any is. Depth maps are not
+    /// exposures, so they are not checked.
+    fn clipped(&self, frames: &[Mat]) -> Result<Option<String>, AppError> {
+        let Some(max_clipping) = self.max_clipping else {
+            return Ok(None);
+        };
+        for (_, frame) in frames
+            .iter()
+            .enumerate()
+            .filter(|(cam, _)| !self.is_depth(*cam))
+        {
+            let (dark, bright) = clipping(frame)?;
+            if dark * 100.0 > max_clipping {
+                return Ok(Some(format!(
+                    "{:.1}% of pixels are black (max {}%)",
+                    dark * 100.0,
+                    max_clipping
+                )));
+            }
+            if bright * 100.0 > max_clipping {
+                return Ok(Some(format!(
+                    "{:.1}% of pixels are white (max {}%)",
+                    bright * 100.0,
+                    max_clipping
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies the save-side transforms (undistortion, ROI crop, rotation, flip, resize,
+    /// color space) to the frame of camera `cam`, on a `UMat` with `--gpu`. Depth maps keep
+    /// their single channel.
+    pub fn prepare(&self, frame: &Mat, cam: usize) -> Result<Mat, AppError> {
+        let color = if self.is_depth(cam) {
+            ColorSpace::Bgr
+        } else {
+            self.color
+        };
+        self.prepare_as(frame, color)
+    }
+
+    /// `prepare`, converting the frame to `color` instead of the configured color space.
+    fn prepare_as(&self, frame: &Mat, color: ColorSpace) -> Result<Mat, AppError> {
+        if self.gpu {
+            return Ok(gpu::download(
+                &self.transform(&gpu::upload(frame)?, color)?,
+            )?);
+        }
+        self.transform(frame, color)
+    }
+
+    fn transform<I: Image>(&self, frame: &I, color: ColorSpace) -> Result<I, AppError> {
+        let mut undistorted = I::blank();
+        let frame = match &self.calibration {
+            Some(calibration) => {
+                undistort_def(
+                    frame,
+                    &mut undistorted,
+                    &calibration.camera_matrix,
+                    &calibration.dist_coeffs,
+                )?;
+                &undistorted
+            }
+            None => frame,
+        };
+        let size = frame.frame_size()?;
+        let bounds = Rect::new(0, 0, size.width, size.height);
+        let frame = match self.roi.map(|roi| roi & bounds) {
+            Some(roi) if roi.area() > 0 => frame.crop(roi)?,
+            _ => frame.copy()?,
+        };
+        let frame = orient_frame(&frame, self.rotate, self.flip)?;
+        let frame = match self.out_size {
+            Some(size) => fit_frame(&frame, size, self.fit)?,
+            None => frame,
+        };
+        Ok(convert_color(&frame, color)?)
+    }
+
+    /// The extension to save `frame` with: `ext`, unless that can't hold the frame's depth,
+    /// as JPEG and WebP only hold 8 bits. Those frames, such as depth maps and 16-bit raw
+    /// frames, are written as PNG so no data is lost.
+    fn extension(&self, frame: &Mat) -> &'static str {
+        match self.ext {
+            "jpg" | "webp" if frame.depth() != CV_8U => ImageFormat::Png.extension(),
+            ext => ext,
+        }
+    }
+
+    /// Encoder parameters for an image written to `path`.
+    fn params(&self, path: &Path) -> &Vector<i32> {
+        if path.extension().is_some_and(|ext| ext == self.ext) {
+            &self.compression_params
+        } else {
+            &self.png_params
+        }
+    }
+
+    /// Saves `frames` as a capture of `label` unless the class is full or they fail the
+    /// sharpness or exposure checks. Whether the capture was written.
+    pub fn save_frames(&mut self, label: &str, frames: &[Mat]) -> Result<bool, AppError> {
+        if self.at_limit(label)? {
+            self.confirm(false);
+            return Ok(false);
+        }
+        let shot = self.shot(vec![String::new(); frames.len()], frames)?;
+        self.save_checked(label, shot)
+    }
+
+    /// Saves every face `detect` finds as a capture of `label` of its own, cut from the first
+    /// camera's frame after the save-side transforms so they apply once, to the whole frame.
+    /// Whether any face was written.
+    pub fn save_faces(
+        &mut self,
+        label: &str,
+        raw: &[Mat],
+        detect: impl FnOnce(&Mat) -> opencv::Result<Vector<Rect>>,
+    ) -> Result<bool, AppError> {
+        // Detection wants BGR; each crop is converted on its own afterwards.
+        let frame = self.prepare_as(&raw[0], ColorSpace::Bgr)?;
+        let mut written = false;
+        for face in detect(&frame)? {
+            if self.at_limit(label)? {
+                self.confirm(false);
+                break;
+            }
+            let crop = convert_color(&Mat::roi(&frame, face)?.try_clone()?, self.color)?;
+            let shot = Shot {
+                suffixes: vec![String::new()],
+                raw: &raw[..1],
+                frames: vec![crop],
+            };
+            written |= self.save_checked(label, shot)?;
+        }
+        Ok(written)
+    }
+
+    /// Saves the prepared `shot` unless it fails the sharpness or exposure checks.
+    fn save_checked(&mut self, label: &str, shot: Shot) -> Result<bool, AppError> {
+        if let Some(score) = self.blurry(&shot.frames)? {
+            println!("reject blurry frame ({:.1})", score);
+            self.confirm(false);
+            return Ok(false);
+        }
+        if let Some(reason) = self.clipped(&shot.frames)? {
+            println!("reject badly exposed frame: {}", reason);
+            self.confirm(false);
+            return Ok(false);
+        }
+        if self.dedupe_distance.is_some()
+            && let Some(frame) = shot.frames.first()
+        {
+            self.last_hash = Some(dhash(frame)?);
+        }
+        self.save_shots(label, &[shot])
+    }
+
+    /// `raw`, one frame per camera, as they would be saved.
+    pub fn prepare_all(&self, raw: &[Mat]) -> Result<Vec<Mat>, AppError> {
+        raw.iter()
+            .enumerate()
+            .map(|(cam, frame)| self.prepare(frame, cam))
+            .collect()
+    }
+
+    /// The shot `suffixes` name of `raw`, one frame per camera, prepared for saving.
+    pub fn shot<'a>(&self, suffixes: Vec<String>, raw: &'a [Mat]) -> Result<Shot<'a>, AppError> {
+        Ok(Shot {
+            suffixes,
+            raw,
+            frames: self.prepare_all(raw)?,
+        })
+    }
+
+    /// Writes one capture of `label`: every camera takes its next index, and each shot is
+    /// named from the template with its suffix and saved with its watermark, clean, thumb and
+    /// raw copies, sidecars, database row, manifest entry and hooks. Each shot counts as an
+    /// image of the class. Whether every file was queued.
+    pub fn save_shots(&mut self, label: &str, shots: &[Shot]) -> Result<bool, AppError> {
+        let before = self.class_count(label);
+        let count = before + shots.len();
+        let mut written = true;
+        let mut saved = SavedCapture {
+            label: label.to_string(),
+            shots: shots.len(),
+            indices: Vec::new(),
+            files: Vec::new(),
+        };
+        for cam in 0..self.cam_names.len().max(1) {
+            let dir = self.capture_dir(label, cam)?;
+            let index = self.take_index(&dir)?;
+            saved.indices.push((dir.clone(), index));
+            let name = self.name_template.render(&Fields {
+                label,
+                session: self.session.as_deref(),
+                index,
+                camera: cam,
+            });
+            for shot in shots.iter().filter(|shot| cam < shot.frames.len()) {
+                let stem = format!("{}{}", name, shot.suffixes[cam]);
+                let (queued, files) = self.save_shot(label, shot, cam, &dir, &stem, index)?;
+                written &= queued;
+                saved.files.extend(files);
+            }
+        }
+        self.confirm(written);
+        self.history.push(saved);
+        if let Some(frame) = shots.first().and_then(|shot| shot.frames.first()) {
+            self.thumbnail = Some((frame.try_clone()?, Instant::now()));
+        }
+        self.save_times.push_back(Instant::now());
+        self.counts.insert(label.to_string(), count);
+        *self.saved.entry(label.to_string()).or_default() += shots.len();
+        if let Some(target) = self.target(label)
+            && before < target
+            && target <= count
+        {
+            // The terminal bell doubles as the completion sound.
+            println!("\x07{} reached its target of {}", label, target);
+        }
+        Ok(written)
+    }
+
+    /// Writes the frame of camera `cam` in `shot` as `stem` in `dir`, with its companions.
+    /// Whether everything was queued, and the files written.
+    fn save_shot(
+        &mut self,
+        label: &str,
+        shot: &Shot,
+        cam: usize,
+        dir: &Path,
+        stem: &str,
+        index: i32,
+    ) -> Result<(bool, Vec<PathBuf>), AppError> {
+        let (frame, raw) = (&shot.frames[cam], &shot.raw[cam]);
+        let mut written = true;
+        let mut files = Vec::new();
+        let ext = self.extension(frame);
+        let path = dir.join(format!("{}.{}", stem, ext));
+        files.push(path.clone());
+        let image = match &self.watermark {
+            Some(template) if !self.is_depth(cam) => {
+                let text = template
+                    .replace("{label}", label)
+                    .replace("{index}", &index.to_string())
+                    .replace("{timestamp}", &timestamp(SystemTime::now()));
+                let mut marked = frame.try_clone()?;
+                let org = Point::new(10, marked.rows() - 10);
+                draw_text(&mut marked, &text, org, 0.6)?;
+                if self.keep_clean {
+                    let clean = self.mirror(manifest::CLEAN_DIR, &path);
+                    written &= self.write_image(&clean, frame)?;
+                    files.push(clean);
+                }
+                marked
+            }
+            _ => frame.try_clone()?,
+        };
+        let mut job = Job::new(&path, image, self.params(&path));
+        if self.exif && is_jpeg(&path) {
+            job = job.exif(self.exif_fields(label, cam));
+        }
+        if let Some(manifest) = &self.manifest {
+            let (store_path, manifest, path) = (
+                PathBuf::from(&self.store_path),
+                manifest.clone(),
+                path.clone(),
+            );
+            job = job.then(move || manifest::append(&store_path, &manifest, &path));
+        }
+        if let Some(notifier) = &self.notifier {
+            let (notifier, location, label) = (
+                Arc::clone(notifier),
+                self.sink.location(&path),
+                label.to_string(),
+            );
+            let (session, camera) = (self.session.clone(), self.cameras.get(cam).cloned());
+            job = job.then(move || {
+                notifier.send(
+                    "capture",
+                    &notify::Capture {
+                        path: &location,
+                        label: &label,
+                        session: session.as_deref(),
+                        camera: camera.as_ref(),
+                    },
+                );
+                Ok(())
+            });
+        }
+        if let Some(hooks) = &self.on_save {
+            let (hooks, path, label) = (Arc::clone(hooks), path.clone(), label.to_string());
+            job = job.then(move || {
+                hooks.saved(&path, &label);
+                Ok(())
+            });
+        }
+        written &= self.writer.send(job);
+        if let Some(size) = self.thumbs {
+            match self.write_thumb(&path, frame, size)? {
+                Some(thumb) => files.push(thumb),
+                None => written = false,
+            }
+        }
+        if self.keep_raw {
+            let raw_path = self
+                .mirror(manifest::RAW_DIR, &path)
+                .with_extension(self.extension(raw));
+            written &= self.write_image(&raw_path, raw)?;
+            files.push(raw_path);
+        }
+        // Recorded right away; `check_writes` drops the row again if the write fails.
+        if let Some(db) = &self.db {
+            let settings = serde_json::to_string(&self.cameras.get(cam))?;
+            let hash = dhash(frame)?;
+            db.insert(
+                &path,
+                label,
+                SystemTime::now(),
+                Some(cam),
+                Some(settings),
+                Some(hash),
+            )?;
+        }
+        if self.sidecar {
+            let sidecar = dir.join(format!("{}.json", stem));
+            self.write_sidecar(&sidecar, label, cam, frame)?;
+            files.push(sidecar);
+        }
+        if cam == 0 && !self.markers.is_empty() {
+            let pose = dir.join(format!("{}.pose.toml", stem));
+            self.write_pose(&pose)?;
+            files.push(pose);
+        }
+        Ok((written, files))
+    }
+
+    /// Deletes the files of the last capture and hands its indices back.
+    pub fn undo(&mut self) -> Result<(), AppError> {
+        let Some(saved) = self.history.pop() else {
+            self.notify("nothing to undo".into());
+            return Ok(());
+        };
+        // The files may still be queued; writing them after the delete would bring them back.
+        self.writer.flush();
+        for file in &saved.files {
+            self.sink.remove(file)?;
+            if let Some(db) = &self.db {
+                db.remove(file)?;
+            }
+        }
+        for (dir, index) in &saved.indices {
+            // Indices come from this recorder's reserved range, so only the newest can be
+            // reused without colliding with a later capture.
+            if let Some(next) = self.indice_map.get_mut(&*dir.to_string_lossy())
+                && *next == index + 1
+            {
+                *next = *index;
+            }
+        }
+        if let Some(count) = self.counts.get_mut(&saved.label) {
+            *count = count.saturating_sub(saved.shots);
+        }
+        if let Some(count) = self.saved.get_mut(&saved.label) {
+            *count = count.saturating_sub(saved.shots);
+        }
+        let name = saved
+            .files
+            .first()
+            .and_then(|file| file.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.notify(format!("deleted {}/{}", saved.label, name));
+        Ok(())
+    }
+
+    /// Captures per second over the last `SAVE_RATE_WINDOW`.
+    pub fn save_rate(&mut self) -> f64 {
+        while self
+            .save_times
+            .front()
+            .is_some_and(|saved| saved.elapsed() > SAVE_RATE_WINDOW)
+        {
+            self.save_times.pop_front();
+        }
+        self.save_times.len() as f64 / SAVE_RATE_WINDOW.as_secs_f64()
+    }
+
+    /// Captures per class and duration of this session, printed on exit.
+    pub fn summary(&self) {
+        let seconds = self.started.elapsed().as_secs();
+        let total: usize = self.saved.values().sum();
+        println!(
+            "session: {} captures in {}:{:02}:{:02}",
+            total,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        for (label, count) in self.saved.iter().filter(|(_, count)| **count > 0) {
+            println!("  {:<20} {:>8}", label, count);
+        }
+        println!(
+            "frames: {} dropped, {} duplicate, {} failed reads; {} failed writes",
+            self.events.count(Event::Dropped),
+            self.events.count(Event::Duplicate),
+            self.events.count(Event::ReadFailed),
+            self.events.count(Event::WriteFailed)
+        );
+    }
+
+    /// Beeps (twice on failure) and flashes the preview border after a capture attempt.
+    pub fn confirm(&mut self, saved: bool) {
+        if self.beep {
+            print!("{}", if saved { "\x07" } else { "\x07\x07" });
+            let _ = io::stdout().flush();
+        }
+        self.flash = Some((saved, Instant::now()));
+    }
+
+    /// Message shown on the preview for a few seconds.
+    pub fn notify(&mut self, text: String) {
+        if self.notice.as_ref().is_none_or(|(shown, _)| *shown != text) {
+            println!("{}", text);
+        }
+        self.notice = Some((text, Instant::now()));
+    }
+
+    /// Whether `label` already holds `max_per_class` captures, warning if so.
+    pub fn at_limit(&mut self, label: &str) -> Result<bool, AppError> {
+        let Some(max) = self.max_per_class else {
+            return Ok(false);
+        };
+        if self.class_count(label) < max {
+            return Ok(false);
+        }
+        self.notify(format!("{} is full ({} captures)", label, max));
+        Ok(true)
+    }
+
+    pub fn target(&self, label: &str) -> Option<usize> {
+        self.class_map.target(label).or(self.target)
+    }
+
+    /// Captures of `label` so far, in this and earlier sessions.
+    pub fn class_count(&self, label: &str) -> usize {
+        self.counts.get(label).copied().unwrap_or(0)
+    }
+
+    /// Counts the captures already in the store, across sessions, from the database when
+    /// there is one and else from the files. A capture is counted once, by its first
+    /// camera's image. Classes with a target start at 0 so their progress shows.
+    pub fn seed_counts(&mut self) -> Result<(), AppError> {
+        let first = self.cam_names.first().map(OsStr::new);
+        for sample in dataset::samples(&self.root)? {
+            let camera = sample.relative.parent().and_then(|dir| dir.file_name());
+            if first.is_none_or(|first| camera == Some(first)) {
+                *self.counts.entry(sample.label).or_default() += 1;
+            }
+        }
+        for label in self.class_map.targeted() {
+            self.counts.entry(label.clone()).or_default();
+        }
+        Ok(())
+    }
+
+    /// Reads the camera settings the capture about to be saved records, if anything records
+    /// them: sidecars, EXIF, the database or capture events.
+    pub fn read_cameras(&mut self, videos: &[Box<dyn FrameSource>]) {
+        if self.sidecar || self.exif || self.db.is_some() || self.notifier.is_some() {
+            self.cameras = videos
+                .iter()
+                .zip(&self.devices)
+                .map(|(video, device)| CameraState::read(video, *device))
+                .collect();
+        }
+    }
+
+    fn exif_fields(&self, label: &str, cam: usize) -> exif::ExifFields {
+        let state = self.cameras.get(cam).cloned().unwrap_or_default();
+        let mut description = format!("label={}", label);
+        if let Some(session) = &self.session {
+            description += &format!(" session={}", session);
+        }
+        let settings = [
+            ("device", state.device.map(f64::from)),
+            ("focus", state.focus),
+            ("exposure", state.exposure),
+            ("gain", state.gain),
+        ];
+        let comment = settings
+            .iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, value)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        exif::ExifFields {
+            date_time: timestamp(SystemTime::now()).replace('-', ":"),
+            description,
+            comment,
+        }
+    }
+
+    fn write_sidecar(
+        &self,
+        path: &Path,
+        label: &str,
+        cam: usize,
+        frame: &Mat,
+    ) -> Result<(), AppError> {
+        let now = SystemTime::now();
+        let default_state = CameraState::default();
+        let sidecar = FrameSidecar {
+            timestamp: timestamp(now),
+            unix_time: now
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0),
+            label,
+            width: frame.cols(),
+            height: frame.rows(),
+            camera: self
+                .cameras
+                .get(cam)
+                .or(self.cameras.first())
+                .unwrap_or(&default_state),
+        };
+        self.sink
+            .put(path, serde_json::to_string_pretty(&sidecar)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_pose(&self, path: &Path) -> Result<(), AppError> {
+        let sidecar = PoseSidecar {
+            markers: &self.markers,
+        };
+        self.sink
+            .put(path, toml::to_string_pretty(&sidecar)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Like `save_frames`, but skips frames that look like the last saved one.
+    pub fn save_unique(&mut self, label: &str, frames: &[Mat]) -> Result<bool, AppError> {
+        if let (Some(distance), Some(last_hash), Some(frame)) =
+            (self.dedupe_distance, self.last_hash, frames.first())
+            && (dhash(frame)? ^ last_hash).count_ones() <= distance
+        {
+            return Ok(false);
+        }
+        self.save_frames(label, frames)
+    }
+
+    /// Saves `frames` and `burst - 1` more read after them. Whether any capture was written.
+    pub fn save_burst(
+        &mut self,
+        label: &str,
+        videos: &mut [Box<dyn FrameSource>],
+        frames: &mut [Mat],
+        burst: u32,
+    ) -> Result<bool, AppError> {
+        self.read_cameras(videos);
+        let mut written = false;
+        for shot in 0..burst.max(1) {
+            if shot > 0 {
+                if !read_source(videos, frames, self.depth) {
+                    self.events.record(Event::ReadFailed, 1, "burst frame");
+                    continue;
+                }
+                written |= self.save_unique(label, frames)?;
+            } else {
+                written |= self.save_frames(label, frames)?;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Where `path` goes in the `tree` directory below the store path.
+    fn mirror(&self, tree: &str, path: &Path) -> PathBuf {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.root.join(tree).join(relative)
+    }
+
+    /// Writes a JPEG of `frame` scaled into `size` at the place of `path` in the `thumbs/`
+    /// tree, returning where, or `None` if it could not be queued.
+    fn write_thumb(
+        &self,
+        path: &Path,
+        frame: &Mat,
+        size: OutSize,
+    ) -> Result<Option<PathBuf>, AppError> {
+        let thumb = self
+            .mirror(manifest::THUMBS_DIR, path)
+            .with_extension("jpg");
+        let frame = to_display(frame)?;
+        let scale = (size.width as f64 / frame.cols().max(1) as f64)
+            .min(size.height as f64 / frame.rows().max(1) as f64)
+            .min(1.0);
+        let mut small = Mat::default();
+        resize(
+            &frame,
+            &mut small,
+            Size::default(),
+            scale,
+            scale,
+            INTER_AREA,
+        )?;
+        let params = Vector::from_slice(&[IMWRITE_JPEG_QUALITY, THUMB_QUALITY]);
+        Ok(self
+            .writer
+            .send(Job::new(&thumb, small, &params))
+            .then_some(thumb))
+    }
+
+    /// Queues the image on the writer thread. Whether the image was queued; write failures
+    /// are reported by `check_writes` but don't end the session.
+    fn write_image(&self, path: &Path, img: &Mat) -> Result<bool, AppError> {
+        let job = Job::new(path, img.try_clone()?, self.params(path));
+        Ok(self.writer.send(job))
+    }
+
+    /// Reports images the writer thread failed to write and forgets them in the database.
+    pub fn check_writes(&mut self) -> Result<(), AppError> {
+        let failures = self.writer.failures();
+        for path in &failures {
+            self.events
+                .record(Event::WriteFailed, 1, &path.to_string_lossy());
+            if let Some(db) = &self.db {
+                db.remove(path)?;
+            }
+        }
+        if let Some(path) = failures.last() {
+            self.confirm(false);
+            self.notify(format!("failed to write {:?}", path));
+        }
+        // The images are in place, so their database rows stay.
+        if let Some((path, error)) = self.writer.then_failures().last() {
+            self.notify(format!("after writing {:?}: {}", path, error));
+        }
+        Ok(())
+    }
+}
+
+/// Reads a few frames after a camera setting changed, so `frames` show its effect.
+fn settle(
+    recorder: &Recorder,
+    videos: &mut [Box<dyn FrameSource>],
+    frames: &mut [Mat],
+) -> Result<(), AppError> {
+    for _ in 0..SETTLE_FRAMES {
+        if !read_source(videos, frames, recorder.depth) {
+            return Err(AppError::StreamError("cannot read bracket frame".into()));
+        }
+    }
+    Ok(())
+}
+
+/// Copies of `frames` that the next read leaves alone.
+fn copy_frames(frames: &[Mat]) -> opencv::Result<Vec<Mat>> {
+    frames.iter().map(|frame| frame.try_clone()).collect()
+}
+
+/// Saves one capture of `label` whose shots step every camera's focus around where it was,
+/// named `_f<focus>`.
+pub fn focus_bracket(
+    recorder: &mut Recorder,
+    label: &str,
+    videos: &mut [Box<dyn FrameSource>],
+    frames: &mut [Mat],
+    settings: &CaptureSettings,
+) -> Result<(), Box<dyn Error>> {
+    recorder.read_cameras(videos);
+    let bases = videos
+        .iter()
+        .map(|video| video.focus())
+        .collect::<Result<Vec<_>, _>>()?;
+    for video in videos.iter_mut() {
+        let _ = video.set(CAP_PROP_AUTOFOCUS, 0.0);
+    }
+    let count = settings.focus_bracket;
+    let mut steps = Vec::new();
+    for step in 0..count {
+        let offset = step as f64 - (count - 1) as f64 / 2.0;
+        let mut suffixes = Vec::new();
+        for (video, base) in videos.iter_mut().zip(&bases) {
+            let focus = (base + offset * settings.bracket_step).max(0.0);
+            video.set_focus(focus)?;
+            suffixes.push(format!("_f{}", focus));
+        }
+        settle(recorder, videos, frames)?;
+        // A depth map belongs to the color camera's shot.
+        suffixes.resize(frames.len(), suffixes.last().cloned().unwrap_or_default());
+        steps.push((suffixes, copy_frames(frames)?));
+    }
+    for (video, base) in videos.iter_mut().zip(&bases) {
+        video.set_focus(*base)?;
+    }
+    let shots = steps
+        .iter()
+        .map(|(suffixes, raw)| recorder.shot(suffixes.clone(), raw))
+        .collect::<Result<Vec<_>, _>>()?;
+    recorder.save_shots(label, &shots)?;
+    Ok(())
+}
+
+fn bracket_exposure(base: f64, ev: f64) -> f64 {
+    // Backends report exposure either as log2 seconds (<= 0) or in linear units.
+    if base <= 0.0 {
+        base + ev
+    } else {
+        base * 2f64.powf(ev)
+    }
+}
+
+/// Saves one capture of `label` with a shot at each of `exposure_bracket`'s EV offsets from
+/// every camera's exposure, named `_ev<ev>`, and with `hdr_merge` their Mertens fusion as
+/// `_hdr`.
+pub fn exposure_bracket(
+    recorder: &mut Recorder,
+    label: &str,
+    videos: &mut [Box<dyn FrameSource>],
+    frames: &mut [Mat],
+    settings: &CaptureSettings,
+) -> Result<(), Box<dyn Error>> {
+    recorder.read_cameras(videos);
+    let bases = videos
+        .iter()
+        .map(|video| video.exposure())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut steps = Vec::new();
+    for ev in &settings.exposure_bracket {
+        for (video, base) in videos.iter_mut().zip(&bases) {
+            video.set_exposure(bracket_exposure(*base, *ev))?;
+        }
+        settle(recorder, videos, frames)?;
+        steps.push((
+            vec![format!("_ev{}", ev); frames.len()],
+            copy_frames(frames)?,
+        ));
+    }
+    for (video, base) in videos.iter_mut().zip(&bases) {
+        video.set_exposure(*base)?;
+    }
+    if settings.hdr_merge && !steps.is_empty() {
+        let mut fused_frames = Vec::new();
+        for cam in 0..frames.len() {
+            if recorder.is_depth(cam) {
+                // A depth map has no exposure to fuse.
+                fused_frames.push(steps[0].1[cam].try_clone()?);
+                continue;
+            }
+            let exposures: Vector<Mat> = steps
+                .iter()
+                .map(|(_, raw)| raw[cam].try_clone())
+                .collect::<Result<_, _>>()?;
+            let mut merge = create_merge_mertens_def()?;
+            let mut fused = Mat::default();
+            MergeMertensTrait::process(&mut merge, &exposures, &mut fused)?;
+            let mut fused_8u = Mat::default();
+            fused.convert_to(&mut fused_8u, CV_8U, 255.0, 0.0)?;
+            fused_frames.push(fused_8u);
+        }
+        steps.push((vec!["_hdr".to_string(); frames.len()], fused_frames));
+    }
+    let shots = steps
+        .iter()
+        .map(|(suffixes, raw)| recorder.shot(suffixes.clone(), raw))
+        .collect::<Result<Vec<_>, _>>()?;
+    recorder.save_shots(label, &shots)?;
+    Ok(())
+}
+
+fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::sink::LocalDir;
+    use crate::source::MockSource;
+    use opencv::core::{CV_8UC3, Scalar};
+
+    /// An empty directory for one test, below the system temp directory.
+    pub fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_recorder_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A recorder saving PNGs of one camera to `store`, with every option off.
+    pub fn recorder(store: &Path, template: &str) -> Recorder {
+        let sink: Arc<dyn FrameSink> = Arc::new(LocalDir);
+        Recorder {
+            store_path: store.to_string_lossy().into_owned(),
+            cam_names: Vec::new(),
+            indice_map: HashMap::new(),
+            reserved: HashMap::new(),
+            ext: ImageFormat::Png.extension(),
+            compression_params: ImageFormat::Png.params(None, None),
+            png_params: ImageFormat::Png.params(None, None),
+            markers: Vec::new(),
+            min_sharpness: None,
+            max_clipping: None,
+            dedupe_distance: None,
+            last_hash: None,
+            roi: None,
+            out_size: None,
+            fit: Fit::Crop,
+            color: ColorSpace::Bgr,
+            rotate: None,
+            flip: None,
+            gpu: false,
+            calibration: None,
+            depth: None,
+            watermark: None,
+            keep_clean: false,
+            thumbs: None,
+            keep_raw: false,
+            root: store.canonicalize().unwrap(),
+            sidecar: false,
+            cameras: Vec::new(),
+            devices: vec![None],
+            exif: false,
+            session: None,
+            manifest: None,
+            on_save: None,
+            notifier: None,
+            class_map: classes::ClassMap::default(),
+            target: None,
+            counts: HashMap::new(),
+            max_per_class: None,
+            notice: None,
+            history: Vec::new(),
+            save_times: VecDeque::new(),
+            thumbnail: None,
+            beep: false,
+            flash: None,
+            name_template: template.parse().unwrap(),
+            db: None,
+            lock: index::StoreLock::open(store).unwrap(),
+            sink: Arc::clone(&sink),
+            writer: Writer::spawn(sink),
+            events: Arc::new(Events::default()),
+            started: Instant::now(),
+            saved: BTreeMap::new(),
+        }
+    }
+
+    /// A frame of one camera, 64x48 and filled with `value`.
+    pub fn flat(value: f64) -> Mat {
+        Mat::new_rows_cols_with_default(48, 64, CV_8UC3, Scalar::all(value)).unwrap()
+    }
+
+    /// A mock camera that shows `count` gray frames, and a frame to read them into.
+    pub fn camera(count: usize) -> (Vec<Box<dyn FrameSource>>, Vec<Mat>) {
+        let frames = (0..count).map(|_| flat(128.0)).collect();
+        (
+            vec![Box::new(MockSource::new(frames))],
+            vec![Mat::default()],
+        )
+    }
+
+    #[test]
+    fn save_frames_writes_captures_named_from_the_template() {
+        let store = scratch("save_frames");
+        let mut recorder = recorder(&store, "{label}_{index:03}");
+        let (mut videos, mut frames) = camera(2);
+        for _ in 0..2 {
+            assert!(read_source(&mut videos, &mut frames, None));
+            assert!(recorder.save_frames("cat", &frames).unwrap());
+        }
+        recorder.writer.flush();
+        assert!(recorder.writer.failures().is_empty());
+        assert!(store.join("cat/cat_000.png").is_file());
+        assert!(store.join("cat/cat_001.png").is_file());
+        assert_eq!(recorder.class_count("cat"), 2);
+        assert_eq!(recorder.saved["cat"], 2);
+    }
+
+    #[test]
+    fn save_frames_rejects_captures_of_a_full_class() {
+        let store = scratch("max_per_class");
+        let mut recorder = recorder(&store, "{index}");
+        recorder.max_per_class = Some(1);
+        let (mut videos, mut frames) = camera(2);
+        assert!(read_source(&mut videos, &mut frames, None));
+        assert!(recorder.save_frames("cat", &frames).unwrap());
+        assert!(read_source(&mut videos, &mut frames, None));
+        assert!(!recorder.save_frames("cat", &frames).unwrap());
+        recorder.writer.flush();
+        assert!(store.join("cat/0.png").is_file());
+        assert!(!store.join("cat/1.png").exists());
+        assert_eq!(recorder.class_count("cat"), 1);
+    }
+
+    #[test]
+    fn undo_removes_the_capture_and_hands_its_index_back() {
+        let store = scratch("undo");
+        let mut recorder = recorder(&store, "{index}");
+        let (mut videos, mut frames) = camera(2);
+        assert!(read_source(&mut videos, &mut frames, None));
+        recorder.save_frames("cat", &frames).unwrap();
+        recorder.undo().unwrap();
+        assert!(!store.join("cat/0.png").exists());
+        assert_eq!(recorder.class_count("cat"), 0);
+        assert!(read_source(&mut videos, &mut frames, None));
+        recorder.save_frames("cat", &frames).unwrap();
+        recorder.writer.flush();
+        assert!(store.join("cat/0.png").is_file());
+    }
+
+    #[test]
+    fn save_burst_reads_the_extra_shots_from_the_camera() {
+        let store = scratch("burst");
+        let mut recorder = recorder(&store, "{index}");
+        let (mut videos, mut frames) = camera(3);
+        assert!(read_source(&mut videos, &mut frames, None));
+        assert!(
+            recorder
+                .save_burst("cat", &mut videos, &mut frames, 3)
+                .unwrap()
+        );
+        recorder.writer.flush();
+        for index in 0..3 {
+            assert!(store.join(format!("cat/{}.png", index)).is_file());
+        }
+        assert_eq!(recorder.class_count("cat"), 3);
+        // Each shot of a burst is a capture of its own, undone one at a time.
+        assert_eq!(recorder.history.len(), 3);
+    }
+
+    #[test]
+    fn save_burst_skips_shots_that_repeat_the_last_capture() {
+        let store = scratch("burst_dedupe");
+        let mut recorder = recorder(&store, "{index}");
+        recorder.dedupe_distance = Some(0);
+        let (mut videos, mut frames) = camera(3);
+        assert!(read_source(&mut videos, &mut frames, None));
+        assert!(
+            recorder
+                .save_burst("cat", &mut videos, &mut frames, 3)
+                .unwrap()
+        );
+        recorder.writer.flush();
+        assert!(store.join("cat/0.png").is_file());
+        assert!(!store.join("cat/1.png").exists());
+        assert_eq!(recorder.class_count("cat"), 1);
+    }
+
+    #[test]
+    fn save_frames_rejects_blurry_and_clipped_frames() {
+        let store = scratch("checks");
+        let mut recorder = recorder(&store, "{index}");
+        // A flat frame has no edges at all.
+        recorder.min_sharpness = Some(1.0);
+        assert!(!recorder.save_frames("cat", &[flat(128.0)]).unwrap());
+        assert!(matches!(recorder.flash, Some((false, _))));
+        recorder.min_sharpness = None;
+        recorder.max_clipping = Some(50.0);
+        assert!(!recorder.save_frames("cat", &[flat(0.0)]).unwrap());
+        assert!(!recorder.save_frames("cat", &[flat(255.0)]).unwrap());
+        assert!(recorder.save_frames("cat", &[flat(128.0)]).unwrap());
+        recorder.writer.flush();
+        assert!(store.join("cat/0.png").is_file());
+        assert_eq!(recorder.class_count("cat"), 1);
+    }
+
+    #[test]
+    fn prepare_applies_the_save_side_transforms() {
+        let store = scratch("prepare");
+        let mut recorder = recorder(&store, "{index}");
+        recorder.rotate = Some(Rotation::Cw90);
+        recorder.color = ColorSpace::Gray;
+        let prepared = recorder.prepare(&flat(128.0), 0).unwrap();
+        assert_eq!((prepared.cols(), prepared.rows()), (48, 64));
+        assert_eq!(prepared.channels(), 1);
+        recorder.out_size = Some(OutSize {
+            width: 32,
+            height: 32,
+        });
+        recorder.roi = Some(Rect::new(0, 0, 16, 16));
+        let prepared = recorder.prepare(&flat(128.0), 0).unwrap();
+        assert_eq!((prepared.cols(), prepared.rows()), (32, 32));
+    }
+
+    #[test]
+    fn bracket_shots_share_an_index_and_undo_together() {
+        let store = scratch("shots");
+        let mut recorder = recorder(&store, "{index}");
+        let raw = [flat(100.0), flat(150.0)];
+        let shots = vec![
+            recorder.shot(vec!["_f10".into()], &raw[..1]).unwrap(),
+            recorder.shot(vec!["_f20".into()], &raw[1..]).unwrap(),
+        ];
+        assert!(recorder.save_shots("cat", &shots).unwrap());
+        recorder.writer.flush();
+        assert!(store.join("cat/0_f10.png").is_file());
+        assert!(store.join("cat/0_f20.png").is_file());
+        assert_eq!(recorder.class_count("cat"), 2);
+        recorder.undo().unwrap();
+        assert!(!store.join("cat/0_f10.png").exists());
+        assert!(!store.join("cat/0_f20.png").exists());
+        assert_eq!(recorder.class_count("cat"), 0);
+    }
+
+    #[test]
+    fn unused_reserved_indices_are_handed_back() {
+        let store = scratch("reserve");
+        let mut recorder = recorder(&store, "{index}");
+        assert!(recorder.save_frames("cat", &[flat(128.0)]).unwrap());
+        let dir = recorder.capture_dir("cat", 0).unwrap();
+        let dir = dir.to_string_lossy().into_owned();
+        let saved = index::load_state(&store).unwrap().unwrap();
+        assert_eq!(saved[&dir], INDEX_RESERVATION);
+        recorder.release_indices().unwrap();
+        let saved = index::load_state(&store).unwrap().unwrap();
+        assert_eq!(saved[&dir], 1);
+    }
+
+    #[test]
+    fn bracket_exposure_steps_in_stops() {
+        assert_eq!(bracket_exposure(-6.0, 1.0), -5.0);
+        assert_eq!(bracket_exposure(100.0, -1.0), 50.0);
+        assert_eq!(bracket_exposure(100.0, 2.0), 400.0);
+    }
+}
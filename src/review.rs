@@ -0,0 +1,121 @@
+use opencv::{
+    core::{CV_8UC3, Mat, Point, Scalar},
+    highgui::{imshow, wait_key_ex},
+    imgcodecs::{IMREAD_UNCHANGED, imread},
+    prelude::*,
+};
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::classes::ClassMap;
+use crate::dataset::{self, Sample};
+use crate::db::Db;
+use crate::naming::NameTemplate;
+use crate::ui::{KEY_LEFT, KEY_RIGHT, draw_text, to_display};
+use crate::{AppError, db, index};
+
+/// Moves `sample` (and its sidecars and mirrored copies) to the same camera directory under
+/// `label`, renamed from `template` with the next free index there.
+fn relabel(
+    store_path: &Path,
+    template: &NameTemplate,
+    sample: &Sample,
+    label: &str,
+) -> Result<PathBuf, AppError> {
+    let relative = Path::new(label).join(sample.relative.parent().unwrap_or(Path::new("")));
+    let dir = store_path.join(&relative);
+    fs::create_dir_all(&dir)?;
+    let next = index::next_free(&dir, template)?;
+    let db = Db::existing(store_path)?;
+    let mut moved = dir.join(sample.path.file_name().unwrap_or_default());
+    for path in dataset::with_sidecars(store_path, &sample.path) {
+        let target_dir = match dataset::mirror_tree(store_path, &path) {
+            Some(tree) => store_path.join(tree).join(&relative),
+            None => dir.clone(),
+        };
+        fs::create_dir_all(&target_dir)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let target = match template.rename(&name, next, Some(label)) {
+            Some(renamed) => target_dir.join(renamed),
+            None => target_dir.join(name.as_ref()),
+        };
+        if path == sample.path {
+            moved = target.clone();
+        }
+        fs::rename(&path, &target)?;
+        if let Some(db) = &db {
+            db.rename(&path, &target, Some(label))?;
+        }
+    }
+    Ok(moved)
+}
+
+/// Steps through the saved images: arrows navigate, a class key moves the image to that
+/// class, `d` deletes it, Esc quits.
+pub fn run(store_path: &Path, template: &NameTemplate) -> Result<(), Box<dyn Error>> {
+    let mut samples = dataset::samples(store_path)?;
+    let class_map = ClassMap::from_store(store_path)?;
+    // Relabelled images take indices in other directories.
+    index::invalidate(store_path)?;
+    let mut current = 0;
+    while current < samples.len() {
+        let sample = &samples[current];
+        let path = sample
+            .path
+            .to_str()
+            .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+        let image = imread(path, IMREAD_UNCHANGED)?;
+        let mut preview = if image.empty() {
+            Mat::new_rows_cols_with_default(240, 320, CV_8UC3, Scalar::all(0.0))?
+        } else {
+            to_display(&image)?
+        };
+        let status = format!("{} ({}/{})", sample.label, current + 1, samples.len());
+        draw_text(&mut preview, &status, Point::new(10, 30), 0.8)?;
+        let name = sample.relative.to_string_lossy();
+        draw_text(&mut preview, &name, Point::new(10, 60), 0.6)?;
+        if imshow("review", &preview).is_err() {
+            break;
+        }
+        let key = wait_key_ex(0).unwrap_or(-1);
+        if KEY_LEFT.contains(&key) {
+            current = current.saturating_sub(1);
+            continue;
+        }
+        if KEY_RIGHT.contains(&key) {
+            current = (current + 1).min(samples.len() - 1);
+            continue;
+        }
+        match char::from_u32((key & 0xFF) as u32) {
+            Some('\x1b') => break,
+            Some('d') => {
+                dataset::remove(store_path, &sample.path, None)?;
+                println!("delete {:?}", sample.path);
+                samples.remove(current);
+                current = current.min(samples.len().saturating_sub(1));
+            }
+            Some(key @ ('a'..='z' | '0'..='9' | 'A'..='Z')) => {
+                let label = class_map.label(key);
+                if label == sample.label {
+                    continue;
+                }
+                let moved = relabel(store_path, template, sample, &label)?;
+                println!("move {:?} to {:?}", sample.path, moved);
+                let relative = moved
+                    .strip_prefix(store_path.join(&label))
+                    .unwrap_or(&moved)
+                    .to_path_buf();
+                samples[current] = Sample {
+                    path: moved,
+                    label,
+                    relative,
+                };
+                current = (current + 1).min(samples.len() - 1);
+            }
+            _ => {}
+        }
+    }
+    Ok(db::sync(store_path)?)
+}
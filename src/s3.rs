@@ -0,0 +1,219 @@
+//! `--store s3://bucket/prefix`: captures are uploaded to an S3-compatible bucket through the
+//! AWS CLI, which brings its own credentials, region and `AWS_ENDPOINT_URL` handling. Each file
+//! is spooled under `.spool/` in the store path, uploaded in order on a background thread with
+//! retries, and deleted once it is in the bucket. Uploads a session could not finish stay in
+//! the spool and go first in the next one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::AppError;
+use crate::manifest::SPOOL_DIR;
+use crate::sink::{FrameSink, write_atomic};
+
+/// Tries per upload while the bucket is reachable.
+const ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubled for each further one.
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// Paths relative to the store path.
+enum Upload {
+    Put(PathBuf),
+    Remove(PathBuf),
+}
+
+pub struct S3Sink {
+    /// The store path as given and canonicalized; capture paths start with either.
+    store_path: PathBuf,
+    root: PathBuf,
+    spool: PathBuf,
+    /// `s3://bucket/prefix`, without a trailing slash.
+    target: String,
+    uploads: Option<Sender<Upload>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Runs `aws` with `args`, failing with its error output.
+fn aws(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("aws")
+        .args(args)
+        .arg("--only-show-errors")
+        .stdin(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::other(
+        String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    ))
+}
+
+/// `relative` as an object key below `target`.
+fn key(target: &str, relative: &Path) -> String {
+    let parts: Vec<_> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    format!("{}/{}", target, parts.join("/"))
+}
+
+fn upload(file: &Path, key: &str, attempts: u32) -> io::Result<()> {
+    let mut wait = BACKOFF;
+    for attempt in 1..attempts {
+        match aws(&["s3", "cp", &file.to_string_lossy(), key]) {
+            Ok(()) => return Ok(()),
+            Err(e) => println!("upload to {} failed ({}/{}): {}", key, attempt, attempts, e),
+        }
+        thread::sleep(wait);
+        wait *= 2;
+    }
+    aws(&["s3", "cp", &file.to_string_lossy(), key])
+}
+
+impl S3Sink {
+    /// Checks for the AWS CLI and starts uploading to `url`, spooling under `store_path`.
+    pub fn open(url: &str, store_path: &Path) -> Result<Self, AppError> {
+        let target = url
+            .strip_prefix("s3://")
+            .map(|bucket| bucket.trim_end_matches('/'))
+            .filter(|bucket| !bucket.is_empty() && !bucket.starts_with('/'))
+            .ok_or(AppError::PathError(format!(
+                "--store needs an s3://bucket/prefix URL, got {}",
+                url
+            )))?;
+        let target = format!("s3://{}", target);
+        if aws(&["--version"]).is_err() {
+            return Err(AppError::PathError(
+                "an s3:// store needs the AWS CLI (aws) on the PATH".into(),
+            ));
+        }
+        let root = store_path.canonicalize()?;
+        let spool = root.join(SPOOL_DIR);
+        fs::create_dir_all(&spool)?;
+        let mut leftover = Vec::new();
+        for entry in glob::glob(&spool.join("**/*").to_string_lossy())? {
+            let entry = entry?;
+            // A `.tmp` file is a write that never finished.
+            if entry.is_file() && entry.extension().is_none_or(|ext| ext != "tmp") {
+                leftover.push(entry.strip_prefix(&spool).unwrap_or(&entry).to_path_buf());
+            }
+        }
+        leftover.sort();
+        let (uploads, queue) = mpsc::channel();
+        if !leftover.is_empty() {
+            println!("resuming {} uploads to {}", leftover.len(), target);
+        }
+        for relative in leftover {
+            let _ = uploads.send(Upload::Put(relative));
+        }
+        let (thread_spool, thread_target) = (spool.clone(), target.clone());
+        let handle = thread::spawn(move || {
+            // After an upload runs out of retries, later ones get a single try until one
+            // gets through, so an offline session doesn't spend minutes on each at exit.
+            let mut offline = false;
+            for message in queue {
+                match message {
+                    Upload::Put(relative) => {
+                        let file = thread_spool.join(&relative);
+                        // Undone before its turn came.
+                        if !file.is_file() {
+                            continue;
+                        }
+                        let key = key(&thread_target, &relative);
+                        match upload(&file, &key, if offline { 1 } else { ATTEMPTS }) {
+                            Ok(()) => {
+                                offline = false;
+                                if let Err(e) = fs::remove_file(&file) {
+                                    println!("cannot remove {:?} from the spool: {}", file, e);
+                                }
+                            }
+                            Err(e) => {
+                                offline = true;
+                                println!("keep {:?} in the spool for later: {}", relative, e);
+                            }
+                        }
+                    }
+                    Upload::Remove(relative) => {
+                        let key = key(&thread_target, &relative);
+                        if let Err(e) = aws(&["s3", "rm", &key]) {
+                            println!("cannot delete {}: {}", key, e);
+                        }
+                    }
+                }
+            }
+        });
+        Ok(S3Sink {
+            store_path: store_path.to_path_buf(),
+            root,
+            spool,
+            target,
+            uploads: Some(uploads),
+            handle: Some(handle),
+        })
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> io::Result<&'a Path> {
+        path.strip_prefix(&self.root)
+            .or_else(|_| path.strip_prefix(&self.store_path))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is outside the store path", path),
+                )
+            })
+    }
+
+    fn send(&self, upload: Upload) -> io::Result<()> {
+        self.uploads
+            .as_ref()
+            .and_then(|uploads| uploads.send(upload).ok())
+            .ok_or(io::Error::other("the upload thread is gone"))
+    }
+}
+
+impl FrameSink for S3Sink {
+    fn put(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let relative = self.relative(path)?;
+        let spooled = self.spool.join(relative);
+        if let Some(dir) = spooled.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&spooled, bytes)?;
+        self.send(Upload::Put(relative.to_path_buf()))
+    }
+
+    /// Drops the file from the spool if it is still waiting, and from the bucket once the
+    /// uploads queued before it are done.
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let relative = self.relative(path)?;
+        let spooled = self.spool.join(relative);
+        if spooled.is_file() {
+            fs::remove_file(&spooled)?;
+        }
+        self.send(Upload::Remove(relative.to_path_buf()))
+    }
+
+    /// The object URL, as the spooled file is gone once it is uploaded.
+    fn location(&self, path: &Path) -> String {
+        match self.relative(path) {
+            Ok(relative) => key(&self.target, relative),
+            Err(_) => path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+impl Drop for S3Sink {
+    /// Finishes the queued uploads.
+    fn drop(&mut self) {
+        self.uploads.take();
+        if let Some(handle) = self.handle.take() {
+            println!("finishing uploads");
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! `--session`: a run's captures go to `store_path/<start time>[_<name>]/<label>/`, next to a
+//! `session.toml` recording when and how the run was started and what it captured.
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::naming::file_timestamp;
+use crate::{AppError, sanitize_label, timestamp};
+
+/// Marks a directory below the store path as a session rather than a class.
+pub const FILE: &str = "session.toml";
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    /// Directory name, also written to the EXIF description of the session's captures.
+    pub name: String,
+    started: String,
+    ended: Option<String>,
+    command: Vec<String>,
+    captures: BTreeMap<String, usize>,
+}
+
+impl Session {
+    /// A session named after the current time, followed by `name` if given.
+    pub fn start(name: Option<&str>) -> Self {
+        let now = SystemTime::now();
+        let started = timestamp(now);
+        let mut dir = file_timestamp(now);
+        if let Some(name) = name {
+            dir = format!("{}_{}", dir, sanitize_label(name));
+        }
+        Session {
+            name: dir,
+            started,
+            ended: None,
+            command: env::args().collect(),
+            captures: BTreeMap::new(),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), AppError> {
+        fs::write(dir.join(FILE), toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Records the end time and the captures per class.
+    pub fn finish(
+        &mut self,
+        dir: &Path,
+        captures: &BTreeMap<String, usize>,
+    ) -> Result<(), AppError> {
+        self.ended = Some(timestamp(SystemTime::now()));
+        self.captures = captures.clone();
+        self.save(dir)
+    }
+}
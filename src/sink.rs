@@ -0,0 +1,54 @@
+//! Where captures are written. The recorder names every file by its path below the store
+//! path and hands the bytes to a `FrameSink`; `LocalDir` writes them there on this machine,
+//! other sinks map the paths to their own keys.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub trait FrameSink: Send + Sync {
+    /// Stores `bytes` as `path`, replacing what was there.
+    fn put(&self, path: &Path, bytes: &[u8]) -> io::Result<()>;
+    /// Deletes `path`, e.g. to undo a capture. Deleting a missing file is not an error.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    /// Where `path` ends up, as capture events report it.
+    fn location(&self, path: &Path) -> String;
+}
+
+/// The files themselves, under the store path.
+pub struct LocalDir;
+
+impl FrameSink for LocalDir {
+    fn put(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(path, bytes)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn location(&self, path: &Path) -> String {
+        path.to_string_lossy().into_owned()
+    }
+}
+
+pub fn create_data_dir(path: &str) -> io::Result<()> {
+    fs::create_dir_all(path)
+}
+
+/// Replaces `path` with `bytes` through a temporary file, so readers never see partial data.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
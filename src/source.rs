@@ -0,0 +1,929 @@
+//! Where frames come from. Cameras, streams, video files and GStreamer pipelines are opened
+//! through `VideoCapture`; everything that reads frames works on `FrameSource`, so a
+//! `MockSource` can stand in for a camera.
+
+use clap::{Subcommand, ValueEnum};
+use opencv::core::{Mat, Vector};
+use opencv::prelude::{MatTraitConst, VideoCaptureTraitConst};
+use opencv::videoio::{
+    CAP_ANY, CAP_AVFOUNDATION, CAP_DSHOW, CAP_FFMPEG, CAP_GSTREAMER, CAP_INTELPERC_DEPTH_MAP,
+    CAP_INTELPERC_IMAGE, CAP_MSMF, CAP_OPENNI_BGR_IMAGE, CAP_OPENNI_DEPTH_GENERATOR,
+    CAP_OPENNI_DEPTH_MAP, CAP_OPENNI2, CAP_PROP_AUTO_EXPOSURE, CAP_PROP_AUTO_WB,
+    CAP_PROP_AUTOFOCUS, CAP_PROP_BRIGHTNESS, CAP_PROP_CONVERT_RGB, CAP_PROP_EXPOSURE,
+    CAP_PROP_FOCUS, CAP_PROP_FOURCC, CAP_PROP_FPS, CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH,
+    CAP_PROP_GAIN, CAP_PROP_HW_ACCELERATION, CAP_PROP_OPEN_TIMEOUT_MSEC,
+    CAP_PROP_OPENNI_REGISTRATION, CAP_PROP_PAN, CAP_PROP_READ_TIMEOUT_MSEC, CAP_PROP_TILT,
+    CAP_PROP_WB_TEMPERATURE, CAP_PROP_ZOOM, CAP_REALSENSE, CAP_V4L2, VIDEO_ACCELERATION_ANY,
+    VIDEO_ACCELERATION_D3D11, VIDEO_ACCELERATION_MFX, VIDEO_ACCELERATION_NONE,
+    VIDEO_ACCELERATION_VAAPI, VideoCapture, VideoWriter,
+};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::AppError;
+use crate::pattern::TestSource;
+
+/// Anything frames are read from. The methods mirror `VideoCapture`'s, properties being
+/// `CAP_PROP_*` ids.
+pub trait FrameSource: Send {
+    /// Takes the next frame without decoding it, so several sources can be read close in time.
+    fn grab(&mut self) -> opencv::Result<bool>;
+    /// Decodes the grabbed frame; `flag` picks a channel, e.g. the depth map of an RGB-D camera.
+    fn retrieve(&mut self, frame: &mut Mat, flag: i32) -> opencv::Result<bool>;
+    /// A property's value, 0 if the source doesn't have it.
+    fn get(&self, prop: i32) -> opencv::Result<f64>;
+    /// Whether the source took the value.
+    fn set(&mut self, prop: i32, value: f64) -> opencv::Result<bool>;
+    fn release(&mut self) -> opencv::Result<()>;
+
+    fn read(&mut self, frame: &mut Mat) -> opencv::Result<bool> {
+        Ok(self.grab()? && self.retrieve(frame, 0)?)
+    }
+}
+
+impl FrameSource for VideoCapture {
+    fn grab(&mut self) -> opencv::Result<bool> {
+        opencv::videoio::VideoCaptureTrait::grab(self)
+    }
+
+    fn retrieve(&mut self, frame: &mut Mat, flag: i32) -> opencv::Result<bool> {
+        opencv::videoio::VideoCaptureTrait::retrieve(self, frame, flag)
+    }
+
+    fn get(&self, prop: i32) -> opencv::Result<f64> {
+        VideoCaptureTraitConst::get(self, prop)
+    }
+
+    fn set(&mut self, prop: i32, value: f64) -> opencv::Result<bool> {
+        opencv::videoio::VideoCaptureTrait::set(self, prop, value)
+    }
+
+    fn release(&mut self) -> opencv::Result<()> {
+        opencv::videoio::VideoCaptureTrait::release(self)
+    }
+
+    fn read(&mut self, frame: &mut Mat) -> opencv::Result<bool> {
+        opencv::videoio::VideoCaptureTrait::read(self, frame)
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn grab(&mut self) -> opencv::Result<bool> {
+        (**self).grab()
+    }
+
+    fn retrieve(&mut self, frame: &mut Mat, flag: i32) -> opencv::Result<bool> {
+        (**self).retrieve(frame, flag)
+    }
+
+    fn get(&self, prop: i32) -> opencv::Result<f64> {
+        (**self).get(prop)
+    }
+
+    fn set(&mut self, prop: i32, value: f64) -> opencv::Result<bool> {
+        (**self).set(prop, value)
+    }
+
+    fn release(&mut self) -> opencv::Result<()> {
+        (**self).release()
+    }
+
+    fn read(&mut self, frame: &mut Mat) -> opencv::Result<bool> {
+        (**self).read(frame)
+    }
+}
+
+/// Hands out the given frames in turn and then fails to read, remembering whatever properties
+/// are set, for driving the capture code without a camera.
+#[derive(Default)]
+pub struct MockSource {
+    frames: VecDeque<Mat>,
+    frame: Mat,
+    props: HashMap<i32, f64>,
+}
+
+impl MockSource {
+    pub fn new(frames: Vec<Mat>) -> Self {
+        MockSource {
+            frames: frames.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Starts with `prop` at `value`, e.g. the frame rate a file would report.
+    pub fn with(mut self, prop: i32, value: f64) -> Self {
+        self.props.insert(prop, value);
+        self
+    }
+}
+
+impl FrameSource for MockSource {
+    fn grab(&mut self) -> opencv::Result<bool> {
+        match self.frames.pop_front() {
+            Some(frame) => {
+                self.frame = frame;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn retrieve(&mut self, frame: &mut Mat, _flag: i32) -> opencv::Result<bool> {
+        self.frame.copy_to(frame)?;
+        Ok(!frame.empty())
+    }
+
+    fn get(&self, prop: i32) -> opencv::Result<f64> {
+        Ok(self.props.get(&prop).copied().unwrap_or_default())
+    }
+
+    fn set(&mut self, prop: i32, value: f64) -> opencv::Result<bool> {
+        self.props.insert(prop, value);
+        Ok(true)
+    }
+
+    fn release(&mut self) -> opencv::Result<()> {
+        self.frames.clear();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum VideoFocus {
+    Auto,
+    Focus {
+        #[arg(default_value = "500")]
+        value: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Backend {
+    Any,
+    V4l2,
+    Dshow,
+    Msmf,
+    Avfoundation,
+    Gstreamer,
+    Ffmpeg,
+}
+
+impl Backend {
+    pub fn api(&self) -> i32 {
+        match self {
+            Backend::Any => CAP_ANY,
+            Backend::V4l2 => CAP_V4L2,
+            Backend::Dshow => CAP_DSHOW,
+            Backend::Msmf => CAP_MSMF,
+            Backend::Avfoundation => CAP_AVFOUNDATION,
+            Backend::Gstreamer => CAP_GSTREAMER,
+            Backend::Ffmpeg => CAP_FFMPEG,
+        }
+    }
+}
+
+/// Decoder for file sources; `any` lets OpenCV pick a GPU decoder if it has one.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum HwAccel {
+    None,
+    Any,
+    D3d11,
+    Vaapi,
+    Mfx,
+}
+
+impl HwAccel {
+    pub fn value(&self) -> i32 {
+        match self {
+            HwAccel::None => VIDEO_ACCELERATION_NONE,
+            HwAccel::Any => VIDEO_ACCELERATION_ANY,
+            HwAccel::D3d11 => VIDEO_ACCELERATION_D3D11,
+            HwAccel::Vaapi => VIDEO_ACCELERATION_VAAPI,
+            HwAccel::Mfx => VIDEO_ACCELERATION_MFX,
+        }
+    }
+}
+
+/// What a `test` source shows; every pattern moves and carries the frame number.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum TestPattern {
+    /// Colour bars scrolling sideways.
+    Bars,
+    /// Gray snow.
+    Noise,
+    /// A white ball bouncing on black.
+    Ball,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct CaptureSettings {
+    #[arg(long, value_enum, default_value = "any")]
+    pub backend: Backend,
+    #[arg(long)]
+    pub width: Option<i32>,
+    #[arg(long)]
+    pub height: Option<i32>,
+    #[arg(long)]
+    pub fps: Option<f64>,
+    #[arg(long)]
+    pub fourcc: Option<String>,
+    #[arg(long)]
+    pub exposure: Option<f64>,
+    #[arg(long)]
+    pub gain: Option<f64>,
+    #[arg(long)]
+    pub brightness: Option<f64>,
+    #[arg(long)]
+    pub wb_temperature: Option<f64>,
+    #[arg(long)]
+    pub pan: Option<f64>,
+    #[arg(long)]
+    pub tilt: Option<f64>,
+    #[arg(long)]
+    pub zoom: Option<f64>,
+    /// Camera settings to restore at startup, also where the save-profile key writes them.
+    /// Defaults to `camera_profile.toml` in the store path, so a saved profile comes back on
+    /// the next run.
+    #[arg(long)]
+    pub profile: Option<PathBuf>,
+    #[arg(long, default_value = "0")]
+    pub sweep_min: f64,
+    #[arg(long, default_value = "255")]
+    pub sweep_max: f64,
+    #[arg(long, default_value = "5")]
+    pub sweep_step: f64,
+    #[arg(long, default_value = "0")]
+    pub focus_bracket: u32,
+    #[arg(long, default_value = "10")]
+    pub bracket_step: f64,
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub exposure_bracket: Vec<f64>,
+    #[arg(long)]
+    pub hdr_merge: bool,
+    #[arg(long)]
+    pub raw: bool,
+}
+
+/// Where camera profiles go without `--profile`, below the store path.
+pub const PROFILE_FILE: &str = "camera_profile.toml";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CameraProfile {
+    width: Option<i32>,
+    height: Option<i32>,
+    fps: Option<f64>,
+    fourcc: Option<String>,
+    focus: Option<f64>,
+    exposure: Option<f64>,
+    gain: Option<f64>,
+    brightness: Option<f64>,
+    wb_temperature: Option<f64>,
+    pan: Option<f64>,
+    tilt: Option<f64>,
+    zoom: Option<f64>,
+}
+
+impl CameraProfile {
+    pub fn from_capture(cap: &dyn FrameSource) -> Result<Self, Box<dyn Error>> {
+        Ok(CameraProfile {
+            width: Some(cap.width()?),
+            height: Some(cap.height()?),
+            fps: Some(cap.fps()?),
+            fourcc: Some(cap.fourcc()?),
+            focus: Some(cap.focus()?),
+            exposure: Some(cap.exposure()?),
+            gain: Some(cap.gain()?),
+            brightness: Some(cap.brightness()?),
+            wb_temperature: Some(cap.wb_temperature()?),
+            pan: Some(cap.pan()?),
+            tilt: Some(cap.tilt()?),
+            zoom: Some(cap.zoom()?),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+impl CaptureSettings {
+    pub fn or_profile(&self, profile: &CameraProfile) -> CaptureSettings {
+        CaptureSettings {
+            backend: self.backend,
+            width: self.width.or(profile.width),
+            height: self.height.or(profile.height),
+            fps: self.fps.or(profile.fps),
+            fourcc: self.fourcc.clone().or(profile.fourcc.clone()),
+            exposure: self.exposure.or(profile.exposure),
+            gain: self.gain.or(profile.gain),
+            brightness: self.brightness.or(profile.brightness),
+            wb_temperature: self.wb_temperature.or(profile.wb_temperature),
+            pan: self.pan.or(profile.pan),
+            tilt: self.tilt.or(profile.tilt),
+            zoom: self.zoom.or(profile.zoom),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum VideoSource {
+    File {
+        #[arg(long)]
+        path: String,
+        #[arg(long, value_enum)]
+        hw_accel: Option<HwAccel>,
+    },
+    Capture {
+        #[arg(long, default_value = "0")]
+        device: i32,
+        #[command(flatten)]
+        settings: CaptureSettings,
+        #[command(subcommand)]
+        focus: VideoFocus,
+    },
+    Multicam {
+        #[arg(long = "device", required = true)]
+        devices: Vec<i32>,
+        #[arg(long)]
+        stereo: bool,
+        #[command(flatten)]
+        settings: CaptureSettings,
+        #[command(subcommand)]
+        focus: VideoFocus,
+    },
+    Stream {
+        #[arg(long)]
+        url: String,
+        #[arg(long, default_value = "5000")]
+        timeout_ms: i32,
+        #[arg(long, default_value = "5")]
+        reconnect_attempts: u32,
+    },
+    Gst {
+        #[arg(long)]
+        pipeline: String,
+    },
+    /// Generated frames, for trying the pipeline without a camera.
+    Test {
+        #[arg(long, value_enum, default_value = "bars")]
+        pattern: TestPattern,
+        #[arg(long, default_value = "640")]
+        width: i32,
+        #[arg(long, default_value = "480")]
+        height: i32,
+        #[arg(long, default_value = "30")]
+        fps: i32,
+    },
+    Depth {
+        #[arg(long, default_value = "0")]
+        device: i32,
+        #[arg(long, value_enum, default_value = "openni2")]
+        sensor: DepthSensor,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DepthSensor {
+    Openni2,
+    Realsense,
+}
+
+impl DepthSensor {
+    pub fn api(&self) -> i32 {
+        match self {
+            DepthSensor::Openni2 => CAP_OPENNI2,
+            DepthSensor::Realsense => CAP_REALSENSE,
+        }
+    }
+
+    pub fn color_channel(&self) -> i32 {
+        match self {
+            DepthSensor::Openni2 => CAP_OPENNI_BGR_IMAGE,
+            DepthSensor::Realsense => CAP_INTELPERC_IMAGE,
+        }
+    }
+
+    pub fn depth_channel(&self) -> i32 {
+        match self {
+            DepthSensor::Openni2 => CAP_OPENNI_DEPTH_MAP,
+            DepthSensor::Realsense => CAP_INTELPERC_DEPTH_MAP,
+        }
+    }
+}
+
+impl VideoSource {
+    pub fn settings(&self) -> Option<&CaptureSettings> {
+        match self {
+            VideoSource::Capture { settings, .. } | VideoSource::Multicam { settings, .. } => {
+                Some(settings)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn settings_mut(&mut self) -> Option<&mut CaptureSettings> {
+        match self {
+            VideoSource::Capture { settings, .. } | VideoSource::Multicam { settings, .. } => {
+                Some(settings)
+            }
+            _ => None,
+        }
+    }
+}
+
+pub trait VideoSize {
+    fn width(&self) -> Result<i32, Box<dyn Error>>;
+    fn height(&self) -> Result<i32, Box<dyn Error>>;
+    fn set_width(&mut self, value: i32) -> Result<(), Box<dyn Error>>;
+    fn set_height(&mut self, value: i32) -> Result<(), Box<dyn Error>>;
+}
+
+pub trait VideoProp {
+    fn focus(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_focus(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn fps(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_fps(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn fourcc(&self) -> Result<String, Box<dyn Error>>;
+    fn set_fourcc(&mut self, value: &str) -> Result<(), Box<dyn Error>>;
+    fn exposure(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_exposure(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn gain(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_gain(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn brightness(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_brightness(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn wb_temperature(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_wb_temperature(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn pan(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_pan(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn tilt(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_tilt(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+    fn zoom(&self) -> Result<f64, Box<dyn Error>>;
+    fn set_zoom(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+}
+
+impl<S: FrameSource + ?Sized> VideoSize for S {
+    fn width(&self) -> Result<i32, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_FRAME_WIDTH)?.round() as i32)
+    }
+
+    fn height(&self) -> Result<i32, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_FRAME_HEIGHT)?.round() as i32)
+    }
+
+    fn set_width(&mut self, value: i32) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_FRAME_WIDTH, value as f64)?;
+        Ok(())
+    }
+
+    fn set_height(&mut self, value: i32) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_FRAME_HEIGHT, value as f64)?;
+        Ok(())
+    }
+}
+
+impl<S: FrameSource + ?Sized> VideoProp for S {
+    fn focus(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_FOCUS)?)
+    }
+
+    fn set_focus(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_FOCUS, value)?;
+        Ok(())
+    }
+
+    fn fps(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_FPS)?)
+    }
+
+    fn set_fps(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_FPS, value)?;
+        Ok(())
+    }
+
+    fn fourcc(&self) -> Result<String, Box<dyn Error>> {
+        let code = self.get(CAP_PROP_FOURCC)? as u32;
+        Ok(code
+            .to_le_bytes()
+            .iter()
+            .map(|b| *b as char)
+            .collect::<String>())
+    }
+
+    fn set_fourcc(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
+        let chars: Vec<char> = value.chars().collect();
+        let [c1, c2, c3, c4] = chars[..] else {
+            return Err(AppError::FourccError(value.into()).into());
+        };
+        self.set(CAP_PROP_FOURCC, VideoWriter::fourcc(c1, c2, c3, c4)? as f64)?;
+        Ok(())
+    }
+
+    fn exposure(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_EXPOSURE)?)
+    }
+
+    fn set_exposure(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        let _ = self.set(CAP_PROP_AUTO_EXPOSURE, 0.25);
+        self.set(CAP_PROP_EXPOSURE, value)?;
+        Ok(())
+    }
+
+    fn gain(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_GAIN)?)
+    }
+
+    fn set_gain(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_GAIN, value)?;
+        Ok(())
+    }
+
+    fn brightness(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_BRIGHTNESS)?)
+    }
+
+    fn set_brightness(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_BRIGHTNESS, value)?;
+        Ok(())
+    }
+
+    fn wb_temperature(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_WB_TEMPERATURE)?)
+    }
+
+    fn set_wb_temperature(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        let _ = self.set(CAP_PROP_AUTO_WB, 0.0);
+        self.set(CAP_PROP_WB_TEMPERATURE, value)?;
+        Ok(())
+    }
+
+    fn pan(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_PAN)?)
+    }
+
+    fn set_pan(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_PAN, value)?;
+        Ok(())
+    }
+
+    fn tilt(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_TILT)?)
+    }
+
+    fn set_tilt(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_TILT, value)?;
+        Ok(())
+    }
+
+    fn zoom(&self) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(CAP_PROP_ZOOM)?)
+    }
+
+    fn set_zoom(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set(CAP_PROP_ZOOM, value)?;
+        Ok(())
+    }
+}
+
+pub fn open_stream(url: &str, timeout_ms: i32) -> Result<VideoCapture, Box<dyn Error>> {
+    let params = Vector::<i32>::from_slice(&[
+        CAP_PROP_OPEN_TIMEOUT_MSEC,
+        timeout_ms,
+        CAP_PROP_READ_TIMEOUT_MSEC,
+        timeout_ms,
+    ]);
+    let cap = VideoCapture::from_file_with_params(url, CAP_ANY, &params)?;
+    if !cap.is_opened()? {
+        return Err(AppError::StreamError(format!("Cannot open stream {}", url)).into());
+    }
+    Ok(cap)
+}
+
+pub fn reconnect_stream(
+    url: &str,
+    timeout_ms: i32,
+    attempts: u32,
+) -> Result<VideoCapture, Box<dyn Error>> {
+    for attempt in 1..=attempts {
+        println!("reconnecting to {} ({}/{})", url, attempt, attempts);
+        match open_stream(url, timeout_ms) {
+            Ok(cap) => return Ok(cap),
+            Err(e) => println!("reconnect failed: {}", e),
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    Err(AppError::StreamError(format!("Lost connection to {}", url)).into())
+}
+
+pub fn device_name(index: i32) -> Option<String> {
+    let path = format!("/sys/class/video4linux/video{}/name", index);
+    fs::read_to_string(path).ok().map(|n| n.trim().to_string())
+}
+
+pub fn list_devices(max_index: i32, backend: Backend) -> Result<(), Box<dyn Error>> {
+    for index in 0..max_index {
+        let mut cap = match VideoCapture::new(index, backend.api()) {
+            Ok(cap) => cap,
+            Err(_) => continue,
+        };
+        if !cap.is_opened()? {
+            continue;
+        }
+        let backend = cap.get_backend_name().unwrap_or_else(|_| "unknown".into());
+        let name = device_name(index).unwrap_or_default();
+        println!(
+            "device {}: {} [{}] {}x{} @ {} fps",
+            index,
+            name,
+            backend,
+            cap.width()?,
+            cap.height()?,
+            cap.fps()?
+        );
+        let _ = cap.release();
+    }
+    Ok(())
+}
+
+pub fn apply_focus(cap: &mut dyn FrameSource, focus: &VideoFocus) {
+    if let VideoFocus::Auto = focus {
+        let _ = cap.set(CAP_PROP_AUTOFOCUS, 1.0);
+    } else if let VideoFocus::Focus { value } = focus {
+        let _ = cap.set(CAP_PROP_AUTOFOCUS, 0.0);
+        let _ = cap.set(CAP_PROP_FOCUS, *value);
+    }
+}
+
+pub fn apply_settings(
+    cap: &mut dyn FrameSource,
+    settings: &CaptureSettings,
+) -> Result<(), Box<dyn Error>> {
+    let profile = match &settings.profile {
+        Some(path) if path.exists() => CameraProfile::load(path)?,
+        _ => CameraProfile::default(),
+    };
+    let settings = &settings.or_profile(&profile);
+    // Without RGB conversion the backend hands over the sensor's native (e.g. 16-bit) frames.
+    if settings.raw {
+        cap.set(CAP_PROP_CONVERT_RGB, 0.0)?;
+    }
+    // Profile focus overrides the focus subcommand, explicit flags override the rest.
+    if let Some(focus) = profile.focus {
+        let _ = cap.set(CAP_PROP_AUTOFOCUS, 0.0);
+        cap.set_focus(focus)?;
+    }
+    if let Some(fourcc) = &settings.fourcc {
+        cap.set_fourcc(fourcc)?;
+    }
+    if let Some(width) = settings.width {
+        cap.set_width(width)?;
+    }
+    if let Some(height) = settings.height {
+        cap.set_height(height)?;
+    }
+    if let Some(fps) = settings.fps {
+        cap.set_fps(fps)?;
+    }
+    if let Some(exposure) = settings.exposure {
+        cap.set_exposure(exposure)?;
+    }
+    if let Some(gain) = settings.gain {
+        cap.set_gain(gain)?;
+    }
+    if let Some(brightness) = settings.brightness {
+        cap.set_brightness(brightness)?;
+    }
+    if let Some(wb_temperature) = settings.wb_temperature {
+        cap.set_wb_temperature(wb_temperature)?;
+    }
+    if let Some(pan) = settings.pan {
+        cap.set_pan(pan)?;
+    }
+    if let Some(tilt) = settings.tilt {
+        cap.set_tilt(tilt)?;
+    }
+    if let Some(zoom) = settings.zoom {
+        cap.set_zoom(zoom)?;
+    }
+    if let Some(width) = settings.width
+        && cap.width()? != width
+    {
+        println!("camera rejected width {}, using {}", width, cap.width()?);
+    }
+    if let Some(height) = settings.height
+        && cap.height()? != height
+    {
+        println!("camera rejected height {}, using {}", height, cap.height()?);
+    }
+    if let Some(fps) = settings.fps
+        && (cap.fps()? - fps).abs() > 0.5
+    {
+        println!("camera rejected fps {}, using {}", fps, cap.fps()?);
+    }
+    println!("FOURCC: {}", cap.fourcc()?);
+    Ok(())
+}
+
+pub type PropGetter = fn(&(dyn FrameSource + 'static)) -> Result<f64, Box<dyn Error>>;
+pub type PropSetter = fn(&mut (dyn FrameSource + 'static), f64) -> Result<(), Box<dyn Error>>;
+
+pub fn step_prop(
+    videos: &mut [Box<dyn FrameSource>],
+    name: &str,
+    get: PropGetter,
+    set: PropSetter,
+    delta: f64,
+) -> Result<(), Box<dyn Error>> {
+    for video in videos.iter_mut() {
+        let value = get(video)? + delta;
+        set(video, value)?;
+        println!("{}: {}", name, get(video)?);
+    }
+    Ok(())
+}
+
+/// The camera properties the control API reads and sets, by name.
+pub const PROPS: &[(&str, PropGetter, PropSetter)] = &[
+    (
+        "focus",
+        <dyn FrameSource>::focus,
+        <dyn FrameSource>::set_focus,
+    ),
+    (
+        "exposure",
+        <dyn FrameSource>::exposure,
+        <dyn FrameSource>::set_exposure,
+    ),
+    ("gain", <dyn FrameSource>::gain, <dyn FrameSource>::set_gain),
+    (
+        "brightness",
+        <dyn FrameSource>::brightness,
+        <dyn FrameSource>::set_brightness,
+    ),
+    (
+        "wb_temperature",
+        <dyn FrameSource>::wb_temperature,
+        <dyn FrameSource>::set_wb_temperature,
+    ),
+    ("pan", <dyn FrameSource>::pan, <dyn FrameSource>::set_pan),
+    ("tilt", <dyn FrameSource>::tilt, <dyn FrameSource>::set_tilt),
+    ("zoom", <dyn FrameSource>::zoom, <dyn FrameSource>::set_zoom),
+];
+
+/// The `PROPS` each camera reports.
+pub fn read_props(videos: &[Box<dyn FrameSource>]) -> Vec<BTreeMap<&'static str, f64>> {
+    videos
+        .iter()
+        .map(|video| {
+            PROPS
+                .iter()
+                .filter_map(|(name, get, _)| get(&**video).ok().map(|value| (*name, value)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Grabs every camera before decoding any, keeping multi-camera frames close in time.
+pub fn read_frames(videos: &mut [Box<dyn FrameSource>], frames: &mut [Mat]) -> bool {
+    let mut all_read = true;
+    for video in videos.iter_mut() {
+        if !matches!(video.grab(), Ok(true)) {
+            all_read = false;
+        }
+    }
+    for (video, frame) in videos.iter_mut().zip(frames.iter_mut()) {
+        if !matches!(video.retrieve(frame, 0), Ok(true)) {
+            all_read = false;
+        }
+    }
+    all_read
+}
+
+pub fn read_rgbd(video: &mut dyn FrameSource, frames: &mut [Mat], sensor: DepthSensor) -> bool {
+    matches!(video.grab(), Ok(true))
+        && matches!(
+            video.retrieve(&mut frames[0], sensor.color_channel()),
+            Ok(true)
+        )
+        && matches!(
+            video.retrieve(&mut frames[1], sensor.depth_channel()),
+            Ok(true)
+        )
+}
+
+pub fn read_source(
+    videos: &mut [Box<dyn FrameSource>],
+    frames: &mut [Mat],
+    depth: Option<DepthSensor>,
+) -> bool {
+    match depth {
+        Some(sensor) => read_rgbd(&mut videos[0], frames, sensor),
+        None => read_frames(videos, frames),
+    }
+}
+
+pub fn open_source(source: &VideoSource) -> Result<Vec<Box<dyn FrameSource>>, Box<dyn Error>> {
+    let videos = match source {
+        VideoSource::File {
+            path,
+            hw_accel: None,
+        } => vec![VideoCapture::from_file_def(path)?],
+        VideoSource::File {
+            path,
+            hw_accel: Some(hw_accel),
+        } => {
+            let params = Vector::<i32>::from_slice(&[CAP_PROP_HW_ACCELERATION, hw_accel.value()]);
+            let cap = VideoCapture::from_file_with_params(path, CAP_ANY, &params)?;
+            if cap.is_opened()? && *hw_accel != HwAccel::None {
+                // The backend falls back to software decoding when no accelerator fits.
+                match VideoCaptureTraitConst::get(&cap, CAP_PROP_HW_ACCELERATION)? as i32 {
+                    VIDEO_ACCELERATION_NONE => {
+                        println!("hardware decoding unavailable for {}", path)
+                    }
+                    _ => println!("hardware decoding {}", path),
+                }
+            }
+            vec![cap]
+        }
+        VideoSource::Capture {
+            device,
+            settings,
+            focus,
+        } => {
+            let mut cap = VideoCapture::new(*device, settings.backend.api())?;
+            apply_focus(&mut cap, focus);
+            apply_settings(&mut cap, settings)?;
+            vec![cap]
+        }
+        VideoSource::Multicam {
+            devices,
+            settings,
+            focus,
+            ..
+        } => {
+            let mut caps = Vec::with_capacity(devices.len());
+            for device in devices {
+                let mut cap = VideoCapture::new(*device, settings.backend.api())?;
+                apply_focus(&mut cap, focus);
+                apply_settings(&mut cap, settings)?;
+                caps.push(cap);
+            }
+            caps
+        }
+        VideoSource::Stream {
+            url, timeout_ms, ..
+        } => vec![open_stream(url, *timeout_ms)?],
+        VideoSource::Gst { pipeline } => {
+            let cap = VideoCapture::from_file(pipeline, CAP_GSTREAMER)?;
+            if !cap.is_opened()? {
+                return Err(AppError::SourceError(format!(
+                    "Cannot open GStreamer pipeline {}",
+                    pipeline
+                ))
+                .into());
+            }
+            vec![cap]
+        }
+        VideoSource::Test {
+            pattern,
+            width,
+            height,
+            fps,
+        } => {
+            let test: Box<dyn FrameSource> =
+                Box::new(TestSource::new(*pattern, *width, *height, *fps));
+            return Ok(vec![test]);
+        }
+        VideoSource::Depth { device, sensor } => {
+            let mut cap = VideoCapture::new(*device, sensor.api())?;
+            if !cap.is_opened()? {
+                return Err(
+                    AppError::SourceError(format!("Cannot open depth camera {}", device)).into(),
+                );
+            }
+            if let DepthSensor::Openni2 = sensor {
+                // Register depth to the color viewpoint so pixels line up.
+                let _ = cap.set(
+                    CAP_OPENNI_DEPTH_GENERATOR + CAP_PROP_OPENNI_REGISTRATION,
+                    1.0,
+                );
+            }
+            vec![cap]
+        }
+    };
+    Ok(videos
+        .into_iter()
+        .map(|video| Box::new(video) as Box<dyn FrameSource>)
+        .collect())
+}
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::AppError;
+use crate::dataset::{self, copy_samples};
+use crate::manifest::csv_field;
+
+fn split_names(count: usize) -> Vec<String> {
+    match count {
+        2 => vec!["train".into(), "val".into()],
+        3 => vec!["train".into(), "val".into(), "test".into()],
+        _ => (0..count).map(|i| format!("split{}", i)).collect(),
+    }
+}
+
+/// Partitions the store into `output/<split>/<label>/` directories, or lists the
+/// assignment in a `path,label,split` CSV when `manifest` is given.
+pub fn run(
+    store_path: &Path,
+    ratios: &[f64],
+    seed: u64,
+    stratify: bool,
+    output: &Path,
+    manifest: Option<&PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    if ratios.is_empty() || ratios.iter().any(|ratio| *ratio < 0.0) {
+        return Err(AppError::DatasetError(format!("invalid split ratios {:?}", ratios)).into());
+    }
+    let names = split_names(ratios.len());
+    let parts = dataset::split(dataset::samples(store_path)?, ratios, seed, stratify);
+    match manifest {
+        Some(manifest) => {
+            let mut csv = String::from("path,label,split\n");
+            for (name, part) in names.iter().zip(&parts) {
+                for sample in part {
+                    let path = sample.path.strip_prefix(store_path).unwrap_or(&sample.path);
+                    csv.push_str(&format!(
+                        "{},{},{}\n",
+                        csv_field(&path.to_string_lossy()),
+                        csv_field(&sample.label),
+                        name
+                    ));
+                }
+            }
+            fs::write(manifest, csv)?;
+        }
+        None => {
+            for (name, part) in names.iter().zip(&parts) {
+                copy_samples(part, &output.join(name))?;
+            }
+        }
+    }
+    for (name, part) in names.iter().zip(&parts) {
+        println!("{}: {} images", name, part.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_split_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A store holding `cats` images labelled cat and `dogs` labelled dog.
+    fn store(name: &str, cats: usize, dogs: usize) -> PathBuf {
+        let store = scratch(name);
+        for (label, count) in [("cat", cats), ("dog", dogs)] {
+            fs::create_dir_all(store.join(label)).unwrap();
+            for i in 0..count {
+                fs::write(store.join(label).join(format!("{}.png", i)), b"png").unwrap();
+            }
+        }
+        store
+    }
+
+    /// `(label, split)` of every manifest row, after checking the header.
+    fn rows(manifest: &Path) -> Vec<(String, String)> {
+        let text = fs::read_to_string(manifest).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("path,label,split"));
+        lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                (fields[1].to_string(), fields[2].to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_names_follow_the_ratio_count() {
+        assert_eq!(split_names(2), ["train", "val"]);
+        assert_eq!(split_names(3), ["train", "val", "test"]);
+        assert_eq!(split_names(4), ["split0", "split1", "split2", "split3"]);
+    }
+
+    #[test]
+    fn invalid_ratios_are_rejected() {
+        let store = store("ratios", 1, 0);
+        let output = store.join("out");
+        assert!(run(&store, &[], 0, false, &output, None).is_err());
+        assert!(run(&store, &[0.8, -0.2], 0, false, &output, None).is_err());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn stratified_manifest_keeps_the_class_balance() {
+        let store = store("stratify", 4, 2);
+        let manifest = scratch("stratify_manifest").join("split.csv");
+        run(
+            &store,
+            &[0.5, 0.5],
+            7,
+            true,
+            &store.join("out"),
+            Some(&manifest),
+        )
+        .unwrap();
+        let rows = rows(&manifest);
+        assert_eq!(rows.len(), 6);
+        for split in ["train", "val"] {
+            let count = |label: &str| {
+                rows.iter()
+                    .filter(|row| row == &&(label.into(), split.into()))
+                    .count()
+            };
+            assert_eq!((count("cat"), count("dog")), (2, 1), "{}", split);
+        }
+        // The manifest replaces the copies.
+        assert!(!store.join("out").exists());
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_split() {
+        let store = store("seed", 5, 5);
+        let dir = scratch("seed_manifest");
+        let (first, second) = (dir.join("first.csv"), dir.join("second.csv"));
+        run(&store, &[0.6, 0.2, 0.2], 42, false, &dir, Some(&first)).unwrap();
+        run(&store, &[0.6, 0.2, 0.2], 42, false, &dir, Some(&second)).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+        let splits: Vec<String> = rows(&first).into_iter().map(|(_, split)| split).collect();
+        let count = |name: &str| splits.iter().filter(|split| *split == name).count();
+        assert_eq!((count("train"), count("val"), count("test")), (6, 2, 2));
+    }
+
+    #[test]
+    fn without_a_manifest_samples_are_copied_per_split() {
+        let store = store("copy", 2, 2);
+        let output = scratch("copy_output");
+        run(&store, &[1.0, 0.0], 0, false, &output, None).unwrap();
+        for label in ["cat", "dog"] {
+            for i in 0..2 {
+                assert!(
+                    output
+                        .join("train")
+                        .join(label)
+                        .join(format!("{}.png", i))
+                        .is_file()
+                );
+            }
+        }
+        assert!(!output.join("val").exists());
+    }
+}
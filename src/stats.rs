@@ -0,0 +1,202 @@
+use opencv::{
+    imgcodecs::{IMREAD_UNCHANGED, imread},
+    prelude::*,
+};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::{AppError, dataset, timestamp};
+
+#[derive(Debug, Default, Serialize)]
+struct ClassStats {
+    images: usize,
+    bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    images: usize,
+    bytes: u64,
+    classes: BTreeMap<String, ClassStats>,
+    resolutions: BTreeMap<String, usize>,
+    oldest: Option<String>,
+    newest: Option<String>,
+}
+
+/// Width and height from a PNG's IHDR chunk, without decoding the image.
+fn png_size(path: &Path) -> Option<(u32, u32)> {
+    let mut header = [0u8; 24];
+    File::open(path).ok()?.read_exact(&mut header).ok()?;
+    if &header[..8] != b"\x89PNG\r\n\x1a\n" || &header[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(header[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(header[20..24].try_into().ok()?);
+    Some((width, height))
+}
+
+fn image_size(path: &Path) -> Result<(u32, u32), AppError> {
+    if let Some(size) = png_size(path) {
+        return Ok(size);
+    }
+    let path = path
+        .to_str()
+        .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+    let image = imread(path, IMREAD_UNCHANGED)?;
+    Ok((image.cols() as u32, image.rows() as u32))
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Counts, sizes, resolutions and capture times of every image under `store_path`.
+fn collect(store_path: &Path) -> Result<Stats, Box<dyn Error>> {
+    let mut stats = Stats::default();
+    let mut oldest: Option<SystemTime> = None;
+    let mut newest: Option<SystemTime> = None;
+    for sample in dataset::samples(store_path)? {
+        let metadata = fs::metadata(&sample.path)?;
+        let class = stats.classes.entry(sample.label.clone()).or_default();
+        class.images += 1;
+        class.bytes += metadata.len();
+        stats.images += 1;
+        stats.bytes += metadata.len();
+        // Undecodable images are reported by `validate`; skip them here.
+        if let Ok((width, height)) = image_size(&sample.path) {
+            *stats
+                .resolutions
+                .entry(format!("{}x{}", width, height))
+                .or_default() += 1;
+        }
+        let modified = metadata.modified()?;
+        oldest = Some(oldest.map_or(modified, |t| t.min(modified)));
+        newest = Some(newest.map_or(modified, |t| t.max(modified)));
+    }
+    stats.oldest = oldest.map(timestamp);
+    stats.newest = newest.map(timestamp);
+    Ok(stats)
+}
+
+pub fn run(store_path: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let stats = collect(store_path)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    println!("{} images, {}", stats.images, human_size(stats.bytes));
+    for (label, class) in &stats.classes {
+        println!(
+            "  {:<20} {:>8} images {:>12}",
+            label,
+            class.images,
+            human_size(class.bytes)
+        );
+    }
+    println!("resolutions:");
+    for (resolution, count) in &stats.resolutions {
+        println!("  {:<20} {:>8}", resolution, count);
+    }
+    if let (Some(oldest), Some(newest)) = (&stats.oldest, &stats.newest) {
+        println!("oldest capture: {}", oldest);
+        println!("newest capture: {}", newest);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test, below the system temp directory.
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "img_collector_stats_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The signature and IHDR chunk of a `width` x `height` PNG, followed by `padding` bytes.
+    fn png(width: u32, height: u32, padding: usize) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.resize(data.len() + padding, 0);
+        data
+    }
+
+    fn put(store: &Path, relative: &str, data: &[u8]) {
+        let path = store.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn png_size_reads_the_ihdr_chunk() {
+        let dir = scratch("png_size");
+        let path = dir.join("image.png");
+        fs::write(&path, png(640, 480, 0)).unwrap();
+        assert_eq!(png_size(&path), Some((640, 480)));
+        fs::write(&path, b"not a png, just some text").unwrap();
+        assert_eq!(png_size(&path), None);
+        fs::write(&path, b"\x89PNG").unwrap();
+        assert_eq!(png_size(&path), None);
+    }
+
+    #[test]
+    fn human_size_picks_the_largest_whole_unit() {
+        assert_eq!(human_size(0), "0.0 B");
+        assert_eq!(human_size(1023), "1023.0 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(human_size(u64::MAX), "16777216.0 TiB");
+    }
+
+    #[test]
+    fn collect_totals_classes_and_resolutions() {
+        let store = scratch("collect");
+        put(&store, "cat/0.png", &png(64, 48, 10));
+        put(&store, "cat/1.png", &png(64, 48, 0));
+        put(&store, "dog/0.png", &png(32, 32, 4));
+        put(&store, "thumbs/dog/0.png", &png(16, 16, 0));
+        let stats = collect(&store).unwrap();
+        assert_eq!(stats.images, 3);
+        assert_eq!(stats.bytes, 3 * 24 + 14);
+        assert_eq!(
+            (stats.classes["cat"].images, stats.classes["cat"].bytes),
+            (2, 58)
+        );
+        assert_eq!(
+            (stats.classes["dog"].images, stats.classes["dog"].bytes),
+            (1, 28)
+        );
+        let resolutions: Vec<_> = stats.resolutions.into_iter().collect();
+        assert_eq!(resolutions, [("32x32".into(), 1), ("64x48".into(), 2)]);
+        assert!(stats.oldest.is_some() && stats.oldest <= stats.newest);
+    }
+
+    #[test]
+    fn an_empty_store_has_no_capture_times() {
+        let stats = collect(&scratch("empty")).unwrap();
+        assert_eq!((stats.images, stats.bytes), (0, 0));
+        assert!(stats.classes.is_empty());
+        assert_eq!((stats.oldest, stats.newest), (None, None));
+    }
+}
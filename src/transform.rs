@@ -0,0 +1,316 @@
+//! The save-side frame transforms and the measures the capture checks take of a frame. The
+//! transforms work on a `Mat` or, with `--gpu`, a `UMat`.
+
+use clap::ValueEnum;
+use opencv::core::{
+    BORDER_CONSTANT, CV_16U, CV_64F, ROTATE_90_CLOCKWISE, ROTATE_90_COUNTERCLOCKWISE, ROTATE_180,
+    Rect, Scalar, Size, copy_make_border, count_non_zero, flip, in_range, mean_std_dev_def, rotate,
+};
+use opencv::imgproc::{
+    COLOR_BGR2GRAY, COLOR_BGR2HSV, COLOR_BGR2RGB, INTER_AREA, cvt_color_def, laplacian_def, resize,
+};
+use opencv::prelude::*;
+
+use crate::gpu::Image;
+use crate::ui::to_display;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Fit {
+    Crop,
+    Pad,
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ColorSpace {
+    Bgr,
+    Rgb,
+    Gray,
+    Hsv,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Rotation {
+    #[value(name = "90")]
+    Cw90,
+    #[value(name = "180")]
+    Cw180,
+    #[value(name = "270")]
+    Cw270,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum FlipAxis {
+    H,
+    V,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl std::str::FromStr for OutSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("expected WxH, got {}", s))?;
+        let width: i32 = width.trim().parse().map_err(|e| format!("width: {}", e))?;
+        let height: i32 = height
+            .trim()
+            .parse()
+            .map_err(|e| format!("height: {}", e))?;
+        if width <= 0 || height <= 0 {
+            return Err(format!("size must be positive, got {}", s));
+        }
+        Ok(OutSize { width, height })
+    }
+}
+
+pub fn to_gray(frame: &Mat) -> opencv::Result<Mat> {
+    if frame.channels() > 1 {
+        let mut gray = Mat::default();
+        cvt_color_def(frame, &mut gray, COLOR_BGR2GRAY)?;
+        Ok(gray)
+    } else {
+        frame.try_clone()
+    }
+}
+
+pub fn sharpness(frame: &Mat) -> opencv::Result<f64> {
+    let gray = to_gray(frame)?;
+    let mut laplacian = Mat::default();
+    laplacian_def(&gray, &mut laplacian, CV_64F)?;
+    let mut mean = Mat::default();
+    let mut stddev = Mat::default();
+    mean_std_dev_def(&laplacian, &mut mean, &mut stddev)?;
+    let stddev = *stddev.at::<f64>(0)?;
+    Ok(stddev * stddev)
+}
+
+pub fn orient_frame<I: Image>(
+    frame: &I,
+    rotation: Option<Rotation>,
+    axis: Option<FlipAxis>,
+) -> opencv::Result<I> {
+    let mut rotated = I::blank();
+    match rotation {
+        Some(Rotation::Cw90) => rotate(frame, &mut rotated, ROTATE_90_CLOCKWISE)?,
+        Some(Rotation::Cw180) => rotate(frame, &mut rotated, ROTATE_180)?,
+        Some(Rotation::Cw270) => rotate(frame, &mut rotated, ROTATE_90_COUNTERCLOCKWISE)?,
+        None => rotated = frame.copy()?,
+    }
+    let mut flipped = I::blank();
+    match axis {
+        Some(FlipAxis::H) => flip(&rotated, &mut flipped, 1)?,
+        Some(FlipAxis::V) => flip(&rotated, &mut flipped, 0)?,
+        None => flipped = rotated,
+    }
+    Ok(flipped)
+}
+
+pub fn convert_color<I: Image>(frame: &I, color: ColorSpace) -> opencv::Result<I> {
+    let code = match (color, frame.channel_count()) {
+        (ColorSpace::Gray, channels) if channels > 1 => COLOR_BGR2GRAY,
+        (ColorSpace::Rgb, 3) => COLOR_BGR2RGB,
+        (ColorSpace::Hsv, 3) => COLOR_BGR2HSV,
+        _ => return frame.copy(),
+    };
+    let mut converted = I::blank();
+    cvt_color_def(frame, &mut converted, code)?;
+    Ok(converted)
+}
+
+pub fn fit_frame<I: Image>(frame: &I, size: OutSize, fit: Fit) -> opencv::Result<I> {
+    let frame_size = frame.frame_size()?;
+    let (cols, rows) = (
+        frame_size.width.max(1) as f64,
+        frame_size.height.max(1) as f64,
+    );
+    let (width, height) = (size.width as f64, size.height as f64);
+    let scaled = |scale: f64| {
+        Size::new(
+            ((cols * scale).round() as i32).max(1),
+            ((rows * scale).round() as i32).max(1),
+        )
+    };
+    let mut resized = I::blank();
+    let mut out = I::blank();
+    match fit {
+        Fit::Stretch => {
+            let target = Size::new(size.width, size.height);
+            resize(frame, &mut out, target, 0.0, 0.0, INTER_AREA)?;
+        }
+        Fit::Crop => {
+            let target = scaled((width / cols).max(height / rows));
+            resize(frame, &mut resized, target, 0.0, 0.0, INTER_AREA)?;
+            let rect = Rect::new(
+                ((target.width - size.width) / 2).max(0),
+                ((target.height - size.height) / 2).max(0),
+                size.width.min(target.width),
+                size.height.min(target.height),
+            );
+            out = resized.crop(rect)?;
+        }
+        Fit::Pad => {
+            let target = scaled((width / cols).min(height / rows));
+            resize(frame, &mut resized, target, 0.0, 0.0, INTER_AREA)?;
+            let pad_x = (size.width - target.width).max(0);
+            let pad_y = (size.height - target.height).max(0);
+            copy_make_border(
+                &resized,
+                &mut out,
+                pad_y / 2,
+                pad_y - pad_y / 2,
+                pad_x / 2,
+                pad_x - pad_x / 2,
+                BORDER_CONSTANT,
+                Scalar::all(0.0),
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+/// Fractions of pixels clipped to black and to white.
+pub fn clipping(frame: &Mat) -> opencv::Result<(f64, f64)> {
+    let gray = to_gray(frame)?;
+    let total = gray.total() as f64;
+    if total == 0.0 {
+        return Ok((0.0, 0.0));
+    }
+    let white = if gray.depth() == CV_16U {
+        65535.0
+    } else {
+        255.0
+    };
+    let mut mask = Mat::default();
+    in_range(&gray, &Scalar::all(0.0), &Scalar::all(0.0), &mut mask)?;
+    let dark = count_non_zero(&mask)? as f64 / total;
+    in_range(&gray, &Scalar::all(white), &Scalar::all(white), &mut mask)?;
+    let bright = count_non_zero(&mask)? as f64 / total;
+    Ok((dark, bright))
+}
+
+/// 64-bit difference hash: one bit per horizontally adjacent pixel pair of a 9x8 thumbnail.
+pub fn dhash(frame: &Mat) -> opencv::Result<u64> {
+    let gray = to_display(&to_gray(frame)?)?;
+    let mut small = Mat::default();
+    resize(&gray, &mut small, Size::new(9, 8), 0.0, 0.0, INTER_AREA)?;
+    let mut hash = 0u64;
+    for row in 0..8 {
+        for col in 0..8 {
+            let left = *small.at_2d::<u8>(row, col)?;
+            let right = *small.at_2d::<u8>(row, col + 1)?;
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{CV_8U, CV_8UC3, Vec3b};
+
+    fn flat(cols: i32, rows: i32, value: f64) -> Mat {
+        Mat::new_rows_cols_with_default(rows, cols, CV_8UC3, Scalar::all(value)).unwrap()
+    }
+
+    /// A gray frame fading from white on the left to black on the right.
+    fn fade(cols: i32, rows: i32) -> Mat {
+        let mut frame =
+            Mat::new_rows_cols_with_default(rows, cols, CV_8U, Scalar::all(0.0)).unwrap();
+        for row in 0..rows {
+            for col in 0..cols {
+                *frame.at_2d_mut::<u8>(row, col).unwrap() = (255 - col * 255 / cols) as u8;
+            }
+        }
+        frame
+    }
+
+    fn size(frame: &Mat) -> (i32, i32) {
+        (frame.cols(), frame.rows())
+    }
+
+    #[test]
+    fn out_size_parses_width_by_height() {
+        let size: OutSize = "640x480".parse().unwrap();
+        assert_eq!((size.width, size.height), (640, 480));
+        let size: OutSize = "64 X 48".parse().unwrap();
+        assert_eq!((size.width, size.height), (64, 48));
+        for bad in ["640", "0x480", "-1x5", "ax3"] {
+            assert!(bad.parse::<OutSize>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn fit_frame_crops_pads_or_stretches_into_the_size() {
+        let frame = flat(64, 48, 128.0);
+        let square = OutSize {
+            width: 32,
+            height: 32,
+        };
+        for fit in [Fit::Crop, Fit::Pad, Fit::Stretch] {
+            assert_eq!(size(&fit_frame(&frame, square, fit).unwrap()), (32, 32));
+        }
+        // Padding goes above and below a frame wider than the size.
+        let padded = fit_frame(&frame, square, Fit::Pad).unwrap();
+        assert_eq!(*padded.at_2d::<Vec3b>(0, 16).unwrap(), Vec3b::all(0));
+        assert_eq!(*padded.at_2d::<Vec3b>(16, 16).unwrap(), Vec3b::all(128));
+    }
+
+    #[test]
+    fn orient_frame_rotates_then_flips() {
+        let frame = fade(64, 48);
+        let rotated = orient_frame(&frame, Some(Rotation::Cw90), None).unwrap();
+        assert_eq!(size(&rotated), (48, 64));
+        // Clockwise, the white left edge becomes the top.
+        assert_eq!(*rotated.at_2d::<u8>(0, 10).unwrap(), 255);
+        let flipped = orient_frame(&frame, None, Some(FlipAxis::H)).unwrap();
+        assert_eq!(*flipped.at_2d::<u8>(10, 63).unwrap(), 255);
+        assert_eq!(size(&orient_frame(&frame, None, None).unwrap()), (64, 48));
+    }
+
+    #[test]
+    fn convert_color_changes_only_color_frames() {
+        let mut frame = flat(4, 4, 0.0);
+        *frame.at_2d_mut::<Vec3b>(0, 0).unwrap() = Vec3b::from([255, 0, 0]);
+        let rgb = convert_color(&frame, ColorSpace::Rgb).unwrap();
+        assert_eq!(*rgb.at_2d::<Vec3b>(0, 0).unwrap(), Vec3b::from([0, 0, 255]));
+        assert_eq!(
+            convert_color(&frame, ColorSpace::Gray).unwrap().channels(),
+            1
+        );
+        let gray = fade(4, 4);
+        for color in [ColorSpace::Rgb, ColorSpace::Hsv, ColorSpace::Gray] {
+            assert_eq!(convert_color(&gray, color).unwrap().channels(), 1);
+        }
+    }
+
+    #[test]
+    fn clipping_counts_black_and_white_pixels() {
+        let mut frame = flat(10, 10, 128.0);
+        Mat::roi_mut(&mut frame, Rect::new(0, 0, 5, 10))
+            .unwrap()
+            .set_to_def(&Scalar::all(0.0))
+            .unwrap();
+        Mat::roi_mut(&mut frame, Rect::new(5, 0, 1, 10))
+            .unwrap()
+            .set_to_def(&Scalar::all(255.0))
+            .unwrap();
+        assert_eq!(clipping(&frame).unwrap(), (0.5, 0.1));
+    }
+
+    #[test]
+    fn sharpness_and_dhash_follow_the_edges() {
+        assert_eq!(sharpness(&flat(64, 48, 128.0)).unwrap(), 0.0);
+        assert!(sharpness(&fade(64, 48)).unwrap() > 0.0);
+        assert_eq!(dhash(&flat(64, 48, 128.0)).unwrap(), 0);
+        // Every pixel is brighter than its right neighbour.
+        assert_eq!(dhash(&fade(64, 48)).unwrap(), u64::MAX);
+    }
+}
@@ -0,0 +1,172 @@
+//! Drawing and input for the preview windows shared by the capture loop and the review and
+//! annotation tools: key codes, outlined text, 8-bit display copies and the mouse ROI.
+
+use opencv::core::{
+    CV_8U, CV_8UC3, NORM_MINMAX, Point, Rect, Scalar, Size, Vector, flip, hconcat, no_array,
+    normalize,
+};
+use opencv::highgui::{
+    EVENT_LBUTTONDOWN, EVENT_LBUTTONUP, EVENT_MOUSEMOVE, EVENT_RBUTTONDOWN, set_mouse_callback,
+};
+use opencv::imgproc::{
+    COLOR_GRAY2BGR, COLORMAP_JET, FONT_HERSHEY_SIMPLEX, INTER_LINEAR, LINE_AA, apply_color_map,
+    cvt_color_def, put_text, resize,
+};
+use opencv::prelude::*;
+
+use std::sync::{Arc, Mutex};
+
+use crate::gpu::Image;
+
+#[derive(Debug, Default)]
+pub struct RoiSelection {
+    pub anchor: Option<Point>,
+    pub current: Point,
+    pub roi: Option<Rect>,
+}
+
+pub fn rect_between(a: Point, b: Point) -> Rect {
+    Rect::new(
+        a.x.min(b.x),
+        a.y.min(b.y),
+        (a.x - b.x).abs(),
+        (a.y - b.y).abs(),
+    )
+}
+
+/// Left-drag selects the crop ROI, right-click clears it.
+pub fn track_roi(selection: &Arc<Mutex<RoiSelection>>) -> opencv::Result<()> {
+    let selection = Arc::clone(selection);
+    set_mouse_callback(
+        "video",
+        Some(Box::new(move |event, x, y, _flags| {
+            let Ok(mut selection) = selection.lock() else {
+                return;
+            };
+            let point = Point::new(x, y);
+            match event {
+                EVENT_LBUTTONDOWN => {
+                    selection.anchor = Some(point);
+                    selection.current = point;
+                }
+                EVENT_MOUSEMOVE => selection.current = point,
+                EVENT_LBUTTONUP => {
+                    if let Some(anchor) = selection.anchor.take() {
+                        let roi = rect_between(anchor, point);
+                        if roi.width > 1 && roi.height > 1 {
+                            println!("crop ROI {:?}", roi);
+                            selection.roi = Some(roi);
+                        }
+                    }
+                }
+                EVENT_RBUTTONDOWN => {
+                    println!("crop ROI cleared");
+                    selection.roi = None;
+                }
+                _ => {}
+            }
+        })),
+    )
+}
+
+pub const KEY_LEFT: [i32; 3] = [0xFF51, 0x250000, 0xF702];
+pub const KEY_UP: [i32; 3] = [0xFF52, 0x260000, 0xF700];
+pub const KEY_RIGHT: [i32; 3] = [0xFF53, 0x270000, 0xF703];
+pub const KEY_DOWN: [i32; 3] = [0xFF54, 0x280000, 0xF701];
+pub const KEY_PAGE_UP: [i32; 3] = [0xFF55, 0x210000, 0xF72C];
+pub const KEY_PAGE_DOWN: [i32; 3] = [0xFF56, 0x220000, 0xF72D];
+/// Help overlay lines for the class keys, shown above the keymap's bindings; a letter or digit
+/// bound to an action is not a class key.
+pub const HELP: &[&str] = &["a-z 0-9 A-Z  save to class (again to save a blurry frame)"];
+
+pub fn draw_text(img: &mut Mat, text: &str, org: Point, scale: f64) -> opencv::Result<()> {
+    let outline = Scalar::new(0.0, 0.0, 0.0, 0.0);
+    let fill = Scalar::new(255.0, 255.0, 255.0, 0.0);
+    put_text(
+        img,
+        text,
+        org,
+        FONT_HERSHEY_SIMPLEX,
+        scale,
+        outline,
+        4,
+        LINE_AA,
+        false,
+    )?;
+    put_text(
+        img,
+        text,
+        org,
+        FONT_HERSHEY_SIMPLEX,
+        scale,
+        fill,
+        2,
+        LINE_AA,
+        false,
+    )
+}
+
+/// `image` flipped left to right when `mirrored`.
+pub fn mirror<I: Image>(image: I, mirrored: bool) -> opencv::Result<I> {
+    if !mirrored {
+        return Ok(image);
+    }
+    let mut flipped = I::blank();
+    flip(&image, &mut flipped, 1)?;
+    Ok(flipped)
+}
+
+/// 8-bit view of a frame; 16-bit frames are stretched to their own min/max range.
+pub fn to_display<I: Image>(frame: &I) -> opencv::Result<I> {
+    if frame.bit_depth() == CV_8U {
+        return frame.copy();
+    }
+    let mut display = I::blank();
+    normalize(
+        frame,
+        &mut display,
+        0.0,
+        255.0,
+        NORM_MINMAX,
+        CV_8U,
+        &no_array(),
+    )?;
+    Ok(display)
+}
+
+/// The frames side by side at the first frame's height, as 8-bit BGR so they can be joined:
+/// deeper single-channel frames such as depth maps are colour-mapped and gray frames converted.
+/// Frames that haven't been read yet are shown black.
+pub fn tile_frames(frames: &[Mat]) -> opencv::Result<Mat> {
+    let height = frames[0].rows().max(1);
+    let mut row = Vector::<Mat>::new();
+    for frame in frames {
+        let tile = if frame.empty() {
+            let width = frames[0].cols().max(1);
+            Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(0.0))?
+        } else {
+            let display = to_display(frame)?;
+            let mut color = Mat::default();
+            match (display.channels(), frame.depth()) {
+                (1, CV_8U) => cvt_color_def(&display, &mut color, COLOR_GRAY2BGR)?,
+                (1, _) => apply_color_map(&display, &mut color, COLORMAP_JET)?,
+                _ => color = display,
+            }
+            let scale = height as f64 / color.rows().max(1) as f64;
+            let mut resized = Mat::default();
+            resize(
+                &color,
+                &mut resized,
+                Size::default(),
+                scale,
+                scale,
+                INTER_LINEAR,
+            )?;
+            resized
+        };
+        row.push(tile);
+    }
+    let mut tiled = Mat::default();
+    hconcat(&row, &mut tiled)?;
+    Ok(tiled)
+}
@@ -0,0 +1,89 @@
+use opencv::{
+    imgcodecs::{IMREAD_UNCHANGED, imread},
+    prelude::*,
+};
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::db::Db;
+use crate::naming::NameTemplate;
+use crate::{AppError, db, index, manifest};
+
+/// Whether the file ends with its format's end marker; a write cut short leaves it missing,
+/// even though decoders often still return a partial image.
+fn has_trailer(path: &Path) -> Result<bool, AppError> {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let trailer: &[u8] = match ext.as_str() {
+        "png" => b"IEND\xAE\x42\x60\x82",
+        "jpg" | "jpeg" => b"\xFF\xD9",
+        _ => return Ok(true),
+    };
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < trailer.len() as u64 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-(trailer.len() as i64)))?;
+    let mut end = vec![0u8; trailer.len()];
+    file.read_exact(&mut end)?;
+    Ok(end == trailer)
+}
+
+/// Why `path` is unusable, or `None` if it decodes.
+fn problem(path: &Path) -> Result<Option<&'static str>, AppError> {
+    if fs::metadata(path)?.len() == 0 {
+        return Ok(Some("zero-byte file"));
+    }
+    if !has_trailer(path)? {
+        return Ok(Some("truncated"));
+    }
+    let path = path
+        .to_str()
+        .ok_or(AppError::PathError("Invalid UTF-8 path".into()))?;
+    if imread(path, IMREAD_UNCHANGED)?.empty() {
+        return Ok(Some("cannot decode"));
+    }
+    Ok(None)
+}
+
+/// Reports broken images; `delete` removes them and `reindex` also closes the index gaps.
+pub fn run(
+    store_path: &Path,
+    template: &NameTemplate,
+    delete: bool,
+    reindex: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut broken = 0;
+    let mut dirs = BTreeSet::new();
+    for path in manifest::images(store_path)? {
+        let Some(problem) = problem(&path)? else {
+            continue;
+        };
+        broken += 1;
+        println!("{:?}: {}", path, problem);
+        if delete || reindex {
+            fs::remove_file(&path)?;
+            if let Some(parent) = path.parent() {
+                dirs.insert(parent.to_path_buf());
+            }
+        }
+    }
+    if reindex {
+        index::invalidate(store_path)?;
+        let db = Db::existing(store_path)?;
+        for dir in &dirs {
+            index::compact(dir, template, db.as_ref())?;
+        }
+    }
+    if delete || reindex {
+        db::sync(store_path)?;
+    }
+    println!("{} broken images", broken);
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! `--webhook URL`: every capture and session event is POSTed as JSON through `curl` on a
+//! background thread, so a slow endpoint never holds up capturing.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::notify::Listener;
+
+/// Seconds before a POST is given up.
+const TIMEOUT: &str = "10";
+
+pub struct Webhook {
+    posts: Option<Sender<String>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+fn post(url: &str, body: &str) -> Result<(), String> {
+    let mut child = Command::new("curl")
+        .args(["-sS", "-f", "--max-time", TIMEOUT, "-X", "POST"])
+        .args([
+            "-H",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+            url,
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("curl: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(body.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("curl {}", status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl Webhook {
+    pub fn spawn(url: &str) -> Self {
+        let (posts, queue) = mpsc::channel::<String>();
+        let url = url.to_string();
+        let handle = thread::spawn(move || {
+            for body in queue {
+                if let Err(e) = post(&url, &body) {
+                    println!("webhook {} failed: {}", url, e);
+                }
+            }
+        });
+        Webhook {
+            posts: Some(posts),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Listener for Webhook {
+    fn send(&self, body: String) {
+        if let Some(posts) = &self.posts {
+            let _ = posts.send(body);
+        }
+    }
+}
+
+impl Drop for Webhook {
+    /// Sends the queued POSTs, so the session end gets out.
+    fn drop(&mut self) {
+        self.posts.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
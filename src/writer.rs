@@ -0,0 +1,172 @@
+//! Image encoding and writing on a background thread, so large frames don't hold up the
+//! preview. Jobs queue up in a bounded channel; once it is full, saving waits for the
+//! writer to catch up rather than piling frames up in memory. Encoded images go to the
+//! session's `FrameSink`.
+
+use opencv::core::{Mat, Vector};
+use opencv::imgcodecs::imencode;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::AppError;
+use crate::exif::{self, ExifFields};
+use crate::sink::FrameSink;
+
+/// Frames waiting to be encoded before saving blocks.
+const QUEUE: usize = 8;
+
+type Then = Box<dyn FnOnce() -> Result<(), AppError> + Send>;
+
+/// One image to encode to `path`, whose extension picks the format.
+pub struct Job {
+    path: PathBuf,
+    image: Mat,
+    params: Vector<i32>,
+    exif: Option<ExifFields>,
+    then: Vec<Then>,
+}
+
+impl Job {
+    pub fn new(path: &Path, image: Mat, params: &Vector<i32>) -> Self {
+        Job {
+            path: path.to_path_buf(),
+            image,
+            params: params.clone(),
+            exif: None,
+            then: Vec::new(),
+        }
+    }
+
+    /// Embeds `fields` in the encoded JPEG.
+    pub fn exif(mut self, fields: ExifFields) -> Self {
+        self.exif = Some(fields);
+        self
+    }
+
+    /// Runs `then` once the image is in place, e.g. to record it in a manifest, after any
+    /// earlier `then`s. Its failure is reported apart from write failures, as the image is
+    /// written all the same.
+    pub fn then(mut self, then: impl FnOnce() -> Result<(), AppError> + Send + 'static) -> Self {
+        self.then.push(Box::new(then));
+        self
+    }
+
+    /// Encodes and writes the image, returning the `then`s to run now that it is in place.
+    fn write(self, sink: &dyn FrameSink) -> Result<Vec<Then>, AppError> {
+        let ext = self
+            .path
+            .extension()
+            .ok_or(AppError::PathError("pathbuf extension err".into()))?;
+        let mut encoded = Vector::<u8>::new();
+        if !imencode(
+            &format!(".{}", ext.to_string_lossy()),
+            &self.image,
+            &mut encoded,
+            &self.params,
+        )? {
+            return Err(AppError::PathError(format!(
+                "cannot encode {:?}",
+                self.path
+            )));
+        }
+        let bytes = match &self.exif {
+            Some(fields) => exif::embed(encoded.as_slice(), fields)?,
+            None => encoded.as_slice().to_vec(),
+        };
+        sink.put(&self.path, &bytes)?;
+        Ok(self.then)
+    }
+}
+
+enum Message {
+    Write(Job),
+    Flush(Sender<()>),
+}
+
+pub struct Writer {
+    jobs: Option<SyncSender<Message>>,
+    failed: Receiver<PathBuf>,
+    then_failed: Receiver<(PathBuf, String)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub fn spawn(sink: Arc<dyn FrameSink>) -> Self {
+        let (jobs, queue) = mpsc::sync_channel(QUEUE);
+        let (report, failed) = mpsc::channel();
+        let (report_then, then_failed) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            for message in queue {
+                match message {
+                    Message::Write(job) => {
+                        let path = job.path.clone();
+                        println!("save img to {:?}", path);
+                        let thens = match job.write(&*sink) {
+                            Ok(thens) => thens,
+                            Err(e) => {
+                                println!("failed to write {:?}: {}", path, e);
+                                let _ = report.send(path);
+                                continue;
+                            }
+                        };
+                        for then in thens {
+                            if let Err(e) = then() {
+                                println!("after writing {:?}: {}", path, e);
+                                let _ = report_then.send((path.clone(), e.to_string()));
+                            }
+                        }
+                    }
+                    Message::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Writer {
+            jobs: Some(jobs),
+            failed,
+            then_failed,
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues `job`, waiting while the queue is full. False if the writer thread is gone.
+    pub fn send(&self, job: Job) -> bool {
+        self.jobs
+            .as_ref()
+            .is_some_and(|jobs| jobs.send(Message::Write(job)).is_ok())
+    }
+
+    /// Waits until every job queued so far is written.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if let Some(jobs) = &self.jobs
+            && jobs.send(Message::Flush(done)).is_ok()
+        {
+            let _ = wait.recv();
+        }
+    }
+
+    /// Images that failed to write since the last call.
+    pub fn failures(&self) -> Vec<PathBuf> {
+        self.failed.try_iter().collect()
+    }
+
+    /// Written images whose `then`s failed since the last call, with the errors.
+    pub fn then_failures(&self) -> Vec<(PathBuf, String)> {
+        self.then_failed.try_iter().collect()
+    }
+}
+
+impl Drop for Writer {
+    /// Finishes the queued jobs.
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}